@@ -425,6 +425,15 @@ mod test {
     #[allow(unused_imports)]
     use libunftp::auth::ClientCert;
 
+    // Credentials like the server passes them to the authenticator.
+    fn credentials(password: Option<&str>, certificate_chain: Option<Vec<ClientCert>>, source_ip: [u8; 4]) -> libunftp::auth::Credentials {
+        let mut creds = libunftp::auth::Credentials::from("");
+        creds.password = password.map(String::from);
+        creds.certificate_chain = certificate_chain;
+        creds.source_ip = source_ip.into();
+        creds
+    }
+
     #[tokio::test]
     async fn test_json_auth() {
         use super::*;
@@ -484,30 +493,13 @@ mod test {
 
         assert_eq!(
             json_authenticator
-                .authenticate(
-                    "dan",
-                    &libunftp::auth::Credentials {
-                        certificate_chain: None,
-                        password: Some("".into()),
-                        source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                    },
-                )
+                .authenticate("dan", &credentials(Some(""), None, [127, 0, 0, 1]))
                 .await
                 .unwrap(),
             DefaultUser
         );
 
-        match json_authenticator
-            .authenticate(
-                "dan",
-                &libunftp::auth::Credentials {
-                    certificate_chain: None,
-                    password: Some("".into()),
-                    source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(128, 0, 0, 1)),
-                },
-            )
-            .await
-        {
+        match json_authenticator.authenticate("dan", &credentials(Some(""), None, [128, 0, 0, 1])).await {
             Err(AuthenticationError::IpDisallowed) => assert!(true),
             _ => assert!(false),
         }
@@ -645,11 +637,7 @@ mod test {
             json_authenticator
                 .authenticate(
                     "alice",
-                    &libunftp::auth::Credentials {
-                        certificate_chain: Some(vec![ClientCert(client_cert.clone())]),
-                        password: Some("has a password".into()),
-                        source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                    },
+                    &credentials(Some("has a password"), Some(vec![ClientCert(client_cert.clone())]), [127, 0, 0, 1]),
                 )
                 .await
                 .unwrap(),
@@ -658,14 +646,7 @@ mod test {
 
         // correct password but missing certificate fails
         match json_authenticator
-            .authenticate(
-                "alice",
-                &libunftp::auth::Credentials {
-                    certificate_chain: None,
-                    password: Some("has a password".into()),
-                    source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                },
-            )
+            .authenticate("alice", &credentials(Some("has a password"), None, [127, 0, 0, 1]))
             .await
         {
             Err(AuthenticationError::CnDisallowed) => assert!(true),
@@ -675,14 +656,7 @@ mod test {
         // correct certificate and no password needed according to json file authenticates successfully
         assert_eq!(
             json_authenticator
-                .authenticate(
-                    "bob",
-                    &libunftp::auth::Credentials {
-                        certificate_chain: Some(vec![ClientCert(client_cert.clone())]),
-                        password: None,
-                        source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                    },
-                )
+                .authenticate("bob", &credentials(None, Some(vec![ClientCert(client_cert.clone())]), [127, 0, 0, 1]))
                 .await
                 .unwrap(),
             DefaultUser
@@ -690,14 +664,7 @@ mod test {
 
         // certificate with incorrect CN and no password needed according to json file fails to authenticate
         match json_authenticator
-            .authenticate(
-                "carol",
-                &libunftp::auth::Credentials {
-                    certificate_chain: Some(vec![ClientCert(client_cert.clone())]),
-                    password: None,
-                    source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                },
-            )
+            .authenticate("carol", &credentials(None, Some(vec![ClientCert(client_cert.clone())]), [127, 0, 0, 1]))
             .await
         {
             Err(AuthenticationError::CnDisallowed) => assert!(true),
@@ -707,14 +674,7 @@ mod test {
         // any trusted certificate without password according to json file authenticates successfully
         assert_eq!(
             json_authenticator
-                .authenticate(
-                    "dean",
-                    &libunftp::auth::Credentials {
-                        certificate_chain: Some(vec![ClientCert(client_cert.clone())]),
-                        password: None,
-                        source_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-                    },
-                )
+                .authenticate("dean", &credentials(None, Some(vec![ClientCert(client_cert.clone())]), [127, 0, 0, 1]))
                 .await
                 .unwrap(),
             DefaultUser
@@ -66,8 +66,9 @@ impl Filesystem {
     }
 
     /// Returns the full, absolute and canonical path corresponding to the (relative to FTP root)
-    /// input path, resolving symlinks and sequences like '../'.
-    async fn full_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+    /// input path, resolving symlinks and sequences like '../'. Paths outside of the user's
+    /// [home](UserDetail::home) directory are refused.
+    async fn full_path<User: UserDetail, P: AsRef<Path>>(&self, user: &User, path: P) -> Result<PathBuf> {
         // `path.join(other_path)` replaces `path` with `other_path` if `other_path` is absolute,
         // so we have to check for it.
         let path = path.as_ref();
@@ -81,7 +82,11 @@ impl Filesystem {
             .await
             .map_err(|e| Error::new(ErrorKind::LocalError, e))??;
 
-        if real_full_path.starts_with(&self.root) {
+        let user_root = match user.home() {
+            Some(home) => self.root.join(home.strip_prefix("/").unwrap_or(home)),
+            None => self.root.clone(),
+        };
+        if real_full_path.starts_with(&user_root) {
             Ok(real_full_path)
         } else {
             Err(Error::from(ErrorKind::PermanentFileNotAvailable))
@@ -94,12 +99,17 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
     type Metadata = Meta;
 
     fn supported_features(&self) -> u32 {
-        libunftp::storage::FEATURE_RESTART | libunftp::storage::FEATURE_SITEMD5
+        libunftp::storage::FEATURE_RESTART
+            | libunftp::storage::FEATURE_SITEMD5
+            | libunftp::storage::FEATURE_APPEND
+            | libunftp::storage::FEATURE_MFMT
+            | libunftp::storage::FEATURE_RENAME
+            | libunftp::storage::FEATURE_HASH
     }
 
     #[tracing_attributes::instrument]
-    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Self::Metadata> {
-        let full_path = self.full_path(path).await?;
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata> {
+        let full_path = self.full_path(user, path).await?;
 
         let fs_meta = tokio::fs::symlink_metadata(full_path)
             .await
@@ -109,12 +119,12 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
 
     #[allow(clippy::type_complexity)]
     #[tracing_attributes::instrument]
-    async fn list<P>(&self, _user: &User, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
+    async fn list<P>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
     where
         P: AsRef<Path> + Send + Debug,
         <Self as StorageBackend<User>>::Metadata: Metadata,
     {
-        let full_path: PathBuf = self.full_path(path).await?;
+        let full_path: PathBuf = self.full_path(user, path).await?;
 
         let prefix: PathBuf = self.root.clone();
 
@@ -135,10 +145,10 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
     }
 
     //#[tracing_attributes::instrument]
-    async fn get<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+    async fn get<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, start_pos: u64) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
         use tokio::io::AsyncSeekExt;
 
-        let full_path = self.full_path(path).await?;
+        let full_path = self.full_path(user, path).await?;
         let mut file = tokio::fs::File::open(full_path).await?;
         if start_pos > 0 {
             file.seek(std::io::SeekFrom::Start(start_pos)).await?;
@@ -149,19 +159,13 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
 
     async fn put<P: AsRef<Path> + Send, R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(
         &self,
-        _user: &User,
+        user: &User,
         bytes: R,
         path: P,
         start_pos: u64,
     ) -> Result<u64> {
         use tokio::io::AsyncSeekExt;
-        // TODO: Add permission checks
-        let path = path.as_ref();
-        let full_path = if path.starts_with("/") {
-            self.root.join(path.strip_prefix("/").unwrap())
-        } else {
-            self.root.join(path)
-        };
+        let full_path = self.full_path(user, path).await?;
 
         let mut file = tokio::fs::OpenOptions::new().write(true).create(true).open(full_path).await?;
         file.set_len(start_pos).await?;
@@ -174,29 +178,83 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
         Ok(bytes_copied)
     }
 
+    async fn put_new<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(
+        &self,
+        user: &User,
+        bytes: R,
+        path: P,
+    ) -> Result<u64> {
+        let full_path = self.full_path(user, path).await?;
+
+        // The file is only created if it doesn't exist yet, in one step.
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(full_path)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => Error::new(ErrorKind::FileAlreadyExists, e),
+                _ => e.into(),
+            })?;
+
+        let mut reader = tokio::io::BufReader::with_capacity(4096, bytes);
+        let mut writer = tokio::io::BufWriter::with_capacity(4096, file);
+
+        let bytes_copied = tokio::io::copy(&mut reader, &mut writer).await?;
+        Ok(bytes_copied)
+    }
+
+    async fn append<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + 'static + Unpin>(
+        &self,
+        user: &User,
+        bytes: R,
+        path: P,
+    ) -> Result<u64> {
+        let full_path = self.full_path(user, path).await?;
+
+        let file = tokio::fs::OpenOptions::new().append(true).create(true).open(full_path).await?;
+
+        let mut reader = tokio::io::BufReader::with_capacity(4096, bytes);
+        let mut writer = tokio::io::BufWriter::with_capacity(4096, file);
+
+        let bytes_copied = tokio::io::copy(&mut reader, &mut writer).await?;
+        Ok(bytes_copied)
+    }
+
+    #[tracing_attributes::instrument]
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<()> {
+        let full_path = self.full_path(user, path).await?;
+        let mtime = filetime::FileTime::from_system_time(modified);
+        // This uses utimensat on unix. It leaves the access time as is.
+        tokio::task::spawn_blocking(move || filetime::set_file_mtime(full_path, mtime))
+            .await
+            .map_err(|e| Error::new(ErrorKind::LocalError, e))?
+            .map_err(|error: std::io::Error| error.into())
+    }
+
     #[tracing_attributes::instrument]
-    async fn del<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
-        let full_path = self.full_path(path).await?;
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let full_path = self.full_path(user, path).await?;
         tokio::fs::remove_file(full_path).await.map_err(|error: std::io::Error| error.into())
     }
 
     #[tracing_attributes::instrument]
-    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
-        let full_path = self.full_path(path).await?;
+    async fn rmd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let full_path = self.full_path(user, path).await?;
         tokio::fs::remove_dir(full_path).await.map_err(|error: std::io::Error| error.into())
     }
 
     #[tracing_attributes::instrument]
-    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
-        tokio::fs::create_dir(self.full_path(path).await?)
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        tokio::fs::create_dir(self.full_path(user, path).await?)
             .await
             .map_err(|error: std::io::Error| error.into())
     }
 
     #[tracing_attributes::instrument]
-    async fn rename<P: AsRef<Path> + Send + Debug>(&self, _user: &User, from: P, to: P) -> Result<()> {
-        let from = self.full_path(from).await?;
-        let to = self.full_path(to).await?;
+    async fn rename<P: AsRef<Path> + Send + Debug>(&self, user: &User, from: P, to: P) -> Result<()> {
+        let from = self.full_path(user, from).await?;
+        let to = self.full_path(user, to).await?;
 
         let from_rename = from.clone();
 
@@ -218,8 +276,8 @@ impl<User: UserDetail> StorageBackend<User> for Filesystem {
     }
 
     #[tracing_attributes::instrument]
-    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<()> {
-        let full_path = self.full_path(path).await?;
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()> {
+        let full_path = self.full_path(user, path).await?;
         tokio::fs::read_dir(full_path).await.map_err(|error: std::io::Error| error.into()).map(|_| ())
     }
 }
@@ -252,6 +310,18 @@ impl Metadata for Meta {
     fn uid(&self) -> u32 {
         0
     }
+
+    #[cfg(unix)]
+    fn unique(&self) -> Option<String> {
+        use std::os::unix::fs::MetadataExt;
+        Some(format!("{:x}g{:x}", self.inner.dev(), self.inner.ino()))
+    }
+
+    #[cfg(unix)]
+    fn unix_mode(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+        Some(self.inner.mode())
+    }
 }
 
 #[cfg(test)]
@@ -127,6 +127,47 @@ fn fs_put() {
     assert_eq!(orig_content, written_content.as_slice());
 }
 
+#[test]
+fn fs_append() {
+    let root = tempfile::tempdir().unwrap();
+    let fs = Filesystem::new(root.path());
+    let rt = Runtime::new().unwrap();
+
+    let appended = rt.block_on(fs.append(&DefaultUser {}, b"hallo".as_ref(), "log.txt")).unwrap();
+    assert_eq!(appended, 5);
+    rt.block_on(fs.append(&DefaultUser {}, b" wereld".as_ref(), "/log.txt")).unwrap();
+
+    assert_eq!(std::fs::read_to_string(root.path().join("log.txt")).unwrap(), "hallo wereld");
+}
+
+#[test]
+fn fs_put_new() {
+    let root = tempfile::tempdir().unwrap();
+    let fs = Filesystem::new(root.path());
+    let rt = Runtime::new().unwrap();
+
+    let written = rt.block_on(fs.put_new(&DefaultUser {}, b"hallo".as_ref(), "/report.txt")).unwrap();
+    assert_eq!(written, 5);
+    let err = rt.block_on(fs.put_new(&DefaultUser {}, b"wereld".as_ref(), "/report.txt")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileAlreadyExists);
+
+    assert_eq!(std::fs::read_to_string(root.path().join("report.txt")).unwrap(), "hallo");
+}
+
+#[test]
+fn fs_set_modified() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("mirrored.txt"), b"hallo").unwrap();
+    let fs = Filesystem::new(root.path());
+    let rt = Runtime::new().unwrap();
+
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_626_171_330);
+    rt.block_on(fs.set_modified(&DefaultUser {}, "/mirrored.txt", modified)).unwrap();
+
+    let meta = std::fs::metadata(root.path().join("mirrored.txt")).unwrap();
+    assert_eq!(meta.modified().unwrap(), modified);
+}
+
 #[test]
 fn fileinfo_fmt() {
     struct MockMetadata {}
@@ -251,3 +292,47 @@ fn fs_md5() {
 
     assert_eq!("ced0b2edc3ec36e8d914320cb0268359", my_md5);
 }
+
+#[test]
+fn fs_home_jail() {
+    #[derive(Debug)]
+    struct Customer {
+        home: PathBuf,
+    }
+
+    impl UserDetail for Customer {
+        fn home(&self) -> Option<&Path> {
+            Some(&self.home)
+        }
+    }
+
+    impl std::fmt::Display for Customer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Customer")
+        }
+    }
+
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(root.path().join("customers/acme")).unwrap();
+    std::fs::create_dir_all(root.path().join("customers/other")).unwrap();
+    std::fs::write(root.path().join("customers/other/secret.txt"), "secret").unwrap();
+    let fs = Filesystem::new(root.path());
+    let acme = Customer {
+        home: PathBuf::from("/customers/acme"),
+    };
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(fs.put(&acme, b"hallo".as_ref(), "/customers/acme/greeting.txt", 0)).unwrap();
+    rt.block_on(fs.cwd(&acme, "/customers/acme")).unwrap();
+    assert_eq!(rt.block_on(fs.list(&acme, "/customers/acme")).unwrap().len(), 1);
+
+    rt.block_on(fs.cwd(&acme, "/customers")).expect_err("cwd above the home directory");
+    assert!(rt.block_on(fs.get(&acme, "/customers/other/secret.txt", 0)).is_err());
+    assert!(rt.block_on(fs.get(&acme, "/customers/acme/../other/secret.txt", 0)).is_err());
+    rt.block_on(fs.put(&acme, b"hallo".as_ref(), "/customers/other/greeting.txt", 0))
+        .expect_err("put into another home");
+    assert!(!root.path().join("customers/other/greeting.txt").exists());
+
+    // Users without a home directory can reach everything.
+    assert!(rt.block_on(fs.get(&DefaultUser {}, "/customers/other/secret.txt", 0)).is_ok());
+}
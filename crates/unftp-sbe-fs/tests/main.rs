@@ -6,6 +6,9 @@ use std::fs;
 use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 use std::{str, time::Duration};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
 use unftp_sbe_fs::ServerExt;
 
 fn ensure_login_required<T: Debug>(r: Result<T>) {
@@ -22,25 +25,108 @@ fn ensure_ftps_required<T: Debug>(r: Result<T>) {
     }
 }
 
+// A bare bones control connection for the commands that async_ftp doesn't support.
+struct RawControlChannel {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl RawControlChannel {
+    async fn connect(addr: &str) -> Self {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut chan = RawControlChannel {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        chan.reply().await;
+        chan
+    }
+
+    async fn login(&mut self) {
+        self.login_as("hoi").await;
+    }
+
+    async fn login_as(&mut self, username: &str) {
+        assert!(self.cmd(&format!("USER {}", username)).await.starts_with("331"));
+        assert!(self.cmd("PASS jij").await.starts_with("230"));
+    }
+
+    // Sets up a passive data connection with EPSV.
+    async fn epsv_connect(&mut self) -> TcpStream {
+        let reply = self.cmd("EPSV").await;
+        assert!(reply.starts_with("229"), "{}", reply);
+        let ip = self.writer.peer_addr().unwrap().ip();
+        TcpStream::connect((ip, epsv_port(&reply))).await.unwrap()
+    }
+
+    async fn cmd(&mut self, cmd: &str) -> String {
+        self.writer.write_all(format!("{}\r\n", cmd).as_bytes()).await.unwrap();
+        self.reply().await
+    }
+
+    async fn reply(&mut self) -> String {
+        self.lines.next_line().await.unwrap().unwrap()
+    }
+
+    // Like cmd but for commands and replies that are not valid UTF-8.
+    async fn cmd_bytes(&mut self, cmd: &[u8]) -> Vec<u8> {
+        self.writer.write_all(&[cmd, b"\r\n"].concat()).await.unwrap();
+        let mut reply = Vec::new();
+        self.lines.get_mut().read_until(b'\n', &mut reply).await.unwrap();
+        reply
+    }
+
+    // Sends a command and returns all lines of its (possibly multi-line) reply.
+    async fn cmd_multiline(&mut self, cmd: &str) -> Vec<String> {
+        let mut lines = vec![self.cmd(cmd).await];
+        let last_line_prefix = format!("{} ", &lines[0][..3]);
+        while !lines.last().unwrap().starts_with(&last_line_prefix) {
+            lines.push(self.reply().await);
+        }
+        lines
+    }
+}
+
+// Returns the port from an EPSV reply like `229 Entering Extended Passive Mode (|||2121|)`.
+fn epsv_port(reply: &str) -> u16 {
+    reply.rsplit("|||").next().unwrap().trim_end_matches("|)").parse().unwrap()
+}
+
+// Runs the server on a free port and returns the address to connect to. The listener is bound
+// before the server is spawned so that clients can connect right away.
+async fn start_server<Storage, User>(server: libunftp::Server<Storage, User>) -> String
+where
+    Storage: libunftp::storage::StorageBackend<User> + 'static,
+    Storage::Metadata: libunftp::storage::Metadata,
+    User: libunftp::auth::UserDetail + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(server.listen_on(vec![libunftp::options::Listener::from(listener)]));
+    addr
+}
+
+async fn read_to_string(mut data: TcpStream) -> String {
+    let mut s = String::new();
+    data.read_to_string(&mut s).await.unwrap();
+    s
+}
+
 #[tokio::test]
 async fn connect() {
-    let addr: &str = "127.0.0.1:1234";
     let path: PathBuf = std::env::temp_dir();
-    tokio::spawn(libunftp::Server::with_fs(path).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    async_ftp::FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(path)).await;
+    async_ftp::FtpStream::connect(&addr).await.unwrap();
 }
 
 #[tokio::test]
 async fn login() {
-    let addr = "127.0.0.1:1235";
     let path = std::env::temp_dir();
     let username = "koen";
     let password = "hoi";
 
-    tokio::spawn(libunftp::Server::with_fs(path).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = async_ftp::FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(path)).await;
+    let mut ftp_stream = async_ftp::FtpStream::connect(&addr).await.unwrap();
     ftp_stream.login(username, password).await.unwrap();
 }
 
@@ -52,7 +138,6 @@ async fn ftps_require_works() {
         mode_data_chan: FtpsRequired,
         give534: bool,
         give534_data: bool,
-        port: u16,
     }
 
     let tests = [
@@ -63,7 +148,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: false,
             give534_data: false,
-            port: 1250,
         },
         Test {
             username: "the-user",
@@ -71,7 +155,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: false,
             give534_data: false,
-            port: 1251,
         },
         Test {
             username: "anonymous",
@@ -79,7 +162,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: true,
             give534_data: false,
-            port: 1252,
         },
         Test {
             username: "the-user",
@@ -87,7 +169,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: true,
             give534_data: false,
-            port: 1253,
         },
         Test {
             username: "AnonyMous",
@@ -95,7 +176,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: false,
             give534_data: false,
-            port: 1254,
         },
         Test {
             username: "the-user",
@@ -103,7 +183,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: true,
             give534_data: false,
-            port: 1255,
         },
         // Data channel tests
         Test {
@@ -112,7 +191,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: false,
             give534_data: false,
-            port: 1256,
         },
         Test {
             username: "the-user",
@@ -120,7 +198,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::None,
             give534: false,
             give534_data: false,
-            port: 1257,
         },
         Test {
             username: "anonymous",
@@ -128,7 +205,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::All,
             give534: false,
             give534_data: true,
-            port: 1258,
         },
         Test {
             username: "the-user",
@@ -136,7 +212,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::All,
             give534: false,
             give534_data: true,
-            port: 1259,
         },
         Test {
             username: "AnonyMous",
@@ -144,7 +219,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::Accounts,
             give534: false,
             give534_data: false,
-            port: 1260,
         },
         Test {
             username: "the-user",
@@ -152,7 +226,6 @@ async fn ftps_require_works() {
             mode_data_chan: FtpsRequired::Accounts,
             give534: false,
             give534_data: true,
-            port: 1261,
         },
     ];
 
@@ -160,14 +233,7 @@ async fn ftps_require_works() {
         // TODO: if we can gracefully shutdown libunftp then we don't need to listen on a new port
         // every time. Alternatively don't listen at all but have a way to talk to unFTP via byte
         // stream or something.
-        let addr = format!("127.0.0.1:{}", test.port);
-
-        tokio::spawn(
-            libunftp::Server::with_fs(std::env::temp_dir())
-                .ftps_required(test.mode_control_chan, test.mode_data_chan)
-                .listen(addr.clone()),
-        );
-        tokio::time::sleep(Duration::new(1, 0)).await;
+        let addr = start_server(libunftp::Server::with_fs(std::env::temp_dir()).ftps_required(test.mode_control_chan, test.mode_data_chan)).await;
 
         let mut ftp_stream = async_ftp::FtpStream::connect(addr.as_str()).await.unwrap();
         let result = ftp_stream.login(test.username, "blah").await;
@@ -183,12 +249,10 @@ async fn ftps_require_works() {
 
 #[tokio::test(flavor = "current_thread")]
 async fn noop() {
-    let addr = "127.0.0.1:1236";
     let path = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(path).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = async_ftp::FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(path)).await;
+    let mut ftp_stream = async_ftp::FtpStream::connect(&addr).await.unwrap();
 
     ftp_stream.noop().await.unwrap();
 }
@@ -197,12 +261,10 @@ async fn noop() {
 async fn get() {
     use std::io::Write;
 
-    let addr = "127.0.0.1:1237";
     let path = std::env::temp_dir();
     let mut filename = path.clone();
 
-    tokio::spawn(libunftp::Server::with_fs(path).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(path)).await;
     // Create a temporary file in the FTP root that we'll retrieve
     filename.push("bla.txt");
     let mut f = std::fs::File::create(filename.clone()).unwrap();
@@ -213,7 +275,7 @@ async fn get() {
     f.write_all(&data).unwrap();
 
     // Retrieve the remote file
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
 
     ensure_login_required(ftp_stream.simple_retr("bla.txt").await);
 
@@ -228,15 +290,13 @@ async fn get() {
 async fn put() {
     use std::io::Cursor;
 
-    let addr = "127.0.0.1:1238";
     let path = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(path).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(path)).await;
 
     let content = b"Hello from this test!\n";
 
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let mut reader = Cursor::new(content);
 
     ensure_login_required(ftp_stream.put("greeting.txt", &mut reader).await);
@@ -251,18 +311,16 @@ async fn put() {
 
 #[tokio::test]
 async fn list() {
-    let addr = "127.0.0.1:1239";
     let root = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
     // Create a filename in the ftp root that we will look for in the `LIST` output
     let path = root.join("test.txt");
     {
         let _f = std::fs::File::create(path);
     }
 
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
 
     ensure_login_required(ftp_stream.list(None).await);
 
@@ -280,12 +338,10 @@ async fn list() {
 
 #[tokio::test]
 async fn pwd() {
-    let addr = "127.0.0.1:1240";
     let root = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(root).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(root)).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
 
     // Make sure we fail if we're not logged in
     ensure_login_required(ftp_stream.pwd().await);
@@ -297,13 +353,11 @@ async fn pwd() {
 
 #[tokio::test]
 async fn cwd() {
-    let addr = "127.0.0.1:1241";
     let root = std::env::temp_dir();
     let path = root.clone();
 
-    tokio::spawn(libunftp::Server::with_fs(path.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(path.clone())).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let dir_in_root = tempfile::TempDir::new_in(path).unwrap();
     let basename = dir_in_root.path().file_name().unwrap();
 
@@ -317,13 +371,11 @@ async fn cwd() {
 
 #[tokio::test]
 async fn cdup() {
-    let addr = "127.0.0.1:1242";
     let root = std::env::temp_dir();
     let path = root.clone();
 
-    tokio::spawn(libunftp::Server::with_fs(path.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(path.clone())).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let dir_in_root = tempfile::TempDir::new_in(path).unwrap();
     let basename = dir_in_root.path().file_name().unwrap();
 
@@ -341,12 +393,10 @@ async fn cdup() {
 
 #[tokio::test]
 async fn dele() {
-    let addr = "127.0.0.1:1243";
     let root = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(root).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(root)).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let file_in_root = tempfile::NamedTempFile::new().unwrap();
     let file_name = file_in_root.path().file_name().unwrap().to_str().unwrap();
 
@@ -359,12 +409,10 @@ async fn dele() {
 
 #[tokio::test]
 async fn quit() {
-    let addr = "127.0.0.1:1244";
     let root = std::env::temp_dir();
 
-    tokio::spawn(libunftp::Server::with_fs(root).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(root)).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     ftp_stream.quit().await.unwrap();
     // Make sure the connection is actually closed
     // This may take some time, so we'll sleep for a bit.
@@ -374,19 +422,17 @@ async fn quit() {
 
 #[tokio::test]
 async fn nlst() {
-    let addr = "127.0.0.1:1245";
     let root = tempfile::TempDir::new().unwrap().into_path();
     let path = root.clone();
 
-    tokio::spawn(libunftp::Server::with_fs(path.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(path.clone())).await;
     // Create a filename that we wanna see in the `NLST` output
     let path = path.join("test.txt");
     {
         let _f = std::fs::File::create(path);
     }
 
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
 
     ensure_login_required(ftp_stream.nlst(None).await);
 
@@ -397,12 +443,10 @@ async fn nlst() {
 
 #[tokio::test]
 async fn mkdir() {
-    let addr = "127.0.0.1:1246";
     let root = tempfile::TempDir::new().unwrap().into_path();
 
-    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let new_dir_name = "hallo";
 
     ensure_login_required(ftp_stream.mkdir(new_dir_name).await);
@@ -417,11 +461,9 @@ async fn mkdir() {
 
 #[tokio::test]
 async fn rename() {
-    let addr = "127.0.0.1:1247";
     let root = tempfile::TempDir::new().unwrap().into_path();
 
-    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
     // Create a file that we will rename
     let full_from = root.join("ikbenhier.txt");
     let _f = std::fs::File::create(&full_from);
@@ -431,7 +473,7 @@ async fn rename() {
     let full_to = root.join("nu ben ik hier.txt");
     let to_filename = full_to.file_name().unwrap().to_str().unwrap();
 
-    let mut ftp_stream = FtpStream::connect(addr).await.expect("Failed to connect");
+    let mut ftp_stream = FtpStream::connect(&addr).await.expect("Failed to connect");
 
     // Make sure we fail if we're not logged in
     ensure_login_required(ftp_stream.rename(&from_filename, &to_filename).await);
@@ -453,12 +495,10 @@ async fn rename() {
 
 #[tokio::test]
 async fn size() {
-    let addr = "127.0.0.1:1248";
     let root = std::env::temp_dir();
-    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen(addr));
-    tokio::time::sleep(Duration::new(1, 0)).await;
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
 
-    let mut ftp_stream = FtpStream::connect(addr).await.unwrap();
+    let mut ftp_stream = FtpStream::connect(&addr).await.unwrap();
     let file_in_root = tempfile::NamedTempFile::new_in(root).unwrap();
     let file_name = file_in_root.path().file_name().unwrap().to_str().unwrap();
 
@@ -477,3 +517,1057 @@ async fn size() {
     let size3 = size2.unwrap();
     assert_eq!(size3, fs::metadata(&file_in_root).unwrap().len() as usize, "Wrong size returned.");
 }
+
+#[tokio::test]
+async fn active_mode() {
+    use libunftp::options::ActivePassiveMode;
+
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).active_passive_mode(ActivePassiveMode::ActiveOnly)).await;
+    {
+        let _f = std::fs::File::create(root.join("test.txt"));
+    }
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd("PASV").await.starts_with("502"), "PASV should be refused in active-only mode");
+
+    // The server may only connect back to the client's own address unless FXP is allowed.
+    assert!(ftp.cmd("PORT 127,0,0,2,4,1").await.starts_with("504"));
+    assert!(ftp.cmd("EPRT |1|127.0.0.1|80|").await.starts_with("504"));
+
+    for use_eprt in [false, true].iter() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reply = if *use_eprt {
+            ftp.cmd(&format!("EPRT |1|127.0.0.1|{}|", port)).await
+        } else {
+            ftp.cmd(&format!("PORT 127,0,0,1,{},{}", port >> 8, port & 0xff)).await
+        };
+        assert!(reply.starts_with("200"), "{}", reply);
+        assert!(ftp.cmd("NLST").await.starts_with("150"));
+
+        let (data, _) = listener.accept().await.unwrap();
+        assert_eq!(read_to_string(data).await.lines().collect::<Vec<_>>(), vec!["test.txt"]);
+        assert!(ftp.reply().await.starts_with("226"));
+    }
+}
+
+#[tokio::test]
+async fn epsv_ipv6() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let listener = TcpListener::bind("[::1]:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen_on(vec![libunftp::options::Listener::from(listener)]));
+    {
+        let _f = std::fs::File::create(root.join("test.txt"));
+    }
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd("PASV").await.starts_with("425"), "PASV can't express an IPv6 address");
+    assert!(ftp.cmd("EPSV 1").await.starts_with("522"));
+
+    let reply = ftp.cmd("EPSV 2").await;
+    assert!(reply.starts_with("229"), "{}", reply);
+    let data = TcpStream::connect(("::1", epsv_port(&reply))).await.unwrap();
+    assert!(ftp.cmd("NLST").await.starts_with("150"));
+    assert_eq!(read_to_string(data).await.lines().collect::<Vec<_>>(), vec!["test.txt"]);
+    assert!(ftp.reply().await.starts_with("226"));
+
+    // After EPSV ALL only EPSV may be used to set up data connections.
+    assert!(ftp.cmd("EPSV ALL").await.starts_with("200"));
+    assert!(ftp.cmd("PASV").await.starts_with("503"));
+    assert!(ftp.cmd("EPSV").await.starts_with("229"));
+}
+
+#[tokio::test]
+async fn mlsd_mlst() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    std::fs::write(root.join("test.txt"), b"Hello unftp").unwrap();
+    std::fs::create_dir(root.join("sub")).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(root.join("test.txt"), std::fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    let feat = ftp.cmd_multiline("FEAT").await;
+    assert!(
+        feat.contains(&String::from(" MLST type*;size*;modify*;perm*;unique*;unix.mode*;")),
+        "{:?}",
+        feat
+    );
+
+    let mlst = ftp.cmd_multiline("MLST test.txt").await;
+    assert_eq!(mlst.len(), 3, "{:?}", mlst);
+    assert!(mlst[0].starts_with("250-"));
+    assert!(mlst[1].starts_with(" type=file;size=11;modify="), "{:?}", mlst);
+    assert!(mlst[1].ends_with("; test.txt"));
+    #[cfg(unix)]
+    assert!(mlst[1].contains(";perm=adfrw;") && mlst[1].ends_with(";unix.mode=0640; test.txt"), "{:?}", mlst);
+
+    assert_eq!(ftp.cmd("OPTS MLST type;size;").await, "200 MLST OPTS type;size;");
+    let data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("MLSD").await.starts_with("150"));
+    let mut entries: Vec<String> = read_to_string(data).await.lines().map(String::from).collect();
+    entries.sort();
+    assert_eq!(entries, vec!["type=dir; sub", "type=file;size=11; test.txt"]);
+    assert!(ftp.reply().await.starts_with("226"));
+    assert_eq!(ftp.cmd("MLSD test.txt").await, "501 Not a directory");
+}
+
+// Sends LIST over an EPSV data connection and returns the last column (the name) of each line.
+async fn list_names(ftp: &mut RawControlChannel, cmd: &str) -> Vec<String> {
+    let data = ftp.epsv_connect().await;
+    assert!(ftp.cmd(cmd).await.starts_with("150"));
+    let listing = read_to_string(data).await;
+    assert!(ftp.reply().await.starts_with("226"));
+    listing.lines().map(|line| line.rsplit(' ').next().unwrap().to_string()).collect()
+}
+
+#[tokio::test]
+async fn list_options() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).list_recursion_limits(1, 100)).await;
+    std::fs::write(root.join("small.txt"), b"a").unwrap();
+    std::fs::write(root.join("large.txt"), b"aaaaaaaaaa").unwrap();
+    std::fs::write(root.join(".hidden"), b"").unwrap();
+    std::fs::create_dir_all(root.join("sub/deeper")).unwrap();
+    std::fs::write(root.join("sub/nested.txt"), b"").unwrap();
+    std::fs::write(root.join("sub/deeper/too_deep.txt"), b"").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+
+    let mut plain = list_names(&mut ftp, "LIST").await;
+    plain.sort();
+    assert_eq!(plain, vec![".hidden", "large.txt", "small.txt", "sub"]);
+    let by_size: Vec<String> = list_names(&mut ftp, "LIST -lSr").await.into_iter().filter(|name| name != "sub").collect();
+    assert_eq!(by_size, vec![".hidden", "small.txt", "large.txt"]);
+    // The depth limit of 1 keeps sub/deeper from being listed.
+    assert_eq!(
+        list_names(&mut ftp, "LIST -R").await,
+        vec![".hidden", "large.txt", "small.txt", "sub", "", "sub:", "deeper", "nested.txt"]
+    );
+
+    // Dotfiles are only left out if the server is told to, and then -a still shows them.
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).list_hide_dotfiles(true)).await;
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(list_names(&mut ftp, "LIST").await, vec!["large.txt", "small.txt", "sub"]);
+    let mut all = list_names(&mut ftp, "LIST -la").await;
+    all.sort();
+    assert_eq!(all, vec![".hidden", "large.txt", "small.txt", "sub"]);
+}
+
+#[tokio::test]
+async fn mode_z() {
+    use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+    use std::io::Read;
+
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    let content = "id,name\r\n".repeat(1000);
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd_multiline("FEAT").await.contains(&String::from(" MODE Z")));
+    assert!(ftp.cmd("MODE Z").await.starts_with("200"));
+    assert!(ftp.cmd("OPTS MODE Z LEVEL 10").await.starts_with("501"));
+    assert_eq!(ftp.cmd("OPTS MODE Z LEVEL 9").await, "200 MODE Z LEVEL set to 9");
+
+    let mut data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("STOR data.csv").await.starts_with("150"));
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content.as_bytes()).unwrap();
+    data.write_all(&encoder.finish().unwrap()).await.unwrap();
+    data.shutdown().await.unwrap();
+    assert!(ftp.reply().await.starts_with("226"));
+    assert_eq!(fs::read_to_string(root.join("data.csv")).unwrap(), content);
+
+    let mut data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR data.csv").await.starts_with("150"));
+    let mut compressed = vec![];
+    data.read_to_end(&mut compressed).await.unwrap();
+    assert!(ftp.reply().await.starts_with("226"));
+    assert!(compressed.len() < content.len());
+    let mut retrieved = String::new();
+    ZlibDecoder::new(&compressed[..]).read_to_string(&mut retrieved).unwrap();
+    assert_eq!(retrieved, content);
+
+    assert!(ftp.cmd("MODE S").await.starts_with("200"));
+    let data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR data.csv").await.starts_with("150"));
+    assert_eq!(read_to_string(data).await, content);
+    assert!(ftp.reply().await.starts_with("226"));
+
+    // The mode counts when the command is sent, not when the data connection is set up.
+    let mut data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("MODE Z").await.starts_with("200"));
+    assert!(ftp.cmd("RETR data.csv").await.starts_with("150"));
+    let mut compressed = vec![];
+    data.read_to_end(&mut compressed).await.unwrap();
+    assert!(ftp.reply().await.starts_with("226"));
+    let mut retrieved = String::new();
+    ZlibDecoder::new(&compressed[..]).read_to_string(&mut retrieved).unwrap();
+    assert_eq!(retrieved, content);
+}
+
+#[tokio::test]
+async fn type_ascii() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd("TYPE A").await.starts_with("200"));
+
+    let mut data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("STOR lines.txt").await.starts_with("150"));
+    data.write_all(b"one\r\ntwo\r\n").await.unwrap();
+    data.shutdown().await.unwrap();
+    assert!(ftp.reply().await.starts_with("226"));
+    assert_eq!(fs::read_to_string(root.join("lines.txt")).unwrap(), "one\ntwo\n");
+
+    let data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR lines.txt").await.starts_with("150"));
+    assert_eq!(read_to_string(data).await, "one\r\ntwo\r\n");
+    assert!(ftp.reply().await.starts_with("226"));
+
+    assert!(ftp.cmd("SIZE lines.txt").await.starts_with("550"));
+    assert!(ftp.cmd("TYPE I").await.starts_with("200"));
+    assert_eq!(ftp.cmd("SIZE lines.txt").await, "213 8");
+
+    // The type counts when the command is sent, not when the data connection is set up.
+    let data = ftp.epsv_connect().await;
+    assert!(ftp.cmd("TYPE A").await.starts_with("200"));
+    assert!(ftp.cmd("RETR lines.txt").await.starts_with("150"));
+    assert_eq!(read_to_string(data).await, "one\r\ntwo\r\n");
+    assert!(ftp.reply().await.starts_with("226"));
+}
+
+#[tokio::test]
+async fn appe() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    std::fs::write(root.join("app.log"), b"line 1\n").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    for (file, data) in &[("app.log", "line 2\n"), ("new.log", "line 1\n")] {
+        let mut stream = ftp.epsv_connect().await;
+        assert!(ftp.cmd(&format!("APPE {}", file)).await.starts_with("150"));
+        stream.write_all(data.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        assert!(ftp.reply().await.starts_with("226"));
+    }
+    assert_eq!(fs::read_to_string(root.join("app.log")).unwrap(), "line 1\nline 2\n");
+    assert_eq!(fs::read_to_string(root.join("new.log")).unwrap(), "line 1\n");
+}
+
+#[tokio::test]
+async fn mfmt() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    std::fs::write(root.join("mirrored.txt"), b"hallo").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd_multiline("FEAT").await.contains(&String::from(" MFMT")));
+    assert_eq!(ftp.cmd("MFMT 20210713101530 mirrored.txt").await, "213 Modify=20210713101530; mirrored.txt");
+    assert_eq!(ftp.cmd("MDTM mirrored.txt").await, "213 20210713101530");
+    assert!(ftp.cmd("MFMT 20210713101530 missing.txt").await.starts_with("550"));
+    assert!(ftp.cmd("MFMT yesterday mirrored.txt").await.starts_with("50"));
+}
+
+#[tokio::test]
+async fn hash() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone())).await;
+    std::fs::write(root.join("hashed.txt"), b"hallo").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp
+        .cmd_multiline("FEAT")
+        .await
+        .contains(&String::from(" HASH SHA-1;SHA-256*;SHA-512;MD5;CRC32;CRC32C")));
+    assert_eq!(ftp.cmd("OPTS HASH").await, "200 SHA-256");
+    assert_eq!(
+        ftp.cmd("HASH hashed.txt").await,
+        "213 SHA-256 0-4 d3751d33f9cd5049c4af2b462735457e4d3baf130bcbb87f389e349fbaeb20b9 hashed.txt"
+    );
+    assert_eq!(ftp.cmd("OPTS HASH md5").await, "200 MD5");
+    assert!(ftp.cmd("OPTS HASH SHA-384").await.starts_with("504"));
+    assert!(ftp.cmd("RANG 1 3").await.starts_with("350"));
+    assert_eq!(ftp.cmd("HASH hashed.txt").await, "213 MD5 1-3 a181a603769c1f98ad927e7367c7aa51 hashed.txt");
+    assert!(ftp.cmd("HASH missing.txt").await.starts_with("550"));
+    assert!(ftp.cmd("RANG 100 200").await.starts_with("350"));
+    assert!(ftp.cmd("HASH hashed.txt").await.starts_with("501"));
+
+    std::fs::write(root.join("empty.txt"), b"").unwrap();
+    assert_eq!(ftp.cmd("HASH empty.txt").await, "213 MD5 0- d41d8cd98f00b204e9800998ecf8427e empty.txt");
+}
+
+#[tokio::test]
+async fn virtual_hosts() {
+    let default_root = tempfile::TempDir::new().unwrap().into_path();
+    let vhost_root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(vhost_root.join("vhost.txt"), b"hallo").unwrap();
+    let vhost_root2 = vhost_root.clone();
+    let server = libunftp::Server::with_fs(default_root).virtual_host(
+        libunftp::VirtualHost::new("ftp.example.com")
+            .greeting("Welcome to example.com")
+            .storage(Box::new(move || unftp_sbe_fs::Filesystem::new(vhost_root2.clone()))),
+    );
+    let addr = start_server(server).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    assert!(ftp.cmd_multiline("FEAT").await.contains(&String::from(" HOST")));
+    assert!(ftp.cmd("HOST ftp.example.org").await.starts_with("504"));
+    assert_eq!(ftp.cmd("HOST FTP.example.com").await, "220 Welcome to example.com");
+    assert!(ftp.cmd("HOST ftp.example.com").await.starts_with("503"));
+    ftp.login().await;
+    assert_eq!(ftp.cmd("SIZE vhost.txt").await, "213 5");
+
+    // Without HOST the client gets the server's own storage.
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd("SIZE vhost.txt").await.starts_with("550"));
+    assert!(ftp.cmd("HOST ftp.example.com").await.starts_with("503"));
+}
+
+#[tokio::test]
+async fn legacy_charset() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("naïve.txt"), b"hallo").unwrap();
+    // Windows-1252 has no Japanese characters.
+    std::fs::write(root.join("日本.txt"), b"hallo").unwrap();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).legacy_charset("windows-1252")).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    // The charset counts when the command is sent, not when the data connection is set up.
+    let mut data = ftp.epsv_connect().await;
+    assert_eq!(ftp.cmd("OPTS UTF8 OFF").await, "200 UTF-8 mode off, using windows-1252.");
+    assert_eq!(ftp.cmd_bytes(b"MKD caf\xe9").await, b"257 /caf\xe9\r\n".to_vec());
+    assert!(root.join("café").is_dir());
+    assert_eq!(ftp.cmd_bytes(b"SIZE na\xefve.txt").await, b"213 5\r\n".to_vec());
+
+    assert!(ftp.cmd("NLST").await.starts_with("150"));
+    let mut listing = Vec::new();
+    data.read_to_end(&mut listing).await.unwrap();
+    assert!(ftp.reply().await.starts_with("226"));
+    let mut names: Vec<&[u8]> = listing
+        .split(|&b| b == b'\n')
+        .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+        .filter(|l| !l.is_empty())
+        .collect();
+    names.sort_unstable();
+    assert_eq!(names, vec![&b"caf\xe9"[..], &b"na\xefve.txt"[..]]);
+
+    assert_eq!(ftp.cmd("OPTS UTF8 ON").await, "200 UTF-8 mode on.");
+    assert!(ftp.cmd("SIZE naïve.txt").await.starts_with("213"));
+}
+
+#[tokio::test]
+async fn abor() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).partial_upload_policy(libunftp::options::PartialUploadPolicy::Delete)).await;
+    std::fs::write(root.join("big.bin"), vec![0u8; 16 * 1024 * 1024]).unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(ftp.cmd("ABOR").await, "226 Data channel already closed");
+
+    // Abort an upload, preceded by Telnet IP and Synch like clients do.
+    let mut stream = ftp.epsv_connect().await;
+    assert!(ftp.cmd("STOR partial.bin").await.starts_with("150"));
+    stream.write_all(&[0u8; 1024]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    ftp.writer.write_all(b"\xff\xf4\xff\xf2ABOR\r\n").await.unwrap();
+    assert!(ftp.reply().await.starts_with("426"));
+    assert!(ftp.reply().await.starts_with("226"));
+    assert!(!root.join("partial.bin").exists());
+
+    // Abort a download the client doesn't read.
+    let _stream = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR big.bin").await.starts_with("150"));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(ftp.cmd("ABOR").await.starts_with("426"));
+    assert!(ftp.reply().await.starts_with("226"));
+    assert!(ftp.cmd("NOOP").await.starts_with("200"));
+}
+
+#[tokio::test]
+async fn stat_transfer() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let monitor = libunftp::TransferMonitor::new();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).transfer_monitor(monitor.clone())).await;
+    std::fs::write(root.join("big.bin"), vec![0u8; 16 * 1024 * 1024]).unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert!(ftp.cmd_multiline("STAT").await[0].starts_with("211"));
+
+    let _stream = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR big.bin").await.starts_with("150"));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let status = ftp.cmd_multiline("STAT").await;
+    assert_eq!(status[0], "213-transfer status:");
+    assert!(status.contains(&String::from("command: RETR")));
+    assert!(status.contains(&String::from("path: /big.bin")));
+
+    let transfers = monitor.transfers();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].command, "RETR");
+    assert_eq!(transfers[0].username, Some(String::from("hoi")));
+    assert!(transfers[0].bytes > 0);
+
+    assert!(ftp.cmd("ABOR").await.starts_with("426"));
+    assert!(ftp.reply().await.starts_with("226"));
+    assert!(monitor.transfers().is_empty());
+}
+
+#[derive(Debug)]
+struct SiteChmod;
+
+#[async_trait::async_trait]
+impl libunftp::site::SiteCommand<unftp_sbe_fs::Filesystem, libunftp::auth::DefaultUser> for SiteChmod {
+    async fn handle(
+        &self,
+        context: libunftp::site::SiteContext<unftp_sbe_fs::Filesystem, libunftp::auth::DefaultUser>,
+        args: &str,
+    ) -> std::result::Result<String, libunftp::site::SiteError> {
+        use libunftp::storage::StorageBackend;
+        let mut parts = args.splitn(2, ' ');
+        let (mode, file) = match (parts.next(), parts.next()) {
+            (Some(mode), Some(file)) => (mode, file),
+            _ => return Err(libunftp::site::SiteError::InvalidArguments(String::from("Usage: SITE CHMOD <mode> <file>"))),
+        };
+        context.storage().metadata(context.user(), context.cwd().join(file)).await?;
+        Ok(format!("Mode of {} set to {}\nby {}", file, mode, context.user()))
+    }
+}
+
+#[tokio::test]
+async fn site_commands() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).site_command("chmod", std::sync::Arc::new(SiteChmod))).await;
+    std::fs::write(root.join("script.sh"), b"#!/bin/sh").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    assert!(ftp.cmd("SITE CHMOD 755 script.sh").await.starts_with("530"));
+    ftp.login().await;
+    assert!(ftp.cmd_multiline("FEAT").await.contains(&String::from(" SITE CHMOD")));
+    assert!(ftp.cmd_multiline("HELP").await.contains(&String::from("214 SITE commands: CHMOD")));
+    assert_eq!(
+        ftp.cmd_multiline("SITE chmod 755 script.sh").await,
+        vec!["200-Mode of script.sh set to 755", "200 by DefaultUser"]
+    );
+    assert!(ftp.cmd("SITE CHMOD 755").await.starts_with("501"));
+    assert!(ftp.cmd("SITE CHMOD 755 missing.sh").await.starts_with("550"));
+    assert!(ftp.cmd("SITE QUOTA").await.starts_with("500"));
+}
+
+#[test]
+#[should_panic(expected = "SITE MD5 is built in")]
+fn site_md5_is_reserved() {
+    let _ = libunftp::Server::with_fs(std::env::temp_dir()).site_command("md5", std::sync::Arc::new(SiteChmod));
+}
+
+#[derive(Debug, Default)]
+struct AuditMiddleware {
+    log: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl libunftp::middleware::ControlChanMiddleware<libunftp::auth::DefaultUser> for AuditMiddleware {
+    async fn handle(
+        &self,
+        session: &libunftp::middleware::SessionInfo<libunftp::auth::DefaultUser>,
+        request: libunftp::middleware::Request,
+        next: libunftp::middleware::Next<'_, libunftp::auth::DefaultUser>,
+    ) -> std::result::Result<libunftp::middleware::Reply, libunftp::middleware::ControlChanError> {
+        use libunftp::middleware::{Reply, ReplyCode, Request};
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} {} {}", session.logged_in(), request.verb(), request.argument()));
+        match (request.verb(), request.argument()) {
+            ("DELE", _) => Ok(Reply::new(ReplyCode::FileError, "Deleting is not allowed")),
+            ("CWD", "~") => next.run(Request::new("CWD", "/home")).await,
+            _ => next.run(request).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn middleware() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let audit = std::sync::Arc::new(AuditMiddleware::default());
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).middleware(audit.clone())).await;
+    std::fs::create_dir(root.join("home")).unwrap();
+    std::fs::write(root.join("keep.txt"), b"hallo").unwrap();
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(ftp.cmd("DELE keep.txt").await, "550 Deleting is not allowed");
+    assert!(root.join("keep.txt").exists());
+    assert!(ftp.cmd("CWD ~").await.starts_with("250"));
+    assert_eq!(ftp.cmd("PWD").await, "257 \"/home\"");
+    assert_eq!(
+        *audit.log.lock().unwrap(),
+        vec!["false USER hoi", "false PASS ", "true DELE keep.txt", "true CWD ~", "true PWD "]
+    );
+}
+
+#[tokio::test]
+async fn ftps_implicit_needs_ftps() {
+    let addr = "127.0.0.1:0";
+    let root = std::env::temp_dir();
+    let result = libunftp::Server::with_fs(root).ftps_implicit(true).listen(addr).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = libunftp::Server::with_fs(root.clone()).shutdown_indicator(async move {
+        shutdown_rx.await.ok();
+        libunftp::options::Shutdown::new().grace_period(Duration::from_secs(10))
+    });
+    let server = tokio::spawn(server.listen_on(vec![libunftp::options::Listener::from(listener)]));
+
+    let mut idle = RawControlChannel::connect(&addr).await;
+    idle.login().await;
+    let mut busy = RawControlChannel::connect(&addr).await;
+    busy.login().await;
+    let mut stream = busy.epsv_connect().await;
+    assert!(busy.cmd("STOR upload.txt").await.starts_with("150"));
+    stream.write_all(b"hello").await.unwrap();
+
+    shutdown_tx.send(()).unwrap();
+    assert_eq!(idle.reply().await, "421 Server is shutting down");
+    assert!(TcpStream::connect(&addr).await.is_err());
+
+    // The running upload may still finish.
+    stream.write_all(b" world").await.unwrap();
+    drop(stream);
+    assert!(busy.reply().await.starts_with("226"));
+    assert_eq!(busy.reply().await, "421 Server is shutting down");
+    assert_eq!(std::fs::read(root.join("upload.txt")).unwrap(), b"hello world");
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn graceful_shutdown_during_download() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("big.bin"), vec![0u8; 1024 * 1024]).unwrap();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = libunftp::Server::with_fs(root).shutdown_indicator(async move {
+        shutdown_rx.await.ok();
+        libunftp::options::Shutdown::new().grace_period(Duration::from_secs(10))
+    });
+    let addr = start_server(server).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    let mut stream = ftp.epsv_connect().await;
+    assert!(ftp.cmd("RETR big.bin").await.starts_with("150"));
+    let mut first = [0u8; 1024];
+    stream.read_exact(&mut first).await.unwrap();
+
+    shutdown_tx.send(()).unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(first.len() + rest.len(), 1024 * 1024);
+    // The download is confirmed before the client is told that the server goes away.
+    assert!(ftp.reply().await.starts_with("226"));
+    assert_eq!(ftp.reply().await, "421 Server is shutting down");
+}
+
+// Copies a certificate and its key from tests/resources to the given files.
+fn install_certificate(name: &str, certs_file: &std::path::Path, key_file: &std::path::Path) {
+    fs::copy(resource(&format!("{}.pem", name)), certs_file).unwrap();
+    fs::copy(resource(&format!("{}.key", name)), key_file).unwrap();
+}
+
+#[tokio::test]
+async fn ftps_reloader() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let (certs_file, key_file) = (dir.path().join("server.pem"), dir.path().join("server.key"));
+    install_certificate("localhost", &certs_file, &key_file);
+    let reloader = libunftp::TlsReloader::new();
+    let addr = start_server(
+        libunftp::Server::with_fs(std::env::temp_dir())
+            .ftps(certs_file.clone(), key_file.clone())
+            .ftps_reloader(reloader.clone()),
+    )
+    .await;
+    assert_eq!(auth_tls_certificate(&addr, "localhost").await, pem_certificate(&resource("localhost.pem")));
+
+    install_certificate("localhost-renewed", &certs_file, &key_file);
+    reloader.reload().unwrap();
+    assert_eq!(
+        auth_tls_certificate(&addr, "localhost").await,
+        pem_certificate(&resource("localhost-renewed.pem"))
+    );
+
+    // A reload that fails keeps the configuration that was loaded last.
+    fs::write(&certs_file, b"not a certificate").unwrap();
+    assert!(reloader.reload().is_err());
+    assert_eq!(
+        auth_tls_certificate(&addr, "localhost").await,
+        pem_certificate(&resource("localhost-renewed.pem"))
+    );
+}
+
+#[tokio::test]
+async fn ftps_reload_interval() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let (certs_file, key_file) = (dir.path().join("server.pem"), dir.path().join("server.key"));
+    install_certificate("localhost", &certs_file, &key_file);
+    let addr = start_server(
+        libunftp::Server::with_fs(std::env::temp_dir())
+            .ftps(certs_file.clone(), key_file.clone())
+            .ftps_reload_interval(Duration::from_millis(50)),
+    )
+    .await;
+    assert_eq!(auth_tls_certificate(&addr, "localhost").await, pem_certificate(&resource("localhost.pem")));
+
+    // A certificate that can't be loaded is ignored.
+    fs::write(&certs_file, b"not a certificate").unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(auth_tls_certificate(&addr, "localhost").await, pem_certificate(&resource("localhost.pem")));
+
+    install_certificate("localhost-renewed", &certs_file, &key_file);
+    let renewed = pem_certificate(&resource("localhost-renewed.pem"));
+    for _ in 0..50 {
+        if auth_tls_certificate(&addr, "localhost").await == renewed {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The renewed certificate wasn't loaded");
+}
+
+// Returns the path of a file in tests/resources. The test certificates there are signed by ca.pem.
+// localhost.pem and localhost-renewed.pem are for localhost and ftp.example.com.pem is for
+// ftp.example.com.
+fn resource(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/resources").join(name)
+}
+
+// Returns the DER encoding of the first certificate in a PEM file.
+fn pem_certificate(path: &std::path::Path) -> Vec<u8> {
+    let mut reader = std::io::BufReader::new(fs::File::open(path).unwrap());
+    rustls::internal::pemfile::certs(&mut reader).unwrap().remove(0).0
+}
+
+// Does the TLS handshake as a client that trusts the test CA and asks for the host name with SNI.
+async fn tls_handshake(stream: TcpStream, hostname: &str) -> tokio_rustls::client::TlsStream<TcpStream> {
+    let mut config = rustls::ClientConfig::new();
+    let mut ca = std::io::BufReader::new(fs::File::open(resource("ca.pem")).unwrap());
+    config.root_store.add_pem_file(&mut ca).unwrap();
+    let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config));
+    let name = webpki::DNSNameRef::try_from_ascii_str(hostname).unwrap();
+    connector.connect(name, stream).await.unwrap()
+}
+
+// Secures a new control connection with AUTH TLS and returns the DER encoding of the certificate
+// that the server sent.
+async fn auth_tls_certificate(addr: &str, hostname: &str) -> Vec<u8> {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let mut greeting = String::new();
+    stream.read_line(&mut greeting).await.unwrap();
+    stream.get_mut().write_all(b"AUTH TLS\r\n").await.unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    assert!(reply.starts_with("234"), "{}", reply);
+    let tls = tls_handshake(stream.into_inner(), hostname).await;
+    let certs = rustls::Session::get_peer_certificates(tls.get_ref().1).unwrap();
+    certs[0].0.clone()
+}
+
+#[tokio::test]
+async fn ftps_sni_certificate() {
+    let addr = start_server(
+        libunftp::Server::with_fs(std::env::temp_dir())
+            .ftps(resource("localhost.pem"), resource("localhost.key"))
+            .ftps_sni_certificate("ftp.example.com", resource("ftp.example.com.pem"), resource("ftp.example.com.key")),
+    )
+    .await;
+
+    assert_eq!(
+        auth_tls_certificate(&addr, "ftp.example.com").await,
+        pem_certificate(&resource("ftp.example.com.pem"))
+    );
+    // Host names without a certificate of their own get the default one.
+    assert_eq!(auth_tls_certificate(&addr, "localhost").await, pem_certificate(&resource("localhost.pem")));
+}
+
+// Sends a command over a TLS control connection and returns the reply. An empty command only reads
+// the next reply.
+async fn tls_cmd(control: &mut BufReader<tokio_rustls::client::TlsStream<TcpStream>>, cmd: &str) -> String {
+    if !cmd.is_empty() {
+        control.get_mut().write_all(format!("{}\r\n", cmd).as_bytes()).await.unwrap();
+    }
+    let mut reply = String::new();
+    control.read_line(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn ftps_implicit_refuses_clear_data_channel() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    fs::write(root.join("secret.txt"), b"encrypted").unwrap();
+    let addr = start_server(
+        libunftp::Server::with_fs(root)
+            .ftps(resource("localhost.pem"), resource("localhost.key"))
+            .ftps_implicit(true),
+    )
+    .await;
+
+    let stream = TcpStream::connect(&addr).await.unwrap();
+    let ip = stream.peer_addr().unwrap().ip();
+    let mut control = BufReader::new(tls_handshake(stream, "localhost").await);
+    assert!(tls_cmd(&mut control, "").await.starts_with("220"));
+    assert!(tls_cmd(&mut control, "USER hoi").await.starts_with("331"));
+    assert!(tls_cmd(&mut control, "PASS jij").await.starts_with("230"));
+    assert!(tls_cmd(&mut control, "PROT C").await.starts_with("534"));
+
+    // The data channel is still encrypted.
+    let reply = tls_cmd(&mut control, "EPSV").await;
+    assert!(reply.starts_with("229"), "{}", reply);
+    let data = TcpStream::connect((ip, epsv_port(reply.trim_end()))).await.unwrap();
+    assert!(tls_cmd(&mut control, "RETR secret.txt").await.starts_with("150"));
+    let mut data = tls_handshake(data, "localhost").await;
+    let mut content = String::new();
+    data.read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "encrypted");
+    assert!(tls_cmd(&mut control, "").await.starts_with("226"));
+}
+
+#[tokio::test]
+async fn listen_on_several_listeners() {
+    let root = std::env::temp_dir();
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let std_addr = std_listener.local_addr().unwrap().to_string();
+    let tokio_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tokio_addr = tokio_listener.local_addr().unwrap().to_string();
+    let listeners = vec![
+        libunftp::options::Listener::from(std_listener),
+        libunftp::options::Listener::from(tokio_listener),
+    ];
+    tokio::spawn(libunftp::Server::with_fs(root.clone()).listen_on(listeners));
+
+    let mut ftp = RawControlChannel::connect(&std_addr).await;
+    ftp.login().await;
+    let mut ftp = RawControlChannel::connect(&tokio_addr).await;
+    ftp.login().await;
+
+    assert!(libunftp::Server::with_fs(root.clone()).listen("not an address").await.is_err());
+    assert!(libunftp::Server::with_fs(root).listen_on(Vec::<String>::new()).await.is_err());
+}
+
+// A user that lives in /home/<name>.
+#[derive(Debug)]
+struct HomeUser {
+    name: String,
+    home: PathBuf,
+}
+
+impl libunftp::auth::UserDetail for HomeUser {
+    fn home(&self) -> Option<&std::path::Path> {
+        Some(&self.home)
+    }
+}
+
+impl std::fmt::Display for HomeUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug)]
+struct HomeAuthenticator;
+
+#[async_trait::async_trait]
+impl libunftp::auth::Authenticator<HomeUser> for HomeAuthenticator {
+    async fn authenticate(&self, username: &str, _creds: &libunftp::auth::Credentials) -> std::result::Result<HomeUser, libunftp::auth::AuthenticationError> {
+        Ok(HomeUser {
+            name: username.to_string(),
+            home: PathBuf::from("/home").join(username),
+        })
+    }
+}
+
+#[tokio::test]
+async fn home_directories() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir_all(root.join("home/hoi")).unwrap();
+    std::fs::create_dir_all(root.join("home/other")).unwrap();
+    std::fs::write(root.join("home/other/secret.txt"), b"secret").unwrap();
+    let server_root = root.clone();
+    let server = libunftp::Server::with_authenticator(
+        Box::new(move || unftp_sbe_fs::Filesystem::new(server_root.clone())),
+        std::sync::Arc::new(HomeAuthenticator),
+    );
+    let addr = start_server(server).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(ftp.cmd("PWD").await, "257 \"/home/hoi\"");
+    assert!(ftp.cmd("MKD sub").await.starts_with("257"));
+    assert!(root.join("home/hoi/sub").is_dir());
+
+    assert!(ftp.cmd("CWD /home/other").await.starts_with("550"));
+    assert!(ftp.cmd("CDUP").await.starts_with("250"));
+    assert_eq!(ftp.cmd("PWD").await, "257 \"/home/hoi\"");
+    assert!(ftp.cmd("SIZE /home/other/secret.txt").await.starts_with("550"));
+    assert!(ftp.cmd("DELE ../other/secret.txt").await.starts_with("550"));
+    assert!(root.join("home/other/secret.txt").exists());
+}
+
+// A user with the permissions its name asks for.
+#[derive(Debug)]
+struct PermissionsUser {
+    permissions: libunftp::auth::Permissions,
+}
+
+impl libunftp::auth::UserDetail for PermissionsUser {
+    fn permissions(&self) -> libunftp::auth::Permissions {
+        self.permissions
+    }
+}
+
+impl std::fmt::Display for PermissionsUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.permissions)
+    }
+}
+
+#[derive(Debug)]
+struct PermissionsAuthenticator;
+
+#[async_trait::async_trait]
+impl libunftp::auth::Authenticator<PermissionsUser> for PermissionsAuthenticator {
+    async fn authenticate(
+        &self,
+        username: &str,
+        _creds: &libunftp::auth::Credentials,
+    ) -> std::result::Result<PermissionsUser, libunftp::auth::AuthenticationError> {
+        let permissions = match username {
+            "reader" => libunftp::auth::Permissions::READ_ONLY,
+            _ => libunftp::auth::Permissions::UPLOAD_ONLY,
+        };
+        Ok(PermissionsUser { permissions })
+    }
+}
+
+#[tokio::test]
+async fn permissions() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("keep.txt"), b"hallo").unwrap();
+    let server_root = root.clone();
+    let server = libunftp::Server::with_authenticator(
+        Box::new(move || unftp_sbe_fs::Filesystem::new(server_root.clone())),
+        std::sync::Arc::new(PermissionsAuthenticator),
+    );
+    let addr = start_server(server).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login_as("reader").await;
+    assert_eq!(ftp.cmd("SIZE keep.txt").await, "213 5");
+    assert_eq!(ftp.cmd("DELE keep.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("MKD sub").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("STOR new.txt").await, "550 Permission denied");
+    assert!(root.join("keep.txt").exists());
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login_as("uploader").await;
+    assert_eq!(ftp.cmd("SIZE keep.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("RETR keep.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("RNFR keep.txt").await, "550 Permission denied");
+    assert!(ftp.cmd("PWD").await.starts_with("257"));
+}
+
+#[tokio::test]
+async fn acl() {
+    use libunftp::acl::{Acl, Rule};
+    use libunftp::auth::Permissions;
+
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir_all(root.join("incoming")).unwrap();
+    std::fs::create_dir_all(root.join("outgoing")).unwrap();
+    std::fs::create_dir_all(root.join("internal")).unwrap();
+    std::fs::write(root.join("outgoing/report.txt"), b"report").unwrap();
+    std::fs::write(root.join("internal/secret.txt"), b"secret").unwrap();
+    let acl = Acl::new()
+        .rule(Rule::new("/internal/**", Permissions::empty()))
+        .rule(Rule::new("/", Permissions::LIST).user("partner"))
+        .rule(Rule::new("/incoming/**", Permissions::WRITE | Permissions::LIST).user("partner"))
+        .rule(Rule::new("/outgoing/**", Permissions::READ_ONLY).user("partner"))
+        .rule(Rule::new("/**", Permissions::empty()).user("partner"));
+
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).acl(acl)).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login_as("partner").await;
+    let mut names = list_names(&mut ftp, "LIST").await;
+    names.sort();
+    assert_eq!(names, vec!["incoming", "outgoing"]);
+    // STAT sends the listing over the control channel. It ends with an empty line.
+    let mut listing = vec![ftp.cmd("STAT /").await];
+    while !listing.last().unwrap().is_empty() {
+        listing.push(ftp.reply().await);
+    }
+    assert!(listing[0].starts_with("200"));
+    assert!(listing.iter().any(|line| line.ends_with(" incoming")));
+    assert!(listing.iter().any(|line| line.ends_with(" outgoing")));
+    assert!(!listing.iter().any(|line| line.contains("internal")));
+    assert_eq!(ftp.cmd("CWD /internal").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("SIZE /internal/secret.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("SIZE /outgoing/../internal/secret.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("SIZE /outgoing/report.txt").await, "213 6");
+    // The perm fact tells what the user may do, not what the file mode allows.
+    let mlst = ftp.cmd_multiline("MLST /outgoing/report.txt").await;
+    assert!(mlst[1].contains(";perm=r;"), "{:?}", mlst);
+    assert_eq!(ftp.cmd("DELE /outgoing/report.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("STOR /outgoing/new.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("MKD /incoming/sub").await, "550 Permission denied");
+    assert!(ftp.cmd("CWD /incoming").await.starts_with("250"));
+    assert_eq!(ftp.cmd("RNFR /outgoing/report.txt").await, "550 Permission denied");
+
+    // The other rules don't apply to other users.
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(ftp.cmd("SIZE /internal/secret.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("SIZE /outgoing/report.txt").await, "213 6");
+    assert!(ftp.cmd("MKD /incoming/sub").await.starts_with("257"));
+}
+
+// Sends STOR over an EPSV data connection and returns the first reply.
+async fn upload(ftp: &mut RawControlChannel, cmd: &str, content: &[u8]) -> String {
+    let mut data = ftp.epsv_connect().await;
+    let reply = ftp.cmd(cmd).await;
+    if reply.starts_with("150") {
+        data.write_all(content).await.unwrap();
+        data.shutdown().await.unwrap();
+        assert!(ftp.reply().await.starts_with("226"));
+    }
+    reply
+}
+
+#[tokio::test]
+async fn dropbox() {
+    use libunftp::acl::{Acl, Dropbox};
+
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::create_dir_all(root.join("incoming")).unwrap();
+    std::fs::create_dir_all(root.join("uploads")).unwrap();
+    std::fs::write(root.join("uploads/report.txt"), b"first").unwrap();
+    let acl = Acl::new()
+        .dropbox(Dropbox::new("/incoming/**").user("partner"))
+        .dropbox(Dropbox::new("/uploads/**").unique_names(true));
+
+    let addr = start_server(libunftp::Server::with_fs(root.clone()).acl(acl)).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login_as("partner").await;
+    assert!(upload(&mut ftp, "STOR /incoming/report.txt", b"hallo").await.starts_with("150"));
+    assert_eq!(fs::read_to_string(root.join("incoming/report.txt")).unwrap(), "hallo");
+    assert_eq!(upload(&mut ftp, "STOR /incoming/report.txt", b"other").await, "553 File already exists");
+    assert_eq!(fs::read_to_string(root.join("incoming/report.txt")).unwrap(), "hallo");
+    assert_eq!(ftp.cmd("APPE /incoming/report.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("RETR /incoming/report.txt").await, "550 Permission denied");
+    assert_eq!(ftp.cmd("LIST /incoming").await, "550 Permission denied");
+    let names = list_names(&mut ftp, "LIST -R").await;
+    assert!(names.contains(&String::from("incoming")));
+    assert!(!names.contains(&String::from("report.txt")));
+
+    // Other users are not limited by the partner's dropbox but uploads to /uploads get unique names.
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(ftp.cmd("SIZE /incoming/report.txt").await, "213 5");
+    let reply = upload(&mut ftp, "STOR /uploads/report.txt", b"second").await;
+    assert!(reply.starts_with("150 FILE: "), "{}", reply);
+    let name = reply.trim_start_matches("150 FILE: ");
+    assert_eq!(fs::read_to_string(root.join("uploads/report.txt")).unwrap(), "first");
+    assert_eq!(fs::read_to_string(root.join("uploads").join(name)).unwrap(), "second");
+}
+
+// A user that keeps the old versions of the files it overwrites if its name asks for it.
+#[derive(Debug)]
+struct OverwriteUser {
+    policy: Option<libunftp::options::OverwritePolicy>,
+}
+
+impl libunftp::auth::UserDetail for OverwriteUser {
+    fn overwrite_policy(&self) -> Option<libunftp::options::OverwritePolicy> {
+        self.policy
+    }
+}
+
+impl std::fmt::Display for OverwriteUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.policy)
+    }
+}
+
+#[derive(Debug)]
+struct OverwriteAuthenticator;
+
+#[async_trait::async_trait]
+impl libunftp::auth::Authenticator<OverwriteUser> for OverwriteAuthenticator {
+    async fn authenticate(
+        &self,
+        username: &str,
+        _creds: &libunftp::auth::Credentials,
+    ) -> std::result::Result<OverwriteUser, libunftp::auth::AuthenticationError> {
+        let policy = match username {
+            "archivist" => Some(libunftp::options::OverwritePolicy::Version),
+            _ => None,
+        };
+        Ok(OverwriteUser { policy })
+    }
+}
+
+#[tokio::test]
+async fn overwrite_policy() {
+    let root = tempfile::TempDir::new().unwrap().into_path();
+    std::fs::write(root.join("report.txt"), b"first").unwrap();
+    let server_root = root.clone();
+    let server = libunftp::Server::with_authenticator(
+        Box::new(move || unftp_sbe_fs::Filesystem::new(server_root.clone())),
+        std::sync::Arc::new(OverwriteAuthenticator),
+    )
+    .overwrite_policy(libunftp::options::OverwritePolicy::Refuse);
+    let addr = start_server(server).await;
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login().await;
+    assert_eq!(upload(&mut ftp, "STOR report.txt", b"second").await, "553 File already exists");
+    assert!(upload(&mut ftp, "STOR other.txt", b"other").await.starts_with("150"));
+    assert_eq!(fs::read_to_string(root.join("report.txt")).unwrap(), "first");
+
+    let mut ftp = RawControlChannel::connect(&addr).await;
+    ftp.login_as("archivist").await;
+    assert!(upload(&mut ftp, "STOR report.txt", b"second").await.starts_with("150"));
+    assert!(upload(&mut ftp, "STOR report.txt", b"third").await.starts_with("150"));
+    assert_eq!(fs::read_to_string(root.join("report.txt")).unwrap(), "third");
+    assert_eq!(fs::read_to_string(root.join("report.txt.1")).unwrap(), "first");
+    assert_eq!(fs::read_to_string(root.join("report.txt.2")).unwrap(), "second");
+    // The next version is one higher than the highest one, even with gaps.
+    std::fs::write(root.join("report.txt.7"), b"seventh").unwrap();
+    assert!(upload(&mut ftp, "STOR report.txt", b"fourth").await.starts_with("150"));
+    assert_eq!(fs::read_to_string(root.join("report.txt.8")).unwrap(), "third");
+}
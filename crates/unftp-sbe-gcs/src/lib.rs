@@ -54,6 +54,12 @@
 //! }
 //! ```
 //!
+//! # Appending
+//!
+//! GCS objects can't be changed, so `APPE` uploads the data to a temporary object next to the
+//! file and composes both into a new version of the file. The temporary objects end in
+//! `.unftp-append-<nanoseconds>` and are left out of listings. A composed object can consist of
+//! at most 1024 components, so a file can be appended to at most 1023 times.
 
 // FIXME: error mapping from GCS/hyper is minimalistic, mostly PermanentError. Do proper mapping and better reporting (temporary failures too!)
 
@@ -78,20 +84,25 @@ use hyper::{
 };
 use hyper_rustls::HttpsConnector;
 use libunftp::auth::UserDetail;
-use libunftp::storage::{Error, ErrorKind, Fileinfo, Metadata, StorageBackend};
-use mime::APPLICATION_OCTET_STREAM;
+use libunftp::storage::{Error, ErrorKind, Fileinfo, HashAlgorithm, Metadata, StorageBackend};
+use mime::{APPLICATION_JSON, APPLICATION_OCTET_STREAM};
 use object_metadata::ObjectMetadata;
 use options::AuthMethod;
-use response_body::{Item, ResponseBody};
+use response_body::{Item, ResponseBody, APPEND_TMP_MARKER, MTIME_METADATA_KEY};
+use serde_json::json;
 use std::{
     fmt::Debug,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 use tokio::io::{self, AsyncReadExt};
 use tokio_util::codec::{BytesCodec, FramedRead};
-use uri::GcsUri;
+use uri::{check_home, GcsUri};
 use yup_oauth2::ServiceAccountAuthenticator;
 
+// The most source objects GCS lets a composite object consist of.
+const MAX_COMPONENTS: u32 = 1024;
+
 /// A [`StorageBackend`](libunftp::storage::StorageBackend) that uses Cloud storage from Google.
 #[derive(Clone, Debug)]
 pub struct CloudStorage {
@@ -159,18 +170,17 @@ impl CloudStorage {
                 .map(|t| t.access_token),
         }
     }
-}
-
-#[async_trait]
-impl<User: UserDetail> StorageBackend<User> for CloudStorage {
-    type Metadata = ObjectMetadata;
 
-    fn supported_features(&self) -> u32 {
-        libunftp::storage::FEATURE_SITEMD5
+    // Fetches the object resource, holding e.g. the size and checksums, of the object at `path`.
+    #[tracing_attributes::instrument]
+    async fn get_item<P: AsRef<Path> + Send + Debug>(&self, path: P) -> Result<Item, Error> {
+        self.find_item(path).await?.ok_or_else(|| Error::from(ErrorKind::PermanentFileNotAvailable))
     }
 
+    // Like get_item but returns `None` if the object doesn't exist, which is told apart from
+    // failing to find out.
     #[tracing_attributes::instrument]
-    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Self::Metadata, Error> {
+    async fn find_item<P: AsRef<Path> + Send + Debug>(&self, path: P) -> Result<Option<Item>, Error> {
         let uri: Uri = self.uris.metadata(path)?;
 
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
@@ -184,20 +194,93 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
             .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
 
         let response: Response<Body> = client.request(request).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
 
         let body = unpack_response(response).await?;
 
         let body_str: &str = std::str::from_utf8(body.chunk()).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
 
-        let response: Item = serde_json::from_str(body_str).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+        serde_json::from_str(body_str)
+            .map(Some)
+            .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))
+    }
 
-        response.to_metadata()
+    // Uploads the data from the reader with a media upload request to the given URI and returns
+    // the size of the resulting object.
+    async fn upload<B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(&self, bytes: B, uri: Uri) -> Result<u64, Error> {
+        let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
+
+        let reader = tokio::io::BufReader::with_capacity(4096, bytes);
+
+        let token = self.get_token().await?;
+        let request: Request<Body> = Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, APPLICATION_OCTET_STREAM.to_string())
+            .method(Method::POST)
+            .body(Body::wrap_stream(FramedRead::new(reader, BytesCodec::new()).map_ok(|b| b.freeze())))
+            .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+
+        let response: Response<Body> = client.request(request).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e)).await?;
+        let body = unpack_response(response).await?;
+        let response: Item = serde_json::from_reader(body.reader()).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+
+        Ok(response.to_metadata()?.len())
     }
 
-    async fn md5<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<String, Error>
-    where
-        P: AsRef<Path> + Send + Debug,
-    {
+    // Composes the object at `path`, if there is one, and the temporary object at `tmp_path` into
+    // a new version of the object at `path`.
+    async fn compose(&self, path: &Path, tmp_path: &Path, existing: Option<&Item>) -> Result<(), Error> {
+        let mut source_objects = vec![];
+        if existing.is_some() {
+            source_objects.push(json!({ "name": self.uris.object_name(path)? }));
+        }
+        source_objects.push(json!({ "name": self.uris.object_name(tmp_path)? }));
+        let destination = match existing {
+            Some(item) => item.to_destination(),
+            None => json!({ "contentType": APPLICATION_OCTET_STREAM.to_string() }),
+        };
+        let compose = json!({
+            "sourceObjects": source_objects,
+            "destination": destination,
+        });
+
+        // The compose fails if another upload changed the object since we looked at it, instead of
+        // dropping that upload's data.
+        let generation = existing.map(|item| item.generation()).unwrap_or("0");
+        let uri: Uri = self.uris.compose(path, generation)?;
+        let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
+        let token = self.get_token().await?;
+        let request: Request<Body> = Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, APPLICATION_JSON.to_string())
+            .method(Method::POST)
+            .body(Body::from(compose.to_string()))
+            .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+        let response: Response<Body> = client.request(request).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e)).await?;
+        match unpack_response(response).await {
+            Ok(_) => Ok(()),
+            // The client may try again.
+            Err(e) if e.kind() == ErrorKind::FileAlreadyExists => Err(Error::from(ErrorKind::TransientFileNotAvailable)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl<User: UserDetail> StorageBackend<User> for CloudStorage {
+    type Metadata = ObjectMetadata;
+
+    fn supported_features(&self) -> u32 {
+        libunftp::storage::FEATURE_SITEMD5 | libunftp::storage::FEATURE_APPEND | libunftp::storage::FEATURE_MFMT | libunftp::storage::FEATURE_HASH
+    }
+
+    #[tracing_attributes::instrument]
+    async fn metadata<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Self::Metadata, Error> {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.metadata(path)?;
 
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
@@ -218,14 +301,48 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
 
         let response: Item = serde_json::from_str(body_str).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
 
-        Ok(response.to_md5()?)
+        response.to_metadata()
     }
 
+    async fn md5<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<String, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+    {
+        check_home(user.home(), &path)?;
+        self.checksum(user, path, HashAlgorithm::Md5, None).await
+    }
+
+    // GCS keeps the MD5 and CRC32C checksums of objects so for those we usually don't need to
+    // download the whole object. Composite objects only have a CRC32C checksum.
     #[tracing_attributes::instrument]
-    async fn list<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>, Error>
+    async fn checksum<P: AsRef<Path> + Send + Debug>(
+        &self,
+        user: &User,
+        path: P,
+        algorithm: HashAlgorithm,
+        range: Option<(u64, u64)>,
+    ) -> Result<String, Error> {
+        check_home(user.home(), &path)?;
+        let known = match (algorithm, range) {
+            (HashAlgorithm::Md5, None) => self.get_item(path.as_ref()).await?.to_md5()?,
+            (HashAlgorithm::Crc32c, None) => Some(self.get_item(path.as_ref()).await?.to_crc32c()?),
+            _ => None,
+        };
+        match known {
+            Some(checksum) => Ok(checksum),
+            None => {
+                let reader = self.get(user, path, 0).await?;
+                algorithm.hash_reader(reader, range).await
+            }
+        }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<PathBuf, Self::Metadata>>, Error>
     where
         <Self as StorageBackend<User>>::Metadata: Metadata,
     {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.list(path)?;
 
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
@@ -261,10 +378,11 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
     //#[tracing_attributes::instrument]
     async fn get<P: AsRef<Path> + Send + Debug>(
         &self,
-        _user: &User,
+        user: &User,
         path: P,
         _start_pos: u64,
     ) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>, Error> {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.get(path)?;
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
 
@@ -290,35 +408,92 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
 
     async fn put<P: AsRef<Path> + Send + Debug, B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
         &self,
-        _user: &User,
+        user: &User,
         bytes: B,
         path: P,
         _start_pos: u64,
     ) -> Result<u64, Error> {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.put(path)?;
+        self.upload(bytes, uri).await
+    }
 
-        let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
+    async fn put_new<P: AsRef<Path> + Send + Debug, B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        bytes: B,
+        path: P,
+    ) -> Result<u64, Error> {
+        check_home(user.home(), &path)?;
+        // GCS refuses the upload if the object exists, so there's no window for another upload
+        // to create it in between.
+        let uri: Uri = self.uris.put_new(path)?;
+        self.upload(bytes, uri).await
+    }
 
-        let reader = tokio::io::BufReader::with_capacity(4096, bytes);
+    async fn append<P: AsRef<Path> + Send + Debug, B: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        bytes: B,
+        path: P,
+    ) -> Result<u64, Error> {
+        // GCS objects can't be changed so we upload the data to a temporary object and then
+        // compose the existing object and the temporary one into a new version of the object.
+        let path = path.as_ref();
+        check_home(user.home(), path)?;
+        let existing = self.find_item(path).await?;
+        // Every append adds a component to the object and GCS refuses to compose more than
+        // MAX_COMPONENTS of them, so stop before uploading the data.
+        if let Some(item) = &existing {
+            if item.component_count() >= MAX_COMPONENTS {
+                return Err(Error::new(
+                    ErrorKind::PermanentFileNotAvailable,
+                    format!("an object can't be appended to more than {} times", MAX_COMPONENTS - 1),
+                ));
+            }
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        tmp_path.push(format!("{}{}", APPEND_TMP_MARKER, nanos));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let result = match self.put(user, bytes, &tmp_path, 0).await {
+            Ok(appended) => self.compose(path, &tmp_path, existing.as_ref()).await.map(|_| appended),
+            Err(e) => Err(e),
+        };
 
+        // The temporary object is not needed anymore whether composing succeeded or not.
+        let _ = self.del(user, &tmp_path).await;
+        result
+    }
+
+    #[tracing_attributes::instrument]
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, modified: SystemTime) -> Result<(), Error> {
+        check_home(user.home(), &path)?;
+        // The updated time of an object can't be changed so we keep the modification time in the
+        // custom metadata of the object.
+        let secs = modified.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let patch = json!({ "metadata": { MTIME_METADATA_KEY: secs.to_string() } });
+
+        let uri: Uri = self.uris.metadata(path)?;
+        let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
         let token = self.get_token().await?;
         let request: Request<Body> = Request::builder()
             .uri(uri)
             .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .header(header::CONTENT_TYPE, APPLICATION_OCTET_STREAM.to_string())
-            .method(Method::POST)
-            .body(Body::wrap_stream(FramedRead::new(reader, BytesCodec::new()).map_ok(|b| b.freeze())))
+            .header(header::CONTENT_TYPE, APPLICATION_JSON.to_string())
+            .method(Method::PATCH)
+            .body(Body::from(patch.to_string()))
             .map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
-
         let response: Response<Body> = client.request(request).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e)).await?;
-        let body = unpack_response(response).await?;
-        let response: Item = serde_json::from_reader(body.reader()).map_err(|e| Error::new(ErrorKind::PermanentFileNotAvailable, e))?;
+        unpack_response(response).await?;
 
-        Ok(response.to_metadata()?.len())
+        Ok(())
     }
 
     #[tracing_attributes::instrument]
-    async fn del<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<(), Error> {
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<(), Error> {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.delete(path)?;
 
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
@@ -336,7 +511,8 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
     }
 
     #[tracing_attributes::instrument]
-    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> Result<(), Error> {
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<(), Error> {
+        check_home(user.home(), &path)?;
         let uri: Uri = self.uris.mkd(path)?;
         let client: Client<HttpsConnector<HttpConnector<GaiResolver>>, Body> = self.client.clone();
 
@@ -367,7 +543,8 @@ impl<User: UserDetail> StorageBackend<User> for CloudStorage {
     }
 
     #[tracing_attributes::instrument]
-    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P) -> Result<(), Error> {
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<(), Error> {
+        check_home(user.home(), &path)?;
         // TODO: Do we want to check here if the path is a directory?
         Ok(())
     }
@@ -389,6 +566,8 @@ fn result_based_on_http_status<T>(status: StatusCode, ok_val: T) -> Result<T, Er
         let err_kind = match status.as_u16() {
             404 => ErrorKind::PermanentFileNotAvailable,
             401 | 403 => ErrorKind::PermissionDenied,
+            // A generation precondition failed, for instance because the object exists already.
+            412 => ErrorKind::FileAlreadyExists,
             429 => ErrorKind::TransientFileNotAvailable,
             _ => ErrorKind::LocalError,
         };
@@ -4,7 +4,12 @@ use libunftp::storage::{Error, ErrorKind, Fileinfo};
 use serde::{de, Deserialize};
 use std::fmt::Display;
 use std::str::FromStr;
-use std::{iter::Extend, path::PathBuf};
+use std::{
+    collections::HashMap,
+    iter::Extend,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct ResponseBody {
@@ -22,8 +27,30 @@ pub(crate) struct Item {
     size: u64,
     #[serde(default, rename = "md5Hash")]
     md5_hash: String,
+    // The big-endian CRC32C checksum, base64 encoded.
+    #[serde(default)]
+    crc32c: String,
+    // Custom metadata. We keep the modification time set with MFMT in here.
+    #[serde(default)]
+    metadata: Option<HashMap<String, String>>,
+    // The version of the object's data, used for preconditions.
+    #[serde(default)]
+    generation: String,
+    #[serde(default, rename = "contentType")]
+    content_type: Option<String>,
+    // The number of source objects a composite object was composed of. Absent for other objects.
+    #[serde(default, rename = "componentCount")]
+    component_count: u32,
 }
 
+/// The custom metadata key that holds the modification time of an object in seconds since the
+/// unix epoch. It is the key `gsutil` uses to preserve modification times.
+pub(crate) const MTIME_METADATA_KEY: &str = "goog-reserved-file-mtime";
+
+/// Marks the temporary objects that APPE uploads the appended data to. They are left out of
+/// listings.
+pub(crate) const APPEND_TMP_MARKER: &str = ".unftp-append-";
+
 // TODO: this is a generic string->* deserializer, move to a util package
 fn item_size_deserializer<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
@@ -40,7 +67,7 @@ impl ResponseBody {
         let files: Vec<Fileinfo<PathBuf, ObjectMetadata>> = self.items.map_or(Ok(vec![]), move |items: Vec<Item>| {
             items
                 .iter()
-                .filter(|item: &&Item| !item.name.ends_with('/'))
+                .filter(|item: &&Item| !item.name.ends_with('/') && !item.name.contains(APPEND_TMP_MARKER))
                 .map(move |item: &Item| item.to_file_info())
                 .collect()
         })?;
@@ -60,9 +87,15 @@ impl ResponseBody {
 
 impl Item {
     pub(crate) fn to_metadata(&self) -> Result<ObjectMetadata, Error> {
+        let mtime = self
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get(MTIME_METADATA_KEY))
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
         Ok(ObjectMetadata {
             size: self.size,
-            last_updated: Some(self.updated.into()),
+            last_updated: Some(mtime.unwrap_or_else(|| self.updated.into())),
             is_file: !self.name.ends_with('/'),
         })
     }
@@ -74,9 +107,38 @@ impl Item {
         Ok(Fileinfo { path, metadata })
     }
 
-    pub(crate) fn to_md5(&self) -> Result<String, Error> {
+    // Composite objects, like the ones that APPE creates, have no MD5 hash. Then this returns `None`.
+    pub(crate) fn to_md5(&self) -> Result<Option<String>, Error> {
+        if self.md5_hash.is_empty() {
+            return Ok(None);
+        }
         let md5 = base64::decode(&self.md5_hash).map_err(|e| Error::new(ErrorKind::LocalError, e))?;
-        Ok(md5.iter().map(|b| format!("{:02x}", b)).collect())
+        Ok(Some(md5.iter().map(|b| format!("{:02x}", b)).collect()))
+    }
+
+    pub(crate) fn to_crc32c(&self) -> Result<String, Error> {
+        let crc32c = base64::decode(&self.crc32c).map_err(|e| Error::new(ErrorKind::LocalError, e))?;
+        Ok(crc32c.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub(crate) fn generation(&self) -> &str {
+        &self.generation
+    }
+
+    // Objects that weren't composed count as one component.
+    pub(crate) fn component_count(&self) -> u32 {
+        self.component_count.max(1)
+    }
+
+    // The content type and custom metadata of the object, to carry over to a new version of it.
+    pub(crate) fn to_destination(&self) -> serde_json::Value {
+        let mut destination = serde_json::json!({
+            "contentType": self.content_type.as_deref().unwrap_or("application/octet-stream"),
+        });
+        if let Some(metadata) = &self.metadata {
+            destination["metadata"] = serde_json::json!(metadata);
+        }
+        destination
     }
 }
 
@@ -95,7 +157,6 @@ pub(crate) fn prefix_to_file_info(prefix: &str) -> Result<Fileinfo<PathBuf, Obje
 mod test {
     use super::*;
     use libunftp::storage::Metadata;
-    use std::time::SystemTime;
 
     #[test]
     fn to_metadata() {
@@ -107,6 +168,11 @@ mod test {
             updated: date_time,
             size: 50,
             md5_hash: "".into(),
+            crc32c: "".into(),
+            metadata: None,
+            generation: "".into(),
+            content_type: None,
+            component_count: 0,
         };
 
         let metadata: ObjectMetadata = item.to_metadata().unwrap();
@@ -115,6 +181,62 @@ mod test {
         assert_eq!(metadata.is_file, true);
     }
 
+    #[test]
+    fn to_metadata_with_mtime() {
+        let item: Item =
+            serde_json::from_str(r#"{"name":"a.txt", "updated":"2021-09-01T12:13:14Z", "size":"8", "metadata":{"goog-reserved-file-mtime":"1626171330"}}"#)
+                .unwrap();
+        let metadata: ObjectMetadata = item.to_metadata().unwrap();
+        assert_eq!(metadata.modified().unwrap(), SystemTime::UNIX_EPOCH + Duration::from_secs(1_626_171_330));
+    }
+
+    #[test]
+    fn to_md5() {
+        let item: Item =
+            serde_json::from_str(r#"{"name":"a.txt", "updated":"2021-09-01T12:13:14Z", "size":"5", "md5Hash":"WY1MIARhuBUiozKFZcJffA=="}"#).unwrap();
+        assert_eq!(item.to_md5().unwrap().as_deref(), Some("598d4c200461b81522a3328565c25f7c"));
+        let composite: Item = serde_json::from_str(r#"{"name":"a.log", "updated":"2021-09-01T12:13:14Z", "size":"8", "crc32c":"ImIEBA=="}"#).unwrap();
+        assert_eq!(composite.to_md5().unwrap(), None);
+    }
+
+    #[test]
+    fn to_crc32c() {
+        let item: Item = serde_json::from_str(r#"{"name":"a.txt", "updated":"2021-09-01T12:13:14Z", "size":"43", "crc32c":"ImIEBA=="}"#).unwrap();
+        assert_eq!(item.to_crc32c().unwrap(), "22620404");
+    }
+
+    #[test]
+    fn to_destination() {
+        let item: Item = serde_json::from_str(
+            r#"{"name":"a.log", "updated":"2021-09-01T12:13:14Z", "size":"8", "generation":"1630498394000000", "contentType":"text/plain", "metadata":{"goog-reserved-file-mtime":"1626171330"}}"#,
+        )
+        .unwrap();
+        assert_eq!(item.generation(), "1630498394000000");
+        assert_eq!(
+            item.to_destination(),
+            serde_json::json!({ "contentType": "text/plain", "metadata": { "goog-reserved-file-mtime": "1626171330" } })
+        );
+    }
+
+    #[test]
+    fn component_count() {
+        let item: Item = serde_json::from_str(r#"{"name":"a.log", "updated":"2021-09-01T12:13:14Z", "size":"8"}"#).unwrap();
+        assert_eq!(item.component_count(), 1);
+        let composite: Item = serde_json::from_str(r#"{"name":"a.log", "updated":"2021-09-01T12:13:14Z", "size":"8", "componentCount":3}"#).unwrap();
+        assert_eq!(composite.component_count(), 3);
+    }
+
+    #[test]
+    fn list_leaves_out_append_objects() {
+        let body: ResponseBody = serde_json::from_str(
+            r#"{"items":[{"name":"a.log", "updated":"2021-09-01T12:13:14Z", "size":"8"}, {"name":"a.log.unftp-append-1630498394000000000", "updated":"2021-09-01T12:13:14Z", "size":"4"}]}"#,
+        )
+        .unwrap();
+        let files = body.list().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, PathBuf::from("a.log"));
+    }
+
     #[test]
     fn to_metadata_parse_error() {
         let response: serde_json::error::Result<Item> = serde_json::from_str(r#"{"name":"", "updated":"2020-09-01T12:13:14Z", "size":8}"#);
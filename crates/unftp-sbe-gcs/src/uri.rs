@@ -1,7 +1,7 @@
 use hyper::Uri;
 use libunftp::storage::{Error, ErrorKind};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Clone, Debug)]
 pub(crate) struct GcsUri {
@@ -33,7 +33,7 @@ impl GcsUri {
             "{}/storage/v1/b/{}/o?prettyPrint=false&fields={}&delimiter=/&prefix={}",
             self.base_url,
             self.bucket,
-            "kind,prefixes,items(id,name,size,updated)", // limit the fields
+            "kind,prefixes,items(id,name,size,updated,metadata)", // limit the fields
             prefix
         ))
     }
@@ -52,6 +52,23 @@ impl GcsUri {
         ))
     }
 
+    // Like `put` but the upload fails with 412 Precondition Failed if the object exists already.
+    pub fn put_new<P: AsRef<Path>>(&self, path: P) -> Result<Uri, Error> {
+        make_uri(format!("{}&ifGenerationMatch=0", self.put(path)?))
+    }
+
+    // The compose only succeeds if the object still has the given generation, `0` meaning that it
+    // doesn't exist.
+    pub fn compose<P: AsRef<Path>>(&self, path: P, if_generation_match: &str) -> Result<Uri, Error> {
+        make_uri(format!(
+            "{}/storage/v1/b/{}/o/{}/compose?ifGenerationMatch={}",
+            self.base_url,
+            self.bucket,
+            self.path_str(path)?,
+            if_generation_match
+        ))
+    }
+
     pub fn delete<P: AsRef<Path>>(&self, path: P) -> Result<Uri, Error> {
         make_uri(format!("{}/storage/v1/b/{}/o/{}", self.base_url, self.bucket, self.path_str(path)?))
     }
@@ -66,16 +83,43 @@ impl GcsUri {
         ))
     }
 
-    fn path_str<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+    // The name of the object as used in JSON request bodies i.e. not percent encoded.
+    pub fn object_name<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
         let path = path.as_ref();
         let relative_path = path.strip_prefix("/").unwrap_or(path);
         if let Some(path) = self.root.join(relative_path).to_str() {
-            let result_path = utf8_percent_encode(path, NON_ALPHANUMERIC).collect::<String>();
-            Ok(result_path)
+            Ok(path.to_string())
         } else {
             Err(Error::from(ErrorKind::PermanentFileNotAvailable))
         }
     }
+
+    fn path_str<P: AsRef<Path>>(&self, path: P) -> Result<String, Error> {
+        let result_path = utf8_percent_encode(&self.object_name(path)?, NON_ALPHANUMERIC).collect::<String>();
+        Ok(result_path)
+    }
+}
+
+// Refuses paths outside of the user's home directory. Paths with '..' components are refused
+// outright because the object name is built from the path as it was given.
+pub(crate) fn check_home<P: AsRef<Path>>(home: Option<&Path>, path: P) -> Result<(), Error> {
+    let home = match home {
+        Some(home) => home,
+        None => return Ok(()),
+    };
+    let mut resolved = PathBuf::from("/");
+    for component in path.as_ref().components() {
+        match component {
+            Component::ParentDir => return Err(Error::from(ErrorKind::FileNameNotAllowedError)),
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    if resolved.starts_with(Path::new("/").join(home)) {
+        Ok(())
+    } else {
+        Err(Error::from(ErrorKind::PermanentFileNotAvailable))
+    }
 }
 
 fn make_uri(path_and_query: String) -> Result<Uri, Error> {
@@ -148,11 +192,50 @@ mod tests {
         ];
 
         let s =
-            "https://storage.googleapis.com/storage/v1/b/the-bucket/o?prettyPrint=false&fields=kind,prefixes,items(id,name,size,updated)&delimiter=/&prefix";
+            "https://storage.googleapis.com/storage/v1/b/the-bucket/o?prettyPrint=false&fields=kind,prefixes,items(id,name,size,updated,metadata)&delimiter=/&prefix";
 
         for test in tests.iter() {
             let uri = GcsUri::new("https://storage.googleapis.com".to_string(), "the-bucket".to_string(), PathBuf::from(test.root));
             assert_eq!(format!("{}={}", s, test.expected_prefix), uri.list(test.sub).unwrap().to_string());
         }
     }
+
+    #[test]
+    fn compose() {
+        let uri = GcsUri::new(
+            "https://storage.googleapis.com".to_string(),
+            "the-bucket".to_string(),
+            PathBuf::from("/the-root"),
+        );
+        assert_eq!(
+            uri.compose("/logs/app.log", "1630498394000000").unwrap().to_string(),
+            "https://storage.googleapis.com/storage/v1/b/the-bucket/o/the%2Droot%2Flogs%2Fapp%2Elog/compose?ifGenerationMatch=1630498394000000"
+        );
+        assert_eq!(uri.object_name("/logs/app.log").unwrap(), "the-root/logs/app.log");
+    }
+
+    #[test]
+    fn put_new() {
+        let uri = GcsUri::new(
+            "https://storage.googleapis.com".to_string(),
+            "the-bucket".to_string(),
+            PathBuf::from("/the-root"),
+        );
+        assert_eq!(
+            uri.put_new("/logs/app.log").unwrap().to_string(),
+            "https://storage.googleapis.com/upload/storage/v1/b/the-bucket/o?uploadType=media&name=the%2Droot%2Flogs%2Fapp%2Elog&ifGenerationMatch=0"
+        );
+    }
+
+    #[test]
+    fn home() {
+        let home = Some(Path::new("/customers/acme"));
+        assert!(check_home(None, "/customers/other").is_ok());
+        assert!(check_home(home, "/customers/acme").is_ok());
+        assert!(check_home(Some(Path::new("customers/acme")), "/customers/acme/file.txt").is_ok());
+        assert!(check_home(home, "/customers").is_err());
+        assert!(check_home(home, "/customers/acme2").is_err());
+        assert!(check_home(home, "/customers/acme/../other/file.txt").is_err());
+        assert!(check_home(home, "/customers/acme/sub/../file.txt").is_err());
+    }
 }
@@ -75,7 +75,11 @@ impl AuthenticationError {
 ///
 /// [Authenticator](crate::auth::Authenticator) implementations can assume that either `certificate_chain` or `password`
 /// will not be `None`.
+///
+/// Fields may be added in new versions. Outside of libunftp, create `Credentials` with
+/// `Credentials::from` and set the fields that you need.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct Credentials {
     /// The password that the client sent.
     pub password: Option<String>,
@@ -83,6 +87,8 @@ pub struct Credentials {
     pub certificate_chain: Option<Vec<ClientCert>>,
     /// The IP address of the user's connection
     pub source_ip: std::net::IpAddr,
+    /// The host name the client asked for with TLS SNI, if it connected with FTPS.
+    pub sni_hostname: Option<String>,
 }
 
 impl From<&str> for Credentials {
@@ -91,6 +97,7 @@ impl From<&str> for Credentials {
             password: Some(String::from(s)),
             certificate_chain: None,
             source_ip: [127, 0, 0, 1].into(),
+            sni_hostname: None,
         }
     }
 }
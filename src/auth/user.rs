@@ -1,4 +1,9 @@
-use std::fmt::{self, Debug, Display, Formatter};
+use crate::options::OverwritePolicy;
+use bitflags::bitflags;
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    path::Path,
+};
 
 /// UserDetail defines the requirements for implementations that hold _Security Subject_
 /// information for use by the server.
@@ -16,6 +21,61 @@ pub trait UserDetail: Send + Sync + Display + Debug {
     fn account_enabled(&self) -> bool {
         true
     }
+
+    /// Returns the home directory of the user as an absolute path in the storage back-end, e.g.
+    /// `/customers/acme`. Sessions start in it and the storage back-ends that come with libunftp
+    /// don't allow the user to access anything outside of it. This default implementation returns
+    /// `None`, giving access to the whole storage back-end.
+    fn home(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Returns the commands this subject is allowed to use. The server replies with 550 to
+    /// commands that aren't allowed. The [access control list](crate::acl) can limit them further
+    /// per path. This default implementation allows everything.
+    fn permissions(&self) -> Permissions {
+        Permissions::all()
+    }
+
+    /// Returns the names of the groups this subject is a member of. Rules of the
+    /// [access control list](crate::acl) can apply to groups. This default implementation returns
+    /// no groups.
+    fn groups(&self) -> &[String] {
+        &[]
+    }
+
+    /// Returns what happens when this subject uploads a file to a path where a file exists already.
+    /// This default implementation returns `None`, leaving it to the policy set with
+    /// [`Server::overwrite_policy`](crate::Server::overwrite_policy).
+    fn overwrite_policy(&self) -> Option<OverwritePolicy> {
+        None
+    }
+}
+
+bitflags! {
+    /// The permissions of a user, returned by [`UserDetail::permissions`].
+    pub struct Permissions: u32 {
+        /// Allows listing directories and getting file information with `LIST`, `NLST`, `MLSD`,
+        /// `MLST`, `STAT`, `SIZE` and `MDTM`.
+        const LIST   = 0b00000001;
+        /// Allows downloading files with `RETR`.
+        const READ   = 0b00000010;
+        /// Allows uploading files with `STOR`, `STOU` and `APPE` and changing their modification
+        /// time with `MFMT`.
+        const WRITE  = 0b00000100;
+        /// Allows deleting files and directories with `DELE` and `RMD`.
+        const DELETE = 0b00001000;
+        /// Allows creating directories with `MKD`.
+        const MKDIR  = 0b00010000;
+        /// Allows renaming files and directories with `RNFR` and `RNTO`.
+        const RENAME = 0b00100000;
+        /// Allows calculating checksums with `MD5`, `SITE MD5` and `HASH`.
+        const MD5    = 0b01000000;
+        /// Read-only access: listing, downloading and calculating checksums.
+        const READ_ONLY = Self::LIST.bits | Self::READ.bits | Self::MD5.bits;
+        /// Upload-only access: uploading files and nothing else.
+        const UPLOAD_ONLY = Self::WRITE.bits;
+    }
 }
 
 /// DefaultUser is a default implementation of the `UserDetail` trait that doesn't hold any user
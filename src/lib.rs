@@ -49,6 +49,8 @@ pub(crate) mod metrics;
 pub(crate) mod server;
 pub mod storage;
 
-pub use crate::server::ftpserver::{error::ServerError, options, Server};
+pub use crate::server::ftpserver::{acl, error::ServerError, middleware, options, site, virtual_host::VirtualHost, Server};
+pub use crate::server::tls::TlsReloader;
+pub use crate::server::transfer::{TransferMonitor, TransferStatus};
 
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -1,6 +1,6 @@
 //! Contains the `add...metric` functions that are used for gathering metrics.
 
-use crate::server::{Command, ControlChanError, ControlChanErrorKind, ControlChanMiddleware, ControlChanMsg, Event, Reply, ReplyCode};
+use crate::server::{Command, ControlChanError, ControlChanErrorKind, ControlChanMsg, Event, EventHandler, Reply, ReplyCode};
 
 use async_trait::async_trait;
 use lazy_static::*;
@@ -9,16 +9,16 @@ use prometheus::{opts, register_int_counter, register_int_counter_vec, register_
 // Control channel middleware that adds metrics
 pub struct MetricsMiddleware<Next>
 where
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     pub collect_metrics: bool,
     pub next: Next,
 }
 
 #[async_trait]
-impl<Next> ControlChanMiddleware for MetricsMiddleware<Next>
+impl<Next> EventHandler for MetricsMiddleware<Next>
 where
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         if self.collect_metrics {
@@ -0,0 +1,158 @@
+//! Contains the line ending conversions done for ASCII (`TYPE A`) transfers.
+//
+// RFC 959 requires the end of a line to be sent as <CRLF> in ASCII mode. We store files with
+// unix line endings so a newline (LF) becomes CRLF when we send a file and CRLF becomes a newline
+// again when we receive one. A lone CR is left as is in both directions.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// The number of bytes we read from the wrapped reader at once.
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// Wraps an [`AsyncWrite`](tokio::io::AsyncWrite) and turns the LF line endings written to it
+/// into CRLF.
+pub(crate) struct AsciiWriter<W> {
+    inner: W,
+    // Converted bytes not yet accepted by the inner writer.
+    pending: Vec<u8>,
+    // The last byte converted. Used to not turn an existing CRLF into CRCRLF.
+    last: u8,
+}
+
+impl<W: AsyncWrite + Unpin> AsciiWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        AsciiWriter {
+            inner,
+            pending: Vec::new(),
+            last: 0,
+        }
+    }
+
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsciiWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_write_pending(cx))?;
+        for &b in buf {
+            if b == b'\n' && this.last != b'\r' {
+                this.pending.push(b'\r');
+            }
+            this.pending.push(b);
+            this.last = b;
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Wraps an [`AsyncRead`](tokio::io::AsyncRead) and turns the CRLF line endings read from it
+/// into LF.
+pub(crate) struct AsciiReader<R> {
+    inner: R,
+    // Converted bytes not yet returned to the caller.
+    converted: Vec<u8>,
+    // Tells if the previous chunk ended with a CR. We only know what to do with it once we see
+    // the next byte.
+    pending_cr: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsciiReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        AsciiReader {
+            inner,
+            converted: Vec::new(),
+            pending_cr: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsciiReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.converted.is_empty() {
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            let mut raw = ReadBuf::new(&mut chunk);
+            futures::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw))?;
+            if raw.filled().is_empty() {
+                if this.pending_cr {
+                    this.pending_cr = false;
+                    this.converted.push(b'\r');
+                    break;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            for &b in raw.filled() {
+                if this.pending_cr && b != b'\n' {
+                    this.converted.push(b'\r');
+                }
+                this.pending_cr = b == b'\r';
+                if !this.pending_cr {
+                    this.converted.push(b);
+                }
+            }
+        }
+        let n = std::cmp::min(buf.remaining(), this.converted.len());
+        buf.put_slice(&this.converted[..n]);
+        this.converted.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn writer_converts_lf_to_crlf() {
+        let mut writer = AsciiWriter::new(Vec::new());
+        writer.write_all(b"one\ntwo\r\nthree\r").await.unwrap();
+        writer.write_all(b"\nfour\n").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(String::from_utf8(writer.inner).unwrap(), "one\r\ntwo\r\nthree\r\nfour\r\n");
+    }
+
+    #[tokio::test]
+    async fn reader_converts_crlf_to_lf() {
+        let input: &[u8] = b"one\r\ntwo\rthree\nfour\r";
+        let mut output = String::new();
+        AsciiReader::new(input).read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "one\ntwo\rthree\nfour\r");
+    }
+
+    #[tokio::test]
+    async fn reader_handles_crlf_split_over_reads() {
+        let input = (&b"one\r"[..]).chain(&b"\ntwo"[..]);
+        let mut output = String::new();
+        AsciiReader::new(input).read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "one\ntwo");
+    }
+}
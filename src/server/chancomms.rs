@@ -1,10 +1,10 @@
 //! Contains code pertaining to the communication between the data and control channels.
 
-use super::session::SharedSession;
+use super::{mlsx::Facts, session::SharedSession};
 use crate::{
     auth::UserDetail,
     server::controlchan::Reply,
-    storage::{Error, StorageBackend},
+    storage::{Error, ListOptions, StorageBackend},
 };
 use futures::channel::mpsc::{Receiver, Sender};
 
@@ -24,10 +24,16 @@ pub enum DataChanCmd {
     Stor {
         /// The path to the file the client would like to store.
         path: String,
+        /// Fails the upload if the file exists already.
+        create_new: bool,
+    },
+    Appe {
+        /// The path to the file the client would like to append to.
+        path: String,
     },
     List {
-        /// Arguments passed along with the list command.
-        options: Option<String>,
+        /// How to filter, sort and recurse as given by the options passed along with the list command.
+        options: ListOptions,
         /// The path of the file/directory the clients wants to list
         path: Option<String>,
     },
@@ -35,6 +41,12 @@ pub enum DataChanCmd {
         /// The path of the file/directory the clients wants to list.
         path: Option<String>,
     },
+    Mlsd {
+        /// The path of the directory the clients wants to list.
+        path: Option<String>,
+        /// The facts to include for each entry.
+        facts: Facts,
+    },
 }
 
 /// Messages that can be sent to the control channel loop.
@@ -57,6 +69,8 @@ pub enum ControlChanMsg {
     },
     /// Data connection was unexpectedly closed
     ConnectionReset,
+    /// The running transfer was cancelled by the `ABOR` command
+    TransferAborted,
     /// Data connection was closed on purpose or not on purpose. We don't know, but that is FTP
     DataConnectionClosedAfterStor,
     /// Failed to write data to disk
@@ -103,6 +117,8 @@ where
 {
     /// Command to assign a data port to a session
     AssignDataPortCommand(SharedSession<Storage, User>),
+    /// Command to assign a data port to a session that asked for it with EPSV
+    AssignExtendedDataPortCommand(SharedSession<Storage, User>),
 }
 
 pub type ProxyLoopSender<Storage, User> = Sender<ProxyLoopMsg<Storage, User>>;
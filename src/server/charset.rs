@@ -0,0 +1,133 @@
+//! Contains the character set handling for clients that switch off UTF-8 with `OPTS UTF8 OFF`.
+//
+// Paths are UTF-8 everywhere inside libunftp. If the server is configured with a legacy charset
+// (e.g. windows-1252 or Shift_JIS) and the client switches UTF-8 off, commands are converted from
+// the legacy charset to UTF-8 as they come in and replies and directory listings are converted
+// back on the way out.
+
+use encoding_rs::Encoding;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+// The label of a legacy charset that encoding_rs doesn't know.
+#[derive(Debug, Clone)]
+pub struct UnknownCharset(String);
+
+impl fmt::Display for UnknownCharset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown charset {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownCharset {}
+
+// Looks up the legacy charset with the given WHATWG label, e.g. `windows-1252` or `Shift_JIS`.
+pub(crate) fn for_label(label: &str) -> Result<&'static Encoding, UnknownCharset> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| UnknownCharset(label.to_string()))
+}
+
+// Converts the text to the legacy charset or returns `None` if the charset can't represent all of
+// it. encoding_rs would otherwise write HTML character references like `&#12354;` for them.
+pub(crate) fn encode(encoding: &'static Encoding, text: &str) -> Option<Vec<u8>> {
+    match encoding.encode(text) {
+        (_, _, true) => None,
+        (bytes, _, false) => Some(bytes.into_owned()),
+    }
+}
+
+// Converts the text to the legacy charset, replacing the characters that it can't represent by `?`.
+pub(crate) fn encode_lossy(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+    if let Some(bytes) = encode(encoding, text) {
+        return bytes;
+    }
+    let mut buf = [0; 4];
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match encode(encoding, c.encode_utf8(&mut buf)) {
+            Some(encoded) => bytes.extend(encoded),
+            None => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+// Converts a directory listing to the legacy charset. Lines with names that the charset can't
+// represent are left out since the client couldn't refer to those files anyway.
+pub(crate) fn encode_listing(encoding: &'static Encoding, listing: &str) -> Vec<u8> {
+    listing
+        .split_terminator('\n')
+        .filter_map(|line| encode(encoding, line))
+        .flat_map(|mut line| {
+            line.push(b'\n');
+            line
+        })
+        .collect()
+}
+
+/// The character set used for the paths exchanged with a client. It is shared between the session
+/// and the control channel codec so that `OPTS UTF8` can switch it for both.
+#[derive(Clone, Debug)]
+pub(crate) struct Charset {
+    // The legacy charset configured on the server, if any.
+    legacy: Option<&'static Encoding>,
+    // False once the client sent `OPTS UTF8 OFF`.
+    utf8: Arc<AtomicBool>,
+}
+
+impl Charset {
+    pub(crate) fn new(legacy: Option<&'static Encoding>) -> Self {
+        Charset {
+            legacy,
+            utf8: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Returns the legacy charset configured on the server, regardless of whether it is in use.
+    pub(crate) fn configured(&self) -> Option<&'static Encoding> {
+        self.legacy
+    }
+
+    /// Returns the legacy charset if the client switched UTF-8 off or `None` if UTF-8 is in use.
+    pub(crate) fn legacy(&self) -> Option<&'static Encoding> {
+        if self.utf8.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.legacy
+        }
+    }
+
+    pub(crate) fn set_utf8(&self, on: bool) {
+        self.utf8.store(on, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn switches_between_utf8_and_legacy() {
+        let charset = Charset::new(Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(charset.legacy(), None);
+        charset.clone().set_utf8(false);
+        assert_eq!(charset.legacy(), Some(encoding_rs::WINDOWS_1252));
+        charset.set_utf8(true);
+        assert_eq!(charset.legacy(), None);
+    }
+
+    #[test]
+    fn leaves_out_unmappable_characters() {
+        let encoding = for_label("windows-1252").unwrap();
+        assert_eq!(encode(encoding, "café"), Some(b"caf\xe9".to_vec()));
+        assert_eq!(encode(encoding, "日本"), None);
+        assert_eq!(encode_lossy(encoding, "257 /café/日本"), b"257 /caf\xe9/??".to_vec());
+        assert_eq!(encode_listing(encoding, "café\r\n日本\r\nnaïve\r\n"), b"caf\xe9\r\nna\xefve\r\n".to_vec());
+        assert!(for_label("klingon").is_err());
+    }
+}
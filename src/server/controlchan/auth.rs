@@ -1,7 +1,7 @@
 use crate::{
     auth::UserDetail,
     server::{
-        controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+        controlchan::{error::ControlChanError, middleware::EventHandler},
         session::SharedSession,
         {Command, Event, Reply, ReplyCode, SessionState},
     },
@@ -16,19 +16,19 @@ where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     pub session: SharedSession<Storage, User>,
     pub next: Next,
 }
 
 #[async_trait]
-impl<Storage, User, Next> ControlChanMiddleware for AuthMiddleware<Storage, User, Next>
+impl<Storage, User, Next> EventHandler for AuthMiddleware<Storage, User, Next>
 where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         match event {
@@ -41,6 +41,7 @@ where
             | Event::Command(Command::Prot { .. })
             | Event::Command(Command::Pbsz { .. })
             | Event::Command(Command::Feat)
+            | Event::Command(Command::Host { .. })
             | Event::Command(Command::Noop)
             | Event::Command(Command::Quit) => self.next.handle(event).await,
             _ => {
@@ -1,9 +1,16 @@
 use super::{command::Command, error::ControlChanError, line_parser, Reply};
+use crate::server::{
+    charset::{self, Charset},
+    ftpserver::middleware::Request,
+};
 
 use bytes::BytesMut;
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
 use tokio_util::codec::{Decoder, Encoder};
 
+// Telnet "Interpret As Command" escape byte.
+const IAC: u8 = 0xFF;
+
 // FTPCodec implements tokio's `Decoder` and `Encoder` traits for the control channel, that we'll
 // use to decode FTP commands and encode their responses.
 pub struct FtpCodec {
@@ -12,26 +19,37 @@ pub struct FtpCodec {
     // is the next index to examine. The next time `decode` is called with `abcde\n`, we will only
     // look at `de\n` before returning.
     next_index: usize,
+    // The charset of the paths in commands and replies. Switched by `OPTS UTF8`.
+    charset: Charset,
 }
 
 impl FtpCodec {
-    pub fn new() -> Self {
-        FtpCodec { next_index: 0 }
+    pub fn new(charset: Charset) -> Self {
+        FtpCodec { next_index: 0, charset }
     }
 }
 
 impl Decoder for FtpCodec {
-    type Item = Command;
+    type Item = (Command, Request);
     type Error = ControlChanError;
 
     // Here we decode the incoming bytes into a meaningful command. We'll split on newlines, and
-    // parse the resulting line using `Command::parse()`. This method will be called by tokio.
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Command>, Self::Error> {
+    // parse the resulting line using `Command::parse()`. The line is also returned as a `Request`
+    // for the user's middleware. This method will be called by tokio.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(Command, Request)>, Self::Error> {
         if let Some(newline_offset) = buf[self.next_index..].iter().position(|b| *b == b'\n') {
             let newline_index = newline_offset + self.next_index;
             let line = buf.split_to(newline_index + 1);
             self.next_index = 0;
-            Ok(Some(line_parser::parse(line)?))
+            let line = strip_telnet_commands(&line);
+            let (command, text) = match self.charset.legacy() {
+                Some(encoding) => {
+                    let text = encoding.decode_without_bom_handling(&line).0.into_owned();
+                    (line_parser::parse(text.clone())?, text)
+                }
+                None => (line_parser::parse(line.to_vec())?, String::from_utf8_lossy(&line).into_owned()),
+            };
+            Ok(Some((command, Request::from_line(&text))))
         } else {
             self.next_index = buf.len();
             Ok(None)
@@ -39,6 +57,41 @@ impl Decoder for FtpCodec {
     }
 }
 
+// Clients send Telnet "Interrupt Process" (IAC IP) and "Synch" (IAC DM, sent as TCP urgent data)
+// before an ABOR. This removes those and any other Telnet commands from the line. The urgent DM
+// byte itself is taken out of the stream by the TCP stack so a lone IAC may be left over. An
+// escaped IAC IAC stands for a literal 0xFF byte.
+fn strip_telnet_commands(line: &[u8]) -> Cow<'_, [u8]> {
+    if !line.contains(&IAC) {
+        return Cow::Borrowed(line);
+    }
+    let mut stripped = Vec::with_capacity(line.len());
+    let mut bytes = line.iter().copied().peekable();
+    while let Some(b) = bytes.next() {
+        if b != IAC {
+            stripped.push(b);
+            continue;
+        }
+        match bytes.peek() {
+            Some(&IAC) => {
+                stripped.push(IAC);
+                bytes.next();
+            }
+            // Telnet commands without options: SE (240) up to and including GA (249)
+            Some(0xF0..=0xF9) => {
+                bytes.next();
+            }
+            // Option negotiation: WILL, WONT, DO and DONT followed by the option code
+            Some(0xFB..=0xFE) => {
+                bytes.next();
+                bytes.next();
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
 impl Encoder<Reply> for FtpCodec {
     type Error = ControlChanError;
 
@@ -72,7 +125,36 @@ impl Encoder<Reply> for FtpCodec {
                 }
             }
         }
-        buf.extend(&buffer);
+        match self.charset.legacy() {
+            Some(encoding) => buf.extend(charset::encode_lossy(encoding, &String::from_utf8_lossy(&buffer))),
+            None => buf.extend(&buffer),
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn strips_telnet_ip_and_synch() {
+        assert_eq!(strip_telnet_commands(b"\xff\xf4\xff\xf2ABOR\r\n").as_ref(), b"ABOR\r\n");
+        // The urgent DM byte was removed by the TCP stack.
+        assert_eq!(strip_telnet_commands(b"\xff\xf4\xffABOR\r\n").as_ref(), b"ABOR\r\n");
+    }
+
+    #[test]
+    fn keeps_escaped_iac() {
+        assert_eq!(strip_telnet_commands(b"STOR a\xff\xffb\r\n").as_ref(), b"STOR a\xffb\r\n");
+        assert_eq!(strip_telnet_commands(b"NOOP\r\n").as_ref(), b"NOOP\r\n");
+    }
+
+    #[test]
+    fn decodes_abor_after_telnet_commands() {
+        let mut codec = FtpCodec::new(Charset::new(None));
+        let mut buf = BytesMut::from(&b"\xff\xf4\xff\xf2ABOR\r\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some((Command::Abor, Request::new("ABOR", ""))));
+    }
+}
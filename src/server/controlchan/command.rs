@@ -1,10 +1,10 @@
 use crate::server::{
-    controlchan::commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam},
+    controlchan::commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam, TypeParam},
     password::Password,
 };
 
 use bytes::Bytes;
-use std::{fmt, path::PathBuf};
+use std::{fmt, net::SocketAddr, path::PathBuf, time::SystemTime};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Command {
@@ -25,7 +25,10 @@ pub enum Command {
         /// The bytes making up the path about which information is requested, if given.
         path: Option<Bytes>,
     },
-    Type,
+    Type {
+        /// The representation type the client would like to switch to.
+        param: TypeParam,
+    },
     Stru {
         /// The structure to which the client would like to switch. Only the `File` structure is
         /// supported by us.
@@ -39,7 +42,23 @@ pub enum Command {
     Help,
     Noop,
     Pasv,
-    Port,
+    Port {
+        /// The address, given as `h1,h2,h3,h4,p1,p2`, the server should connect to for the data
+        /// connection.
+        addr: SocketAddr,
+    },
+    /// Extended Port (EPRT) as specified in RFC 2428. The IPv6 capable version of PORT.
+    Eprt {
+        /// The address the server should connect to for the data connection.
+        addr: SocketAddr,
+    },
+    /// Extended Passive (EPSV) as specified in RFC 2428. The IPv6 capable version of PASV.
+    Epsv {
+        /// Set by `EPSV ALL`: the client promises to only use EPSV for data connections from now on.
+        all: bool,
+        /// The network protocol (1 for IPv4, 2 for IPv6) the client asked for, if any.
+        protocol: Option<u8>,
+    },
     Retr {
         /// The path to the file the client would like to retrieve.
         path: String,
@@ -48,6 +67,10 @@ pub enum Command {
         /// The path to the file the client would like to store.
         path: String,
     },
+    Appe {
+        /// The path to the file the client would like to append to.
+        path: String,
+    },
     List {
         /// Arguments passed along with the list command.
         options: Option<String>,
@@ -58,6 +81,16 @@ pub enum Command {
         /// The path of the file/directory the clients wants to list.
         path: Option<String>,
     },
+    /// Machine readable directory listing as specified in RFC 3659.
+    Mlsd {
+        /// The path of the directory the clients wants to list.
+        path: Option<String>,
+    },
+    /// Machine readable information about a single file as specified in RFC 3659.
+    Mlst {
+        /// The path of the file/directory the clients wants information about.
+        path: Option<String>,
+    },
     Feat,
     Pwd,
     Cwd {
@@ -84,7 +117,7 @@ pub enum Command {
     },
     Allo {
         // The `ALLO` command can actually have an optional argument, but since we regard `ALLO`
-    // as noop, we won't even parse it.
+        // as noop, we won't even parse it.
     },
     Abor,
     Stou,
@@ -115,9 +148,36 @@ pub enum Command {
     Mdtm {
         file: PathBuf,
     },
+    /// Sets the modification time of a file as specified in the MFMT draft (draft-somers-ftp-mfxx).
+    Mfmt {
+        /// The new modification time.
+        modified: SystemTime,
+        /// The file to change.
+        file: PathBuf,
+    },
     Md5 {
         file: PathBuf,
     },
+    /// Returns the hash of a file as specified in the HASH draft (draft-bryan-ftpext-hash).
+    Hash {
+        file: PathBuf,
+    },
+    /// Sets the byte range, both ends inclusive, that the next `HASH` command works on as specified
+    /// in draft-bryan-ftp-range. `RANG 1 0` resets it.
+    Rang {
+        start: u64,
+        end: u64,
+    },
+    /// Selects the virtual host to connect to as specified in RFC 7151.
+    Host {
+        host: String,
+    },
+    /// A `SITE` subcommand that isn't built in. It is handled by the handler registered for it with
+    /// `Server::site_command`, if any.
+    Site {
+        command_name: String,
+        arguments: String,
+    },
     Other {
         command_name: String,
         arguments: String,
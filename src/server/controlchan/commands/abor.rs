@@ -19,7 +19,6 @@ use crate::{
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
-use futures::prelude::*;
 
 #[derive(Debug)]
 pub struct Abor;
@@ -34,16 +33,20 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let logger = args.logger;
+        // The client didn't connect to the data port yet so there is no data loop to stop.
+        if session.data_abort_rx.is_some() {
+            session.data_abort_tx = None;
+            session.data_abort_rx = None;
+            session.data_cmd_rx = None;
+            return Ok(Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel"));
+        }
         match session.data_abort_tx.take() {
-            Some(mut tx) => {
-                tokio::spawn(async move {
-                    if let Err(err) = tx.send(()).await {
-                        slog::warn!(logger, "abort failed: {}", err);
-                    }
-                });
-                Ok(Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel"))
-            }
+            // The data loop closes the data connection and replies: 426 for the transfer it
+            // interrupted, if any, followed by 226 for the ABOR.
+            Some(mut tx) => match tx.try_send(()) {
+                Ok(()) => Ok(Reply::none()),
+                Err(_) => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed")),
+            },
             None => Ok(Reply::new(ReplyCode::ClosingDataConnection, "Data channel already closed")),
         }
     }
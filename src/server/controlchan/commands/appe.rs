@@ -0,0 +1,57 @@
+//! The RFC 959 Append (with create) (`APPE`) command
+//
+// This command causes the server-DTP to accept the data
+// transferred via the data connection and to store the data in
+// a file at the server site.  If the file specified in the
+// pathname exists at the server site, then the data shall be
+// appended to that file; otherwise the file specified in the
+// pathname shall be created at the server site.
+
+use crate::server::chancomms::DataChanCmd;
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        command::Command,
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend, FEATURE_APPEND},
+};
+use async_trait::async_trait;
+use futures::prelude::*;
+
+#[derive(Debug)]
+pub struct Appe;
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Appe
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_APPEND == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+        let mut session = args.session.lock().await;
+        let cmd: DataChanCmd = match args.parsed_command.clone() {
+            Command::Appe { path } => DataChanCmd::Appe { path },
+            _ => panic!("Programmer error, expected command to be APPE"),
+        };
+        let logger = args.logger;
+        match session.data_cmd_tx.take() {
+            Some(mut tx) => {
+                tokio::spawn(async move {
+                    if let Err(err) = tx.send(cmd).await {
+                        slog::warn!(logger, "{}", err);
+                    }
+                });
+                Ok(Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"))
+            }
+            None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        }
+    }
+}
@@ -48,6 +48,9 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut tx = args.tx_control_chan.clone();
         let logger = args.logger;
+        if args.session.lock().await.cmd_tls {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Control channel already secured"));
+        }
         match (args.tls_configured, self.protocol.clone()) {
             (true, AuthParam::Tls) => {
                 tokio::spawn(async move {
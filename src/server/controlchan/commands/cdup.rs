@@ -30,7 +30,14 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        session.cwd.pop();
+        // Like at the root, going up from the user's home directory leaves the user where they are.
+        let at_home = match &*session.user {
+            Some(user) => user.home() == Some(session.cwd.as_path()),
+            None => false,
+        };
+        if !at_home {
+            session.cwd.pop();
+        }
         Ok(Reply::new(ReplyCode::FileActionOkay, "OK"))
     }
 }
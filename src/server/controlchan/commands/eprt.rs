@@ -0,0 +1,53 @@
+//! The RFC 2428 Extended Port (`EPRT`) command
+//
+// The EPRT command allows for the specification of an extended address
+// for the data connection.  The extended address MUST consist of the
+// network protocol as well as the network and transport addresses.  The
+// format of EPRT is:
+//
+// EPRT<space><d><net-prt><d><net-addr><d><tcp-port><d>
+//
+// The EPRT command keyword MUST be followed by a single space (ASCII
+// 32).  Following the space, a delimiter character (<d>) MUST be
+// specified.  The delimiter character MUST be one of the ASCII
+// characters in range 33-126 inclusive.  The character "|" (ASCII 124)
+// is recommended unless it coincides with a character needed to encode
+// the network address.
+
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        commands::Port,
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use std::net::SocketAddr;
+
+#[derive(Debug)]
+pub struct Eprt {
+    addr: SocketAddr,
+}
+
+impl Eprt {
+    pub fn new(addr: SocketAddr) -> Self {
+        Eprt { addr }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Eprt
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        // Apart from the address format EPRT behaves exactly like PORT.
+        Port::new(self.addr).handle(args).await
+    }
+}
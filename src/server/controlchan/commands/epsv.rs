@@ -0,0 +1,143 @@
+//! The RFC 2428 Extended Passive Mode (`EPSV`) command
+//
+// The EPSV command requests that a server listen on a data port and
+// wait for a connection.  The EPSV command takes an optional argument.
+// The response to this command includes only the TCP port number of the
+// listening connection.  The format of the response, however, is similar
+// to the argument of the EPRT command.  This allows the same parsing
+// routines to be used for both commands.  In addition, the format leaves
+// a place holder for the network protocol and/or network address, which
+// may be needed in the EPSV response in the future.  The response code
+// for entering passive mode using an extended address MUST be 229.
+//
+// When the EPSV command is issued with no argument, the server will
+// choose the network protocol for the data connection based on the
+// protocol used for the control connection.
+//
+// When EPSV ALL is issued the server MUST reject all data connection
+// setup commands other than EPSV (i.e., EPRT, PORT, PASV, et al.).
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::{ProxyLoopMsg, ProxyLoopSender},
+        controlchan::{
+            commands::pasv::{setup_inter_loop_comms, try_port_range, unmap_ipv4},
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        datachan::{self, DataSocket},
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::net::IpAddr;
+
+#[derive(Debug)]
+pub struct Epsv {
+    all: bool,
+    protocol: Option<u8>,
+}
+
+impl Epsv {
+    pub fn new(all: bool, protocol: Option<u8>) -> Self {
+        Epsv { all, protocol }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn handle_nonproxy_mode<S, U>(&self, args: CommandContext<S, U>) -> Result<Reply, ControlChanError>
+    where
+        U: UserDetail + 'static,
+        S: StorageBackend<U> + 'static,
+        S::Metadata: Metadata,
+    {
+        let CommandContext {
+            logger,
+            tx_control_chan: tx,
+            session,
+            ..
+        } = args;
+
+        let listener = match try_port_range(args.local_addr, args.passive_ports).await {
+            Err(_) => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+            Ok(l) => l,
+        };
+        let port = listener.local_addr()?.port();
+
+        setup_inter_loop_comms(session.clone(), tx).await;
+        // Open the data connection in a new task and process it.
+        tokio::spawn(async move {
+            if let Ok((socket, _socket_addr)) = listener.accept().await {
+                datachan::spawn_processing(logger, session, DataSocket::Connected(socket)).await;
+            }
+        });
+
+        Ok(make_epsv_reply(port))
+    }
+
+    #[tracing_attributes::instrument]
+    async fn handle_proxy_mode<S, U>(&self, args: CommandContext<S, U>, mut tx: ProxyLoopSender<S, U>) -> Result<Reply, ControlChanError>
+    where
+        U: UserDetail + 'static,
+        S: StorageBackend<U> + 'static,
+        S::Metadata: Metadata,
+    {
+        setup_inter_loop_comms(args.session.clone(), args.tx_control_chan).await;
+        tx.send(ProxyLoopMsg::AssignExtendedDataPortCommand(args.session.clone())).await.unwrap();
+        Ok(Reply::None)
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Epsv
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if !args.active_passive_mode.passive_allowed() {
+            return Ok(Reply::new(
+                ReplyCode::CommandNotImplemented,
+                "PASSIVE mode is not supported - use ACTIVE instead",
+            ));
+        }
+
+        // The data connection goes to the address the client connected to which, behind a proxy,
+        // is not our local address.
+        let conn_ip = match args.session.lock().await.destination {
+            Some(destination) if args.tx_proxyloop.is_some() => destination.ip(),
+            _ => args.local_addr.ip(),
+        };
+        let supported_protocol = match unmap_ipv4(conn_ip) {
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 2,
+        };
+        if let Some(protocol) = self.protocol {
+            if protocol != supported_protocol {
+                return Ok(Reply::new_with_string(
+                    ReplyCode::NetworkProtocolNotSupported,
+                    format!("Network protocol not supported, use ({})", supported_protocol),
+                ));
+            }
+        }
+
+        if self.all {
+            args.session.lock().await.epsv_all = true;
+            return Ok(Reply::new(ReplyCode::CommandOkay, "EPSV ALL command successful"));
+        }
+
+        let sender: Option<ProxyLoopSender<Storage, User>> = args.tx_proxyloop.clone();
+        match sender {
+            Some(tx) => self.handle_proxy_mode(args, tx).await,
+            None => self.handle_nonproxy_mode(args).await,
+        }
+    }
+}
+
+pub fn make_epsv_reply(port: u16) -> Reply {
+    Reply::new_with_string(ReplyCode::EnteringExtendedPassiveMode, format!("Entering Extended Passive Mode (|||{}|)", port))
+}
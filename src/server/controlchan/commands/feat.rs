@@ -10,7 +10,7 @@ use crate::{
         },
         ftpserver::options::SiteMd5,
     },
-    storage::{Metadata, StorageBackend, FEATURE_RESTART, FEATURE_SITEMD5},
+    storage::{HashAlgorithm, Metadata, StorageBackend, FEATURE_HASH, FEATURE_MFMT, FEATURE_RESTART, FEATURE_SITEMD5},
 };
 use async_trait::async_trait;
 
@@ -26,7 +26,17 @@ where
 {
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        let mut feat_text = vec![" SIZE", " MDTM", " UTF8"];
+        let session = args.session.lock().await;
+        let mlst = format!(" MLST {}", session.mlst_facts.to_feat_string(true));
+        // The currently selected algorithm is marked with an asterisk.
+        let hash_algorithms: Vec<String> = HashAlgorithm::ALL
+            .iter()
+            .map(|&a| if a == session.hash_algorithm { format!("{}*", a) } else { a.to_string() })
+            .collect();
+        let hash = format!(" HASH {}", hash_algorithms.join(";"));
+        let site_commands: Vec<String> = args.site_commands.keys().map(|name| format!(" SITE {}", name)).collect();
+        drop(session);
+        let mut feat_text = vec![" SIZE", " MDTM", " MODE Z", " UTF8", &mlst];
         // Add the features. According to the spec each feature line must be
         // indented by a space.
         if args.tls_configured {
@@ -34,15 +44,32 @@ where
             feat_text.push(" PBSZ");
             feat_text.push(" PROT");
         }
+        if args.active_passive_mode.active_allowed() {
+            feat_text.push(" EPRT");
+        }
+        if args.active_passive_mode.passive_allowed() {
+            feat_text.push(" EPSV");
+        }
         if args.storage_features & FEATURE_RESTART > 0 {
             feat_text.push(" REST STREAM");
         }
+        if args.storage_features & FEATURE_MFMT > 0 {
+            feat_text.push(" MFMT");
+        }
+        if args.storage_features & FEATURE_HASH > 0 {
+            feat_text.push(&hash);
+        }
+        if !args.virtual_hosts.is_empty() {
+            feat_text.push(" HOST");
+        }
         if args.sitemd5 != SiteMd5::None && args.storage_features & FEATURE_SITEMD5 > 0 {
             feat_text.push(" SITE MD5");
         }
+        feat_text.extend(site_commands.iter().map(String::as_str));
 
-        // Show them in alphabetical order.
+        // Show them in alphabetical order, each only once.
         feat_text.sort_unstable();
+        feat_text.dedup();
         feat_text.insert(0, "Extensions supported:");
         feat_text.push("END");
 
@@ -0,0 +1,90 @@
+//! The `HASH` command from draft-bryan-ftpext-hash
+//
+// The HASH command asks the server to calculate the hash of a file with the algorithm selected
+// through `OPTS HASH`. If a byte range was set with `RANG` only that part of the file is hashed.
+// On success the server replies with:
+//
+// 213 <algorithm> <start>-<end> <hash> <path>
+//
+// The end is left out for an empty file. A range that starts beyond the end of the file gets a 501
+// reply.
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+    },
+    storage::{Metadata, StorageBackend, FEATURE_HASH},
+};
+use async_trait::async_trait;
+use futures::{channel::mpsc::Sender, prelude::*};
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(Debug)]
+pub struct Hash {
+    path: PathBuf,
+}
+
+impl Hash {
+    pub fn new(path: PathBuf) -> Self {
+        Hash { path }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Hash
+where
+    User: UserDetail,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: 'static + Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_HASH == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+        let mut session = args.session.lock().await;
+        let user = session.user.clone();
+        let storage = Arc::clone(&session.storage);
+        let path = session.cwd.join(self.path.clone());
+        let display_path = self.path.clone();
+        let algorithm = session.hash_algorithm;
+        let range = session.hash_range.take();
+        let mut tx_control_chan: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let logger = args.logger;
+
+        tokio::spawn(async move {
+            let user = (*user).as_ref().unwrap();
+            let result = async {
+                let len = storage.metadata(user, &path).await?.len();
+                let (start, end) = match range {
+                    // The range must start within the file.
+                    Some((start, _)) if start >= len => {
+                        return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Invalid byte range for the file"));
+                    }
+                    Some((start, end)) => (start, std::cmp::min(end, len - 1)),
+                    None => (0, len.saturating_sub(1)),
+                };
+                let hash = storage.checksum(user, &path, algorithm, range.map(|_| (start, end))).await?;
+                // An empty file has no last byte to report.
+                let end = if len == 0 { String::new() } else { end.to_string() };
+                let reply_text = format!("{} {}-{} {} {}", algorithm, start, end, hash, display_path.display());
+                Ok(Reply::new_with_string(ReplyCode::FileStatus, reply_text))
+            }
+            .await;
+            let msg = match result {
+                Ok(reply) => ControlChanMsg::CommandChannelReply(reply),
+                Err(err) => ControlChanMsg::StorageError(err),
+            };
+            if let Err(err) = tx_control_chan.send(msg).await {
+                slog::warn!(logger, "{}", err);
+            }
+        });
+        Ok(Reply::none())
+    }
+}
@@ -27,8 +27,13 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        let text = vec!["Help:", "Powered by libunftp"];
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut text = vec![String::from("Help:"), String::from("Powered by libunftp")];
+        if !args.site_commands.is_empty() {
+            let mut names: Vec<&str> = args.site_commands.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            text.push(format!("SITE commands: {}", names.join(" ")));
+        }
         // TODO: Add useful information here like operating server type and app name.
         Ok(Reply::new_multiline(ReplyCode::HelpMessage, text))
     }
@@ -0,0 +1,70 @@
+//! The RFC 7151 Host (`HOST`) command
+//
+// The HOST command allows a user-PI to specify the pathname of the
+// virtual host to which it wants to connect.  It must be sent before
+// the USER command so that the server-PI can, for example, select the
+// authentication database, the file system and the TLS certificate of
+// that virtual host.
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        ftpserver::options::DEFAULT_GREETING,
+        session::SessionState,
+        tls::FtpsConfig,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Host {
+    host: String,
+}
+
+impl Host {
+    pub fn new(host: String) -> Self {
+        Host { host }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Host
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.virtual_hosts.is_empty() {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "No virtual hosts configured"));
+        }
+        let mut session = args.session.lock().await;
+        if session.state != SessionState::New || session.virtual_host.is_some() {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "HOST may only be sent once, before USER"));
+        }
+        let vhost = match args.virtual_hosts.get(&self.host.to_ascii_lowercase()) {
+            Some(vhost) => vhost,
+            None => return Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Unknown virtual host")),
+        };
+        if let Some(generator) = &vhost.storage {
+            session.storage = Arc::new(generator());
+        }
+        if let Some(authenticator) = &vhost.authenticator {
+            session.authenticator = Some(authenticator.clone());
+        }
+        if let FtpsConfig::On { .. } = vhost.ftps_mode {
+            session.ftps_config = vhost.ftps_mode.clone();
+        }
+        session.virtual_host = Some(vhost.hostname.clone());
+        slog::info!(args.logger, "Client selected virtual host {}", vhost.hostname);
+        Ok(Reply::new(ReplyCode::ServiceReady, vhost.greeting.unwrap_or(DEFAULT_GREETING)))
+    }
+}
@@ -21,7 +21,7 @@ use crate::{
         handler::{CommandContext, CommandHandler},
         Command, Reply, ReplyCode,
     },
-    storage::{Metadata, StorageBackend},
+    storage::{ListOptions, ListSort, Metadata, StorageBackend},
 };
 use async_trait::async_trait;
 use futures::prelude::*;
@@ -40,7 +40,10 @@ where
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
         let cmd: DataChanCmd = match args.parsed_command.clone() {
-            Command::List { path, options } => DataChanCmd::List { path, options },
+            Command::List { path, options } => DataChanCmd::List {
+                path,
+                options: list_options(options.as_deref(), args.list_max_depth, args.list_max_entries, args.list_hide_dotfiles),
+            },
             _ => panic!("Programmer error, expected command to be LIST"),
         };
         let logger = args.logger;
@@ -57,3 +60,26 @@ where
         }
     }
 }
+
+// Translates the ls style flags (e.g. `-laR`) clients send along with LIST. Flags we don't know
+// about, like `-l` which is what we always do anyway, are ignored.
+fn list_options(flags: Option<&str>, max_depth: u32, max_entries: usize, hide_dotfiles: bool) -> ListOptions {
+    let mut options = ListOptions {
+        hide_dotfiles,
+        max_depth,
+        max_entries,
+        ..ListOptions::default()
+    };
+    let flags = flags.unwrap_or_default().chars().filter(|c| *c != '-' && *c != ' ');
+    for flag in flags {
+        match flag {
+            'a' | 'A' => options.hide_dotfiles = false,
+            'R' => options.recursive = true,
+            't' => options.sort = ListSort::Modified,
+            'S' => options.sort = ListSort::Size,
+            'r' => options.reverse = true,
+            _ => {}
+        }
+    }
+    options
+}
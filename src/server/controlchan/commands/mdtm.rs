@@ -15,7 +15,7 @@ use chrono::{offset::Utc, DateTime};
 use futures::{channel::mpsc::Sender, prelude::*};
 use std::{path::PathBuf, sync::Arc};
 
-const RFC3659_TIME: &str = "%Y%m%d%H%M%S";
+pub(super) const RFC3659_TIME: &str = "%Y%m%d%H%M%S";
 
 #[derive(Debug)]
 pub struct Mdtm {
@@ -0,0 +1,80 @@
+//! The Modify Fact: Modification Time (`MFMT`) command from draft-somers-ftp-mfxx
+//
+// The MFMT command is used to modify a file's last modification time. The first argument is the
+// new time in the same format as returned by MDTM, always in UTC. The second argument is the path
+// of the file. On success the server replies with:
+//
+// 213 Modify=YYYYMMDDHHMMSS; path
+
+use super::mdtm::RFC3659_TIME;
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+    },
+    storage::{Metadata, StorageBackend, FEATURE_MFMT},
+};
+use async_trait::async_trait;
+use chrono::{offset::Utc, DateTime};
+use futures::{channel::mpsc::Sender, prelude::*};
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+#[derive(Debug)]
+pub struct Mfmt {
+    modified: SystemTime,
+    path: PathBuf,
+}
+
+impl Mfmt {
+    pub fn new(modified: SystemTime, path: PathBuf) -> Self {
+        Mfmt { modified, path }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Mfmt
+where
+    User: UserDetail,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: 'static + Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_MFMT == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+        let session = args.session.lock().await;
+        let user = session.user.clone();
+        let storage = Arc::clone(&session.storage);
+        let path = session.cwd.join(self.path.clone());
+        let modified = self.modified;
+        let reply_text = format!("Modify={}; {}", DateTime::<Utc>::from(modified).format(RFC3659_TIME), self.path.display());
+        let mut tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let mut tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let logger = args.logger;
+
+        tokio::spawn(async move {
+            match storage.set_modified((*user).as_ref().unwrap(), &path, modified).await {
+                Ok(()) => {
+                    if let Err(err) = tx_success
+                        .send(ControlChanMsg::CommandChannelReply(Reply::new_with_string(ReplyCode::FileStatus, reply_text)))
+                        .await
+                    {
+                        slog::warn!(logger, "{}", err);
+                    }
+                }
+                Err(err) => {
+                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                        slog::warn!(logger, "{}", err);
+                    }
+                }
+            }
+        });
+        Ok(Reply::none())
+    }
+}
@@ -0,0 +1,69 @@
+//! The RFC 3659 Machine List Directory (`MLSD`) command
+//
+// The MLSD command is intended to standardize the file and directory
+// information returned by the server-FTP process.  This command differs
+// from the LIST command in that the format of the replies is strictly
+// defined although extensible.
+//
+// MLSD lists the contents of a directory if a directory is named,
+// otherwise a 501 reply is returned.  If no object is named, the
+// current directory is assumed.  That will cause MLSD to list the
+// contents of the current directory.
+
+use crate::server::chancomms::DataChanCmd;
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        command::Command,
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use futures::prelude::*;
+
+#[derive(Debug)]
+pub struct Mlsd;
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Mlsd
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let mut session = args.session.lock().await;
+        // Unlike LIST, MLSD doesn't list a file.
+        if let Command::Mlsd { path: Some(path) } = &args.parsed_command {
+            let path = session.cwd.join(path);
+            if let Ok(metadata) = session.storage.metadata((*session.user).as_ref().unwrap(), &path).await {
+                if !metadata.is_dir() {
+                    return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "Not a directory"));
+                }
+            }
+        }
+        let cmd: DataChanCmd = match args.parsed_command.clone() {
+            Command::Mlsd { path } => DataChanCmd::Mlsd {
+                path,
+                facts: session.mlst_facts,
+            },
+            _ => panic!("Programmer error, expected command to be MLSD"),
+        };
+        let logger = args.logger;
+        match session.data_cmd_tx.take() {
+            Some(mut tx) => {
+                tokio::spawn(async move {
+                    if let Err(err) = tx.send(cmd).await {
+                        slog::warn!(logger, "could not notify data channel to respond with MLSD. {}", err);
+                    }
+                });
+                Ok(Reply::new(ReplyCode::FileStatusOkay, "Sending directory list"))
+            }
+            None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        }
+    }
+}
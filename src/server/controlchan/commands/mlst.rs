@@ -0,0 +1,88 @@
+//! The RFC 3659 Machine List (`MLST`) command
+//
+// The MLST command is intended to standardize the file and directory
+// information returned by the server-FTP process.  MLST provides data
+// about exactly the object named on its command line, and no others.
+// The information is returned over the control connection. If no
+// object is named, the current directory is assumed.
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        ftpserver::acl,
+        mlsx,
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use futures::{channel::mpsc::Sender, prelude::*};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Mlst {
+    path: Option<String>,
+}
+
+impl Mlst {
+    pub fn new(path: Option<String>) -> Self {
+        Mlst { path }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Mlst
+where
+    User: UserDetail,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: 'static + Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let session = args.session.lock().await;
+        let user = session.user.clone();
+        let storage: Arc<Storage> = Arc::clone(&session.storage);
+        let (path, name) = match &self.path {
+            Some(path) => (session.cwd.join(path), path.clone()),
+            None => (session.cwd.clone(), session.cwd.to_string_lossy().to_string()),
+        };
+        let facts = session.mlst_facts;
+        let acl = session.acl.clone();
+        let username = session.username.clone().unwrap_or_default();
+        let mut tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let mut tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
+        let logger = args.logger;
+
+        tokio::spawn(async move {
+            let user = (*user).as_ref().unwrap();
+            match storage.metadata(user, &path).await {
+                Ok(metadata) => {
+                    let permissions = acl::effective_permissions(acl.as_deref(), user, &username, &path);
+                    // The entry is indented by a single space to set it apart from the reply lines.
+                    let lines = vec![
+                        format!("Listing {}", name),
+                        format!(" {}", mlsx::format_entry(&name, &metadata, permissions, facts)),
+                        String::from("End"),
+                    ];
+                    if let Err(err) = tx_success
+                        .send(ControlChanMsg::CommandChannelReply(Reply::new_multiline(ReplyCode::FileActionOkay, lines)))
+                        .await
+                    {
+                        slog::warn!(logger, "{}", err);
+                    }
+                }
+                Err(err) => {
+                    if let Err(err) = tx_fail.send(ControlChanMsg::StorageError(err)).await {
+                        slog::warn!(logger, "{}", err);
+                    }
+                }
+            }
+        });
+        Ok(Reply::none())
+    }
+}
@@ -3,21 +3,32 @@
 //! - [RFC 959 - FTP](https://tools.ietf.org/html/rfc959)
 //! - [RFC 3659 - Extensions to FTP](https://tools.ietf.org/html/rfc3659)
 //! - [RFC 2228 - FTP Security Extensions](https://tools.ietf.org/html/rfc2228)
+//! - [RFC 2428 - FTP Extensions for IPv6 and NATs](https://tools.ietf.org/html/rfc2428)
+//! - [RFC 7151 - File Transfer Protocol HOST Command for Virtual Hosts](https://tools.ietf.org/html/rfc7151)
+//! - [draft-bryan-ftpext-hash - File Hashes in FTP](https://tools.ietf.org/html/draft-bryan-ftpext-hash-02)
 
 mod abor;
 mod acct;
 mod allo;
+mod appe;
 mod auth;
 mod ccc;
 mod cdup;
 mod cwd;
 mod dele;
+mod eprt;
+mod epsv;
 mod feat;
+mod hash;
 mod help;
+mod host;
 mod list;
 mod md5;
 mod mdtm;
+mod mfmt;
 mod mkd;
+mod mlsd;
+mod mlst;
 mod mode;
 mod nlst;
 mod noop;
@@ -29,11 +40,13 @@ mod port;
 mod prot;
 mod pwd;
 mod quit;
+mod rang;
 mod rest;
 mod retr;
 mod rmd;
 mod rnfr;
 mod rnto;
+mod site;
 mod size;
 mod stat;
 mod stor;
@@ -47,16 +60,24 @@ pub use self::md5::Md5;
 pub use abor::Abor;
 pub use acct::Acct;
 pub use allo::Allo;
+pub use appe::Appe;
 pub use auth::{Auth, AuthParam};
 pub use ccc::Ccc;
 pub use cdup::Cdup;
 pub use cwd::Cwd;
 pub use dele::Dele;
+pub use eprt::Eprt;
+pub use epsv::{make_epsv_reply, Epsv};
 pub use feat::Feat;
+pub use hash::Hash;
 pub use help::Help;
+pub use host::Host;
 pub use list::List;
 pub use mdtm::Mdtm;
+pub use mfmt::Mfmt;
 pub use mkd::Mkd;
+pub use mlsd::Mlsd;
+pub use mlst::Mlst;
 pub use mode::{Mode, ModeParam};
 pub use nlst::Nlst;
 pub use noop::Noop;
@@ -68,16 +89,18 @@ pub use port::Port;
 pub use prot::{Prot, ProtParam};
 pub use pwd::Pwd;
 pub use quit::Quit;
+pub use rang::Rang;
 pub use rest::Rest;
 pub use retr::Retr;
 pub use rmd::Rmd;
 pub use rnfr::Rnfr;
 pub use rnto::Rnto;
+pub use site::Site;
 pub use size::Size;
 pub use stat::Stat;
 pub use stor::Stor;
 pub use stou::Stou;
 pub use stru::{Stru, StruParam};
 pub use syst::Syst;
-pub use type_::Type;
+pub use type_::{Type, TypeParam};
 pub use user::User;
@@ -11,6 +11,13 @@
 // C - Compressed
 //
 // The default transfer mode is Stream.
+//
+// The FTP deflate transmission mode draft (draft-preston-ftpext-deflate) adds:
+//
+// Z - Deflate
+//
+// In this mode the data is compressed with deflate in the zlib format (RFC 1950) and sent as a
+// single stream for the whole transfer.
 
 use crate::{
     auth::UserDetail,
@@ -23,9 +30,9 @@ use crate::{
 };
 use async_trait::async_trait;
 
-/// The parameter that can be given to the `MODE` command. The `MODE` command is obsolete, and we
-/// only support the `Stream` mode. We still have to support the command itself for compatibility
-/// reasons, though.
+/// The parameter that can be given to the `MODE` command. We support the `Stream` mode and the
+/// `Deflate` mode from the FTP deflate transmission mode draft. The `Block` and `Compressed` modes
+/// of RFC 959 are obsolete and not supported.
 #[derive(Debug, PartialEq, Clone)]
 pub enum ModeParam {
     /// Data is sent in a continuous stream of bytes.
@@ -34,6 +41,8 @@ pub enum ModeParam {
     Block,
     /// Some round-about way of sending compressed data.
     Compressed,
+    /// Data is sent as a zlib compressed stream (`MODE Z`).
+    Deflate,
 }
 
 #[derive(Debug)]
@@ -55,12 +64,19 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         match &self.params {
-            ModeParam::Stream => Ok(Reply::new(ReplyCode::CommandOkay, "Using Stream transfer mode")),
+            ModeParam::Stream => {
+                args.session.lock().await.mode_z = false;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Using Stream transfer mode"))
+            }
+            ModeParam::Deflate => {
+                args.session.lock().await.mode_z = true;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Using Deflate transfer mode"))
+            }
             _ => Ok(Reply::new(
                 ReplyCode::CommandNotImplementedForParameter,
-                "Only Stream transfer mode is supported",
+                "Only Stream and Deflate transfer modes are supported",
             )),
         }
     }
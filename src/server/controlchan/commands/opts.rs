@@ -9,12 +9,15 @@
 
 use crate::{
     auth::UserDetail,
-    server::controlchan::{
-        error::ControlChanError,
-        handler::{CommandContext, CommandHandler},
-        Reply, ReplyCode,
+    server::{
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        mlsx::Facts,
     },
-    storage::{Metadata, StorageBackend},
+    storage::{HashAlgorithm, Metadata, StorageBackend},
 };
 use async_trait::async_trait;
 
@@ -24,6 +27,13 @@ use async_trait::async_trait;
 pub enum Opt {
     /// The client wants us to enable UTF-8 encoding for file paths and such.
     Utf8 { on: bool },
+    /// The client selects the facts it wants to see in `MLSD` and `MLST` listings (RFC 3659).
+    Mlst { facts: Facts },
+    /// The client sets the compression level (0-9) to use in `MODE Z`.
+    ModeZ { level: u32 },
+    /// The client selects the algorithm to use in the `HASH` command or, if none is given, asks for
+    /// the one currently selected.
+    Hash { algorithm: Option<String> },
 }
 
 #[derive(Debug)]
@@ -45,10 +55,46 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         match &self.option {
-            Opt::Utf8 { on: true } => Ok(Reply::new(ReplyCode::CommandOkay, "Always in UTF-8 mode.")),
-            Opt::Utf8 { on: false } => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Non UTF-8 mode not supported")),
+            Opt::Utf8 { on } => {
+                let session = args.session.lock().await;
+                match (*on, session.charset.configured()) {
+                    (true, None) => Ok(Reply::new(ReplyCode::CommandOkay, "Always in UTF-8 mode.")),
+                    (false, None) => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Non UTF-8 mode not supported")),
+                    (true, Some(_)) => {
+                        session.charset.set_utf8(true);
+                        Ok(Reply::new(ReplyCode::CommandOkay, "UTF-8 mode on."))
+                    }
+                    (false, Some(encoding)) => {
+                        session.charset.set_utf8(false);
+                        Ok(Reply::new_with_string(
+                            ReplyCode::CommandOkay,
+                            format!("UTF-8 mode off, using {}.", encoding.name()),
+                        ))
+                    }
+                }
+            }
+            Opt::Mlst { facts } => {
+                args.session.lock().await.mlst_facts = *facts;
+                Ok(Reply::new_with_string(
+                    ReplyCode::CommandOkay,
+                    format!("MLST OPTS {}", facts.to_feat_string(false)),
+                ))
+            }
+            Opt::ModeZ { level } if *level > 9 => Ok(Reply::new(ReplyCode::ParameterSyntaxError, "MODE Z LEVEL must be between 0 and 9")),
+            Opt::ModeZ { level } => {
+                args.session.lock().await.mode_z_level = *level;
+                Ok(Reply::new_with_string(ReplyCode::CommandOkay, format!("MODE Z LEVEL set to {}", level)))
+            }
+            Opt::Hash { algorithm: None } => Ok(Reply::new(ReplyCode::CommandOkay, args.session.lock().await.hash_algorithm.name())),
+            Opt::Hash { algorithm: Some(name) } => match HashAlgorithm::from_name(name) {
+                Some(algorithm) => {
+                    args.session.lock().await.hash_algorithm = algorithm;
+                    Ok(Reply::new(ReplyCode::CommandOkay, algorithm.name()))
+                }
+                None => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Unknown algorithm")),
+            },
         }
     }
 }
@@ -63,7 +63,8 @@ where
                 };
                 let mut tx: Sender<ControlChanMsg> = args.tx_control_chan.clone();
 
-                let auther = args.authenticator.clone();
+                // A virtual host selected with HOST may have its own authenticator.
+                let auther = session.authenticator.clone().unwrap_or(args.authenticator);
 
                 // without this, the REST authenticator hangs when
                 // performing a http call through Hyper
@@ -72,6 +73,7 @@ where
                     password: Some(pass),
                     source_ip: session.source.ip(),
                     certificate_chain: session.cert_chain.clone(),
+                    sni_hostname: session.sni_hostname.clone(),
                 };
                 tokio::spawn(async move {
                     let msg = match auther.authenticate(&user, &creds).await {
@@ -79,6 +81,9 @@ where
                             if user.account_enabled() {
                                 let mut session = session2clone.lock().await;
                                 slog::info!(logger, "User {} logged in", user);
+                                if let Some(home) = user.home() {
+                                    session.cwd = home.to_path_buf();
+                                }
                                 session.user = Arc::new(Some(user));
                                 ControlChanMsg::AuthSuccess
                             } else {
@@ -15,10 +15,10 @@ use crate::{
             handler::{CommandContext, CommandHandler},
             Reply, ReplyCode,
         },
-        datachan,
+        datachan::{self, DataSocket},
         ftpserver::options::PassiveHost,
         session::SharedSession,
-        ControlChanErrorKind, ControlChanMsg,
+        ControlChanMsg,
     },
     storage::{Metadata, StorageBackend},
 };
@@ -27,8 +27,11 @@ use futures::{
     channel::mpsc::{channel, Receiver, Sender},
     prelude::*,
 };
-use std::net::Ipv4Addr;
-use std::{io, net::SocketAddr, ops::Range};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::Range,
+};
 use tokio::net::TcpListener;
 
 const BIND_RETRIES: u8 = 10;
@@ -41,49 +44,6 @@ impl Pasv {
         Pasv {}
     }
 
-    #[tracing_attributes::instrument]
-    async fn try_port_range(local_addr: SocketAddr, passive_ports: Range<u16>) -> io::Result<TcpListener> {
-        let rng_length = passive_ports.end - passive_ports.start + 1;
-
-        let mut listener: io::Result<TcpListener> = Err(io::Error::new(io::ErrorKind::InvalidInput, "Bind retries cannot be 0"));
-
-        for _ in 1..BIND_RETRIES {
-            let random_u32 = {
-                let mut data = [0; 4];
-                getrandom::getrandom(&mut data).expect("Error generating random port");
-                u32::from_ne_bytes(data)
-            };
-
-            let port = random_u32 % rng_length as u32 + passive_ports.start as u32;
-            listener = TcpListener::bind(std::net::SocketAddr::new(local_addr.ip(), port as u16)).await;
-            if listener.is_ok() {
-                break;
-            }
-        }
-
-        listener
-    }
-
-    // modifies the session by adding channels that are used to communicate with the data connection
-    // processing loop.
-    #[tracing_attributes::instrument]
-    async fn setup_inter_loop_comms<S, U>(&self, session: SharedSession<S, U>, control_loop_tx: Sender<ControlChanMsg>)
-    where
-        U: UserDetail + 'static,
-        S: StorageBackend<U> + 'static,
-        S::Metadata: Metadata,
-    {
-        let (cmd_tx, cmd_rx): (Sender<DataChanCmd>, Receiver<DataChanCmd>) = channel(1);
-        let (data_abort_tx, data_abort_rx): (Sender<()>, Receiver<()>) = channel(1);
-
-        let mut session = session.lock().await;
-        session.data_cmd_tx = Some(cmd_tx);
-        session.data_cmd_rx = Some(cmd_rx);
-        session.data_abort_tx = Some(data_abort_tx);
-        session.data_abort_rx = Some(data_abort_rx);
-        session.control_msg_tx = Some(control_loop_tx);
-    }
-
     // For non-proxy mode we choose a data port here and start listening on it while letting the control
     // channel know (via method return) what the address is that the client should connect to.
     #[tracing_attributes::instrument]
@@ -101,16 +61,7 @@ impl Pasv {
             ..
         } = args;
 
-        // obtain the ip address the client is connected to
-        let conn_addr = match args.local_addr {
-            std::net::SocketAddr::V4(addr) => addr,
-            std::net::SocketAddr::V6(_) => {
-                slog::error!(logger, "local address is ipv6! we only listen on ipv4, so this shouldn't happen");
-                return Err(ControlChanErrorKind::InternalServerError.into());
-            }
-        };
-
-        let listener = Pasv::try_port_range(args.local_addr, args.passive_ports).await;
+        let listener = try_port_range(args.local_addr, args.passive_ports).await;
 
         let listener = match listener {
             Err(_) => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
@@ -119,18 +70,18 @@ impl Pasv {
 
         let port = listener.local_addr()?.port();
 
-        let reply = make_pasv_reply(passive_host, conn_addr.ip(), port).await;
+        let reply = make_pasv_reply(passive_host, args.local_addr.ip(), port).await;
         if let Reply::CodeAndMsg {
             code: ReplyCode::EnteringPassiveMode,
             ..
         } = reply
         {
-            self.setup_inter_loop_comms(session.clone(), tx).await;
+            setup_inter_loop_comms(session.clone(), tx).await;
             // Open the data connection in a new task and process it.
             // We cannot await this since we first need to let the client know where to connect :-)
             tokio::spawn(async move {
                 if let Ok((socket, _socket_addr)) = listener.accept().await {
-                    datachan::spawn_processing(logger, session, socket).await;
+                    datachan::spawn_processing(logger, session, DataSocket::Connected(socket)).await;
                 }
             });
         }
@@ -147,7 +98,7 @@ impl Pasv {
         S: StorageBackend<U> + 'static,
         S::Metadata: Metadata,
     {
-        self.setup_inter_loop_comms(args.session.clone(), args.tx_control_chan).await;
+        setup_inter_loop_comms(args.session.clone(), args.tx_control_chan).await;
         tx.send(ProxyLoopMsg::AssignDataPortCommand(args.session.clone())).await.unwrap();
         Ok(Reply::None)
     }
@@ -162,6 +113,15 @@ where
 {
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if !args.active_passive_mode.passive_allowed() {
+            return Ok(Reply::new(
+                ReplyCode::CommandNotImplemented,
+                "PASSIVE mode is not supported - use ACTIVE instead",
+            ));
+        }
+        if args.session.lock().await.epsv_all {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Only EPSV is allowed after EPSV ALL"));
+        }
         let sender: Option<ProxyLoopSender<Storage, User>> = args.tx_proxyloop.clone();
         match sender {
             Some(tx) => self.handle_proxy_mode(args, tx).await,
@@ -170,28 +130,124 @@ where
     }
 }
 
-pub async fn make_pasv_reply(passive_host: PassiveHost, conn_ip: &Ipv4Addr, port: u16) -> Reply {
+#[tracing_attributes::instrument]
+pub(super) async fn try_port_range(local_addr: SocketAddr, passive_ports: Range<u16>) -> io::Result<TcpListener> {
+    let rng_length = passive_ports.end - passive_ports.start + 1;
+
+    let mut listener: io::Result<TcpListener> = Err(io::Error::new(io::ErrorKind::InvalidInput, "Bind retries cannot be 0"));
+
+    for _ in 1..BIND_RETRIES {
+        let random_u32 = {
+            let mut data = [0; 4];
+            getrandom::getrandom(&mut data).expect("Error generating random port");
+            u32::from_ne_bytes(data)
+        };
+
+        let port = random_u32 % rng_length as u32 + passive_ports.start as u32;
+        listener = TcpListener::bind(std::net::SocketAddr::new(local_addr.ip(), port as u16)).await;
+        if listener.is_ok() {
+            break;
+        }
+    }
+
+    listener
+}
+
+// modifies the session by adding channels that are used to communicate with the data connection
+// processing loop.
+#[tracing_attributes::instrument]
+pub(super) async fn setup_inter_loop_comms<S, U>(session: SharedSession<S, U>, control_loop_tx: Sender<ControlChanMsg>)
+where
+    U: UserDetail + 'static,
+    S: StorageBackend<U> + 'static,
+    S::Metadata: Metadata,
+{
+    let (cmd_tx, cmd_rx): (Sender<DataChanCmd>, Receiver<DataChanCmd>) = channel(1);
+    let (data_abort_tx, data_abort_rx): (Sender<()>, Receiver<()>) = channel(1);
+
+    let mut session = session.lock().await;
+    session.data_cmd_tx = Some(cmd_tx);
+    session.data_cmd_rx = Some(cmd_rx);
+    session.data_abort_tx = Some(data_abort_tx);
+    session.data_abort_rx = Some(data_abort_rx);
+    session.control_msg_tx = Some(control_loop_tx);
+}
+
+pub async fn make_pasv_reply(passive_host: PassiveHost, conn_ip: IpAddr, port: u16) -> Reply {
     let p1 = port >> 8;
     let p2 = port - (p1 * 256);
-    let octets = match passive_host {
-        PassiveHost::Ip(ip) => ip.octets(),
-        PassiveHost::FromConnection => conn_ip.octets(),
-        PassiveHost::Dns(ref dns_name) => {
-            let x = dns_name.split(':').take(1).map(|s| format!("{}:2121", s)).next().unwrap();
-            match tokio::net::lookup_host(x).await {
-                Err(_) => return Reply::new_with_string(ReplyCode::CantOpenDataConnection, format!("Could not resolve DNS address '{}'", dns_name)),
-                Ok(mut ip_iter) => loop {
-                    match ip_iter.next() {
-                        None => return Reply::new_with_string(ReplyCode::CantOpenDataConnection, format!("Could not resolve DNS address '{}'", dns_name)),
-                        Some(SocketAddr::V4(ip)) => break ip.ip().octets(),
-                        Some(SocketAddr::V6(_)) => continue,
-                    }
-                },
-            }
-        }
+    let ip = match passive_host {
+        PassiveHost::Ip(ip) => IpAddr::V4(ip),
+        PassiveHost::Ipv6(ip) => unmap_ipv4(IpAddr::V6(ip)),
+        PassiveHost::FromConnection => unmap_ipv4(conn_ip),
+        PassiveHost::Dns(ref dns_name) => match resolve_passive_host(dns_name, conn_ip).await {
+            Some(ip) => ip,
+            None => return Reply::new_with_string(ReplyCode::CantOpenDataConnection, format!("Could not resolve DNS address '{}'", dns_name)),
+        },
+    };
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets(),
+        // The PASV reply has no way to express an IPv6 address (RFC 2428).
+        IpAddr::V6(_) => return Reply::new(ReplyCode::CantOpenDataConnection, "Can't do PASV over IPv6, use EPSV instead"),
     };
     Reply::new_with_string(
         ReplyCode::EnteringPassiveMode,
         format!("Entering Passive Mode ({},{},{},{},{},{})", octets[0], octets[1], octets[2], octets[3], p1, p2),
     )
 }
+
+// Resolves the DNS name of the passive host. Of the addresses it resolves to we take one of the same
+// family as the control connection, so that clients on IPv6 get an IPv6 address, or else the first.
+async fn resolve_passive_host(dns_name: &str, conn_ip: IpAddr) -> Option<IpAddr> {
+    let host = dns_name.split(':').next().unwrap_or_default();
+    let addrs: Vec<IpAddr> = tokio::net::lookup_host((host, 0)).await.ok()?.map(|addr| unmap_ipv4(addr.ip())).collect();
+    let ipv6 = unmap_ipv4(conn_ip).is_ipv6();
+    addrs.iter().find(|ip| ip.is_ipv6() == ipv6).or_else(|| addrs.first()).copied()
+}
+
+// IPv4 clients connecting to a dual-stack (IPv6) socket show up with IPv4-mapped IPv6 addresses
+// i.e. ::ffff:a.b.c.d. This turns those back into plain IPv4 addresses.
+pub(crate) fn unmap_ipv4(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => IpAddr::V4(Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8)),
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::Ipv6Addr;
+
+    #[tokio::test]
+    async fn pasv_reply() {
+        let conn_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(
+            make_pasv_reply(PassiveHost::from("127.0.0.1"), conn_ip, 2121).await,
+            Reply::new(ReplyCode::EnteringPassiveMode, "Entering Passive Mode (127,0,0,1,8,73)")
+        );
+        assert_eq!(
+            make_pasv_reply(PassiveHost::FromConnection, conn_ip, 2121).await,
+            Reply::new(ReplyCode::EnteringPassiveMode, "Entering Passive Mode (10,0,0,1,8,73)")
+        );
+        assert_eq!(
+            make_pasv_reply(PassiveHost::from("::1"), conn_ip, 2121).await,
+            Reply::new(ReplyCode::CantOpenDataConnection, "Can't do PASV over IPv6, use EPSV instead")
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_passive_host() {
+        assert_eq!(PassiveHost::from("::1"), PassiveHost::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            resolve_passive_host("localhost", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))).await,
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        // Without an IPv6 address for the name we fall back to the IPv4 one.
+        assert!(resolve_passive_host("localhost", IpAddr::V6(Ipv6Addr::LOCALHOST)).await.is_some());
+    }
+}
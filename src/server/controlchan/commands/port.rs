@@ -18,17 +18,30 @@
 
 use crate::{
     auth::UserDetail,
-    server::controlchan::{
-        error::ControlChanError,
-        handler::{CommandContext, CommandHandler},
-        Reply, ReplyCode,
+    server::{
+        controlchan::{
+            commands::pasv::{setup_inter_loop_comms, unmap_ipv4},
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        datachan::{self, DataSocket},
     },
     storage::{Metadata, StorageBackend},
 };
 use async_trait::async_trait;
+use std::net::SocketAddr;
 
 #[derive(Debug)]
-pub struct Port;
+pub struct Port {
+    addr: SocketAddr,
+}
+
+impl Port {
+    pub fn new(addr: SocketAddr) -> Self {
+        Port { addr }
+    }
+}
 
 #[async_trait]
 impl<Storage, User> CommandHandler<Storage, User> for Port
@@ -38,10 +51,41 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(
-            ReplyCode::CommandNotImplemented,
-            "ACTIVE mode is not supported - use PASSIVE instead",
-        ))
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if !args.active_passive_mode.active_allowed() {
+            return Ok(Reply::new(
+                ReplyCode::CommandNotImplemented,
+                "ACTIVE mode is not supported - use PASSIVE instead",
+            ));
+        }
+        if args.tx_proxyloop.is_some() {
+            return Ok(Reply::new(
+                ReplyCode::CommandNotImplemented,
+                "ACTIVE mode is not supported in proxy protocol mode - use PASSIVE instead",
+            ));
+        }
+
+        let (source_ip, epsv_all) = {
+            let session = args.session.lock().await;
+            (session.source.ip(), session.epsv_all)
+        };
+        if epsv_all {
+            return Ok(Reply::new(ReplyCode::BadCommandSequence, "Only EPSV is allowed after EPSV ALL"));
+        }
+
+        // Guard against FTP bounce attacks as recommended by RFC 2577: Don't connect to hosts other
+        // than the client unless FXP was explicitly allowed, and never to privileged ports.
+        if !args.allow_fxp && unmap_ipv4(self.addr.ip()) != unmap_ipv4(source_ip) {
+            slog::warn!(args.logger, "Refusing active mode connection to {} for client {}", self.addr, source_ip);
+            return Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Data connection address not allowed"));
+        }
+        if self.addr.port() < 1024 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "Data connection port not allowed"));
+        }
+
+        setup_inter_loop_comms(args.session.clone(), args.tx_control_chan.clone()).await;
+        datachan::spawn_processing(args.logger, args.session, DataSocket::Pending(self.addr)).await;
+
+        Ok(Reply::new(ReplyCode::CommandOkay, "PORT command successful"))
     }
 }
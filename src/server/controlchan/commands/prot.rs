@@ -47,6 +47,11 @@ where
         match (args.tls_configured, self.param.clone()) {
             (true, ProtParam::Clear) => {
                 let mut session = args.session.lock().await;
+                // With implicit FTPS everything on the connection is encrypted, so a clear data
+                // channel is refused.
+                if session.ftps_implicit {
+                    return Ok(Reply::new(ReplyCode::FtpsRequired, "PROT C not allowed with implicit FTPS"));
+                }
                 session.data_tls = false;
                 Ok(Reply::new(ReplyCode::CommandOkay, "PROT OK. Switching data channel to plaintext"))
             }
@@ -0,0 +1,56 @@
+//! The Range (`RANG`) command from draft-bryan-ftp-range
+//
+// The RANG command sets the byte range, given as the inclusive start and end position, that the
+// next command works on. `RANG 1 0` resets the range. We currently only honour it for the `HASH`
+// command and reset it after each `HASH`.
+
+use crate::{
+    auth::UserDetail,
+    server::controlchan::{
+        error::ControlChanError,
+        handler::{CommandContext, CommandHandler},
+        Reply, ReplyCode,
+    },
+    storage::{Metadata, StorageBackend, FEATURE_HASH},
+};
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct Rang {
+    start: u64,
+    end: u64,
+}
+
+impl Rang {
+    pub fn new(start: u64, end: u64) -> Self {
+        Rang { start, end }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Rang
+where
+    User: UserDetail,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: 'static + Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        if args.storage_features & FEATURE_HASH == 0 {
+            return Ok(Reply::new(ReplyCode::CommandNotImplemented, "Not supported by the selected storage back-end."));
+        }
+        let mut session = args.session.lock().await;
+        if self.start == 1 && self.end == 0 {
+            session.hash_range = None;
+            return Ok(Reply::new(ReplyCode::FileActionPending, "Byte range reset."));
+        }
+        if self.start > self.end {
+            return Ok(Reply::new(ReplyCode::ParameterSyntaxError, "The start of the range may not be after its end."));
+        }
+        session.hash_range = Some((self.start, self.end));
+        Ok(Reply::new_with_string(
+            ReplyCode::FileActionPending,
+            format!("Restarting at {}. End byte range at {}.", self.start, self.end),
+        ))
+    }
+}
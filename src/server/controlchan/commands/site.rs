@@ -0,0 +1,83 @@
+//! The custom `SITE` subcommands registered with `Server::site_command`
+//
+// RFC 959 leaves the SITE command to the server to provide services specific to its system. The
+// built-in SITE MD5 has its own command. Other subcommands are passed to the handler the library
+// user registered for them.
+
+use crate::{
+    auth::UserDetail,
+    server::{
+        chancomms::ControlChanMsg,
+        controlchan::{
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        ftpserver::site::{SiteContext, SiteError},
+    },
+    storage::{Metadata, StorageBackend},
+};
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Site {
+    command_name: String,
+    arguments: String,
+}
+
+impl Site {
+    pub fn new(command_name: String, arguments: String) -> Self {
+        Site { command_name, arguments }
+    }
+}
+
+#[async_trait]
+impl<Storage, User> CommandHandler<Storage, User> for Site
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    #[tracing_attributes::instrument]
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        let handler = match args.site_commands.get(&self.command_name) {
+            Some(handler) => Arc::clone(handler),
+            None => return Ok(Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented")),
+        };
+        let session = args.session.lock().await;
+        let context = SiteContext::new(
+            session.user.clone(),
+            session.username.clone().unwrap_or_default(),
+            session.cwd.clone(),
+            Arc::clone(&session.storage),
+            session.acl.clone(),
+        );
+        drop(session);
+        let arguments = self.arguments.clone();
+        let mut tx = args.tx_control_chan.clone();
+        let logger = args.logger;
+
+        tokio::spawn(async move {
+            let msg = match handler.handle(context, &arguments).await {
+                Ok(text) => {
+                    let lines: Vec<&str> = text.lines().collect();
+                    let reply = if lines.len() > 1 {
+                        Reply::new_multiline(ReplyCode::CommandOkay, lines)
+                    } else {
+                        Reply::new_with_string(ReplyCode::CommandOkay, text)
+                    };
+                    ControlChanMsg::CommandChannelReply(reply)
+                }
+                Err(SiteError::InvalidArguments(msg)) => ControlChanMsg::CommandChannelReply(Reply::new_with_string(ReplyCode::ParameterSyntaxError, msg)),
+                Err(SiteError::Failed(msg)) => ControlChanMsg::CommandChannelReply(Reply::new_with_string(ReplyCode::FileError, msg)),
+                Err(SiteError::Storage(err)) => ControlChanMsg::StorageError(err),
+            };
+            if let Err(err) = tx.send(msg).await {
+                slog::warn!(logger, "{}", err);
+            }
+        });
+        Ok(Reply::none())
+    }
+}
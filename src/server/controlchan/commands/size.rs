@@ -3,6 +3,7 @@ use crate::{
     server::{
         chancomms::ControlChanMsg,
         controlchan::{
+            commands::TypeParam,
             error::ControlChanError,
             handler::{CommandContext, CommandHandler},
         },
@@ -35,6 +36,11 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let session = args.session.lock().await;
+        // RFC 3659 lets us refuse SIZE when the transfer size depends on the line ending
+        // conversion done in ASCII mode.
+        if session.data_type == TypeParam::Ascii {
+            return Ok(Reply::new(ReplyCode::FileError, "SIZE not allowed in ASCII mode"));
+        }
         let user = session.user.clone();
         let storage: Arc<Storage> = Arc::clone(&session.storage);
         let path = session.cwd.join(self.path.clone());
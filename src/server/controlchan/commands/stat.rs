@@ -16,6 +16,8 @@
 // status information about the server FTP process.  This
 // should include current values of all transfer parameters and
 // the status of connections.
+//
+// While a file transfer is running we reply with its progress instead.
 
 use crate::{
     auth::UserDetail,
@@ -26,11 +28,13 @@ use crate::{
             handler::{CommandContext, CommandHandler},
             Reply, ReplyCode,
         },
+        ftpserver::acl,
     },
-    storage::{Error, ErrorKind, Metadata, StorageBackend},
+    storage::{Error, ErrorKind, ListOptions, Metadata, StorageBackend},
 };
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::{offset::Utc, DateTime};
 use futures::{channel::mpsc::Sender, prelude::*};
 use std::{io::Read, sync::Arc};
 
@@ -48,7 +52,7 @@ impl Stat {
 #[async_trait]
 impl<Storage, User> CommandHandler<Storage, User> for Stat
 where
-    User: UserDetail,
+    User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: 'static + Metadata,
 {
@@ -57,11 +61,23 @@ where
         match self.path.clone() {
             None => {
                 let session = args.session.lock().await;
+                if let Some(transfer) = &session.transfer {
+                    let status = transfer.status();
+                    let text: Vec<String> = vec![
+                        "transfer status:".to_string(),
+                        format!("command: {}", status.command),
+                        format!("path: {}", status.path.to_string_lossy()),
+                        format!("bytes transferred: {}", status.bytes),
+                        format!("started: {}", DateTime::<Utc>::from(status.started).format("%Y-%m-%d %H:%M:%S UTC")),
+                        format!("throughput: {:.0} bytes/s", status.throughput()),
+                    ];
+                    return Ok(Reply::new_multiline(ReplyCode::FileStatus, text));
+                }
                 let text: Vec<String> = vec![
                     "server status:".to_string(),
                     format!("powered by libunftp: {}", env!("CARGO_PKG_VERSION")),
                     format!("sbe: {}", session.storage.name()),
-                    format!("authenticator: {}", args.authenticator.name()),
+                    format!("authenticator: {}", session.authenticator.as_ref().unwrap_or(&args.authenticator).name()),
                     format!("user: {}", session.username.as_ref().unwrap()),
                     format!("client addr: {}", session.source),
                     format!("ftps configured: {}", args.tls_configured),
@@ -80,13 +96,22 @@ where
                 let session = args.session.lock().await;
                 let user = session.user.clone();
                 let storage = Arc::clone(&session.storage);
+                let path = session.cwd.join(path);
+                // Like LIST, leave out the entries that the access control list hides from the user.
+                let options = ListOptions {
+                    filter: session
+                        .acl
+                        .clone()
+                        .map(|acl| acl::list_filter(acl, session.user.clone(), session.username.clone().unwrap_or_default())),
+                    ..ListOptions::default()
+                };
 
                 let mut tx_success: Sender<ControlChanMsg> = args.tx_control_chan.clone();
                 let mut tx_fail: Sender<ControlChanMsg> = args.tx_control_chan.clone();
                 let logger = args.logger;
 
                 tokio::spawn(async move {
-                    match storage.list_fmt((*user).as_ref().unwrap(), path).await {
+                    match storage.list_fmt_with_options((*user).as_ref().unwrap(), path, &options).await {
                         Ok(mut cursor) => {
                             let mut result: String = String::new();
                             match cursor.read_to_string(&mut result) {
@@ -8,19 +8,28 @@
 // created at the server site if the file specified in the
 // pathname does not already exist.
 
-use crate::server::chancomms::DataChanCmd;
+use crate::server::chancomms::{ControlChanMsg, DataChanCmd};
 use crate::{
     auth::UserDetail,
-    server::controlchan::{
-        command::Command,
-        error::ControlChanError,
-        handler::{CommandContext, CommandHandler},
-        Reply, ReplyCode,
+    server::{
+        controlchan::{
+            command::Command,
+            commands::stou::unique_file_name,
+            error::ControlChanError,
+            handler::{CommandContext, CommandHandler},
+            Reply, ReplyCode,
+        },
+        ftpserver::options::OverwritePolicy,
     },
-    storage::{Metadata, StorageBackend},
+    storage::{self, ErrorKind, Metadata, StorageBackend, FEATURE_RENAME},
 };
 use async_trait::async_trait;
 use futures::prelude::*;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(Debug)]
 pub struct Stor;
@@ -35,21 +44,143 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let cmd: DataChanCmd = match args.parsed_command.clone() {
-            Command::Stor { path } => DataChanCmd::Stor { path },
+        let path = match args.parsed_command.clone() {
+            Command::Stor { path } => path,
             _ => panic!("Programmer error, expected command to be STOR"),
         };
         let logger = args.logger;
-        match session.data_cmd_tx.take() {
-            Some(mut tx) => {
-                tokio::spawn(async move {
-                    if let Err(err) = tx.send(cmd).await {
+        let mut tx = match session.data_cmd_tx.take() {
+            Some(tx) => tx,
+            None => return Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
+        };
+        let full_path = session.cwd.join(&path);
+        // Dropboxes in the access control list come first, then the policy of the user.
+        let dropbox = match (&*session.user, &session.acl) {
+            (Some(user), Some(acl)) => acl.dropbox_collision(session.username.as_deref().unwrap_or_default(), user.groups(), &full_path),
+            _ => None,
+        };
+        let user_policy = (*session.user).as_ref().and_then(|user| user.overwrite_policy());
+        let policy = match dropbox.or(user_policy).unwrap_or(session.overwrite_policy) {
+            // The server's policy was checked by `Server::listen` but a user's may still need renames
+            // that the storage back-end can't do. Never overwrite the file then.
+            OverwritePolicy::Version if args.storage_features & FEATURE_RENAME == 0 => OverwritePolicy::Refuse,
+            policy => policy,
+        };
+        if policy == OverwritePolicy::Overwrite {
+            tokio::spawn(async move {
+                if let Err(err) = tx.send(DataChanCmd::Stor { path, create_new: false }).await {
+                    slog::warn!(logger, "{}", err);
+                }
+            });
+            return Ok(Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"));
+        }
+
+        // We ask the storage back-end if the file exists without holding on to the session. The
+        // storage back-end checks again when it creates the file.
+        let user = session.user.clone();
+        let storage: Arc<Storage> = Arc::clone(&session.storage);
+        let resume = session.start_pos > 0;
+        let shared_session = args.session.clone();
+        let mut tx_control_chan = args.tx_control_chan.clone();
+        tokio::spawn(async move {
+            let user = (*user).as_ref().unwrap();
+            let result = match (exists(&*storage, user, &full_path).await, policy) {
+                (Err(err), _) => Err(err),
+                (Ok(false), _) => Ok((Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"), Some(path))),
+                // Resuming an upload would change the existing file.
+                (Ok(true), _) if resume => Ok((Reply::new(ReplyCode::BadFileName, "File already exists"), None)),
+                (Ok(true), OverwritePolicy::UniqueName) => {
+                    let name = unique_file_name();
+                    let path = full_path.with_file_name(&name).to_string_lossy().to_string();
+                    Ok((Reply::new_with_string(ReplyCode::FileStatusOkay, format!("FILE: {}", name)), Some(path)))
+                }
+                (Ok(true), OverwritePolicy::Version) => match keep_version(&*storage, user, &full_path).await {
+                    Ok(()) => Ok((Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"), Some(path))),
+                    Err(err) => Err(err),
+                },
+                (Ok(true), _) => Ok((Reply::new(ReplyCode::BadFileName, "File already exists"), None)),
+            };
+            let (msg, path) = match result {
+                Ok((reply, path)) => (ControlChanMsg::CommandChannelReply(reply), path),
+                Err(err) => (ControlChanMsg::StorageError(err), None),
+            };
+            if let Err(err) = tx_control_chan.send(msg).await {
+                slog::warn!(logger, "{}", err);
+            }
+            match path {
+                Some(path) => {
+                    if let Err(err) = tx.send(DataChanCmd::Stor { path, create_new: true }).await {
                         slog::warn!(logger, "{}", err);
                     }
-                });
-                Ok(Reply::new(ReplyCode::FileStatusOkay, "Ready to receive data"))
+                }
+                // The data connection can still be used for another command.
+                None => {
+                    let mut session = shared_session.lock().await;
+                    if session.data_cmd_tx.is_none() {
+                        session.data_cmd_tx = Some(tx);
+                    }
+                }
             }
-            None => Ok(Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established")),
-        }
+        });
+        Ok(Reply::none())
+    }
+}
+
+// Tells if the file exists. Only a not-found error from the storage back-end means that it doesn't.
+async fn exists<Storage, User>(storage: &Storage, user: &User, path: &Path) -> Result<bool, storage::Error>
+where
+    User: UserDetail,
+    Storage: StorageBackend<User>,
+{
+    match storage.metadata(user, path).await {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::PermanentFileNotAvailable => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// Renames the existing file to `<name>.<n>`, one version higher than the highest one in the
+// directory, so that the upload doesn't replace it. Listing the directory once is cheaper than
+// asking the storage back-end about each version, which are HTTP requests for the cloud back-ends.
+async fn keep_version<Storage, User>(storage: &Storage, user: &User, path: &Path) -> Result<(), storage::Error>
+where
+    User: UserDetail,
+    Storage: StorageBackend<User>,
+    Storage::Metadata: Metadata,
+{
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(storage::Error::from(ErrorKind::FileNameNotAllowedError)),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    let latest = storage
+        .list(user, dir)
+        .await?
+        .iter()
+        .filter_map(|fi| version_number(&name, &fi.path.file_name()?.to_string_lossy()))
+        .max()
+        .unwrap_or(0);
+    let mut version_path: OsString = path.as_os_str().to_owned();
+    version_path.push(format!(".{}", latest + 1));
+    storage.rename(user, path.to_path_buf(), PathBuf::from(version_path)).await
+}
+
+// Returns `n` if the file name is `<name>.<n>`.
+fn version_number(name: &str, file_name: &str) -> Option<u64> {
+    file_name.strip_prefix(name)?.strip_prefix('.')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn version_numbers() {
+        assert_eq!(version_number("report.csv", "report.csv.12"), Some(12));
+        assert_eq!(version_number("report.csv", "report.csv"), None);
+        assert_eq!(version_number("report.csv", "report.csv.bak"), None);
+        assert_eq!(version_number("report.csv", "report.csv.1.2"), None);
+        assert_eq!(version_number("report", "report.csv.1"), None);
     }
 }
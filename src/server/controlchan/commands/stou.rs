@@ -29,14 +29,14 @@ where
     #[tracing_attributes::instrument]
     async fn handle(&self, args: CommandContext<Storager, User>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
-        let uuid: String = Uuid::new_v4().to_string();
+        let uuid: String = unique_file_name();
         let filename: &Path = std::path::Path::new(&uuid);
         let path: String = session.cwd.join(&filename).to_string_lossy().to_string();
         let logger = args.logger;
         match session.data_cmd_tx.take() {
             Some(mut tx) => {
                 tokio::spawn(async move {
-                    if let Err(err) = tx.send(DataChanCmd::Stor { path }).await {
+                    if let Err(err) = tx.send(DataChanCmd::Stor { path, create_new: true }).await {
                         slog::warn!(logger, "sending command failed. {}", err);
                     }
                 });
@@ -46,3 +46,8 @@ where
         }
     }
 }
+
+// Returns a new file name that is very unlikely to be taken already.
+pub(crate) fn unique_file_name() -> String {
+    Uuid::new_v4().to_string()
+}
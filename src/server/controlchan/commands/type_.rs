@@ -26,6 +26,9 @@
 // argument is changed, Format then returns to the Non-print
 // default.
 
+// We only support ASCII Non-print and Image. Since we did not support ASCII in the past and clients
+// expect to download files unaltered we start in Image mode rather than the RFC's ASCII default.
+
 use crate::{
     auth::UserDetail,
     server::controlchan::{
@@ -37,8 +40,27 @@ use crate::{
 };
 use async_trait::async_trait;
 
+/// The parameter that can be given to the `TYPE` command.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TypeParam {
+    /// Text with CRLF line endings on the data connection (`A` or `A N`).
+    Ascii,
+    /// EBCDIC text (`E`). Not supported.
+    Ebcdic,
+    /// Files are transferred unaltered (`I` or `L 8`).
+    Image,
+}
+
 #[derive(Debug)]
-pub struct Type;
+pub struct Type {
+    param: TypeParam,
+}
+
+impl Type {
+    pub fn new(param: TypeParam) -> Self {
+        Type { param }
+    }
+}
 
 #[async_trait]
 impl<Storage, User> CommandHandler<Storage, User> for Type
@@ -48,7 +70,17 @@ where
     Storage::Metadata: Metadata,
 {
     #[tracing_attributes::instrument]
-    async fn handle(&self, _args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
-        Ok(Reply::new(ReplyCode::CommandOkay, "Always in binary mode"))
+    async fn handle(&self, args: CommandContext<Storage, User>) -> Result<Reply, ControlChanError> {
+        match self.param {
+            TypeParam::Ascii => {
+                args.session.lock().await.data_type = TypeParam::Ascii;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Switching to ASCII mode"))
+            }
+            TypeParam::Image => {
+                args.session.lock().await.data_type = TypeParam::Image;
+                Ok(Reply::new(ReplyCode::CommandOkay, "Switching to binary mode"))
+            }
+            TypeParam::Ebcdic => Ok(Reply::new(ReplyCode::CommandNotImplementedForParameter, "EBCDIC is not supported")),
+        }
     }
 }
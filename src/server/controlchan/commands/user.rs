@@ -37,17 +37,19 @@ where
     async fn handle(&self, args: CommandContext<Storage, Usr>) -> Result<Reply, ControlChanError> {
         let mut session = args.session.lock().await;
         let username_str = std::str::from_utf8(&self.username)?;
-        let cert_auth_sufficient = args.authenticator.cert_auth_sufficient(username_str).await;
+        // A virtual host selected with HOST may have its own authenticator.
+        let authenticator = session.authenticator.clone().unwrap_or_else(|| args.authenticator.clone());
+        let cert_auth_sufficient = authenticator.cert_auth_sufficient(username_str).await;
         match (session.state, &session.cert_chain, cert_auth_sufficient) {
             (SessionState::New, Some(_), true) => {
-                let auth_result: Result<Usr, AuthenticationError> = args
-                    .authenticator
+                let auth_result: Result<Usr, AuthenticationError> = authenticator
                     .authenticate(
                         username_str,
                         &Credentials {
                             certificate_chain: session.cert_chain.clone(),
                             password: None,
                             source_ip: session.source.ip(),
+                            sni_hostname: session.sni_hostname.clone(),
                         },
                     )
                     .await;
@@ -56,6 +58,9 @@ where
                         let user = username_str;
                         session.username = Some(user.to_string());
                         session.state = SessionState::WaitCmd;
+                        if let Some(home) = user_detail.home() {
+                            session.cwd = home.to_path_buf();
+                        }
                         session.user = Arc::new(Some(user_detail));
                         Ok(Reply::new(ReplyCode::UserLoggedInViaCert, "User logged in"))
                     }
@@ -235,6 +240,13 @@ mod tests {
                 tx_proxyloop: None,
                 logger: slog::Logger::root(slog::Discard {}, o!()),
                 sitemd5: Default::default(),
+                active_passive_mode: Default::default(),
+                allow_fxp: false,
+                list_max_depth: 0,
+                list_max_entries: 0,
+                list_hide_dotfiles: false,
+                virtual_hosts: Default::default(),
+                site_commands: Default::default(),
             }
         }
     }
@@ -3,6 +3,7 @@ use crate::{
     metrics::MetricsMiddleware,
     server::{
         chancomms::{ControlChanMsg, ProxyLoopSender},
+        charset::Charset,
         controlchan::{
             auth::AuthMiddleware,
             codecs::FtpCodec,
@@ -11,29 +12,42 @@ use crate::{
             error::{ControlChanError, ControlChanErrorKind},
             ftps::{FtpsControlChanEnforcerMiddleware, FtpsDataChanEnforcerMiddleware},
             handler::{CommandContext, CommandHandler},
+            line_parser,
             log::LoggingMiddleware,
-            middleware::ControlChanMiddleware,
+            middleware::EventHandler,
+            permission::PermissionMiddleware,
             Reply, ReplyCode,
         },
-        ftpserver::options::{FtpsRequired, PassiveHost, SiteMd5},
+        ftpserver::{
+            acl::Acl,
+            middleware::{ControlChanMiddleware, Next, Request, SessionInfo},
+            options::{ActivePassiveMode, FtpsRequired, OverwritePolicy, PartialUploadPolicy, PassiveHost, SiteMd5},
+            site::SiteCommand,
+            virtual_host::VirtualHost,
+        },
         session::SharedSession,
+        shutdown,
         tls::FtpsConfig,
+        transfer::TransferMonitor,
         Event, Session, SessionState,
     },
     storage::{ErrorKind, Metadata, StorageBackend},
 };
 
 use async_trait::async_trait;
+use encoding_rs::Encoding;
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
-    SinkExt, StreamExt,
+    future::BoxFuture,
+    FutureExt, SinkExt, StreamExt,
 };
 use rustls::{ServerSession, Session as RustlsSession};
-use std::{net::SocketAddr, ops::Range, sync::Arc, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, ops::Range, sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::Mutex,
+    time::Instant,
 };
 use tokio_util::codec::{Decoder, Framed};
 
@@ -52,13 +66,27 @@ where
     pub authenticator: Arc<dyn Authenticator<User>>,
     pub passive_ports: Range<u16>,
     pub passive_host: PassiveHost,
+    pub active_passive_mode: ActivePassiveMode,
+    pub allow_fxp: bool,
     pub ftps_config: FtpsConfig,
+    pub ftps_implicit: bool,
     pub collect_metrics: bool,
     pub idle_session_timeout: Duration,
     pub logger: slog::Logger,
     pub ftps_required_control_chan: FtpsRequired,
     pub ftps_required_data_chan: FtpsRequired,
     pub sitemd5: SiteMd5,
+    pub list_max_depth: u32,
+    pub list_max_entries: usize,
+    pub list_hide_dotfiles: bool,
+    pub virtual_hosts: Arc<HashMap<String, VirtualHost<Storage, User>>>,
+    pub site_commands: Arc<HashMap<String, Arc<dyn SiteCommand<Storage, User>>>>,
+    pub middlewares: Arc<Vec<Arc<dyn ControlChanMiddleware<User>>>>,
+    pub legacy_charset: Option<&'static Encoding>,
+    pub partial_upload_policy: PartialUploadPolicy,
+    pub overwrite_policy: OverwritePolicy,
+    pub transfer_monitor: Option<TransferMonitor>,
+    pub acl: Option<Arc<Acl>>,
 }
 
 /// Does TCP processing when an FTP client connects
@@ -68,6 +96,7 @@ pub async fn spawn<Storage, User>(
     tcp_stream: TcpStream,
     destination: Option<SocketAddr>,
     proxyloop_msg_tx: Option<ProxyLoopSender<Storage, User>>,
+    mut shutdown: shutdown::Listener,
 ) -> Result<(), ControlChanError>
 where
     User: UserDetail + 'static,
@@ -79,21 +108,42 @@ where
         authenticator,
         passive_ports,
         passive_host,
+        active_passive_mode,
+        allow_fxp,
         ftps_config,
+        ftps_implicit,
         ftps_required_control_chan,
         ftps_required_data_chan,
         collect_metrics,
         idle_session_timeout,
         logger,
         sitemd5,
+        list_max_depth,
+        list_max_entries,
+        list_hide_dotfiles,
+        virtual_hosts,
+        site_commands,
+        middlewares,
+        legacy_charset,
+        partial_upload_policy,
+        overwrite_policy,
+        transfer_monitor,
+        acl,
         ..
     } = config;
 
     let tls_configured = matches!(ftps_config, FtpsConfig::On { .. });
     let storage_features = storage.supported_features();
+    let charset = Charset::new(legacy_charset);
     let (control_msg_tx, control_msg_rx): (Sender<ControlChanMsg>, Receiver<ControlChanMsg>) = channel(1);
     let session: Session<Storage, User> = Session::new(Arc::new(storage), tcp_stream.peer_addr()?)
-        .ftps(ftps_config.clone())
+        .ftps(ftps_config)
+        .ftps_implicit(ftps_implicit)
+        .charset(charset.clone())
+        .partial_upload_policy(partial_upload_policy)
+        .overwrite_policy(overwrite_policy)
+        .transfer_monitor(transfer_monitor)
+        .acl(acl)
         .metrics(collect_metrics)
         .control_msg_tx(control_msg_tx.clone())
         .destination(destination);
@@ -110,11 +160,23 @@ where
         tls_configured,
         passive_ports,
         passive_host,
+        active_passive_mode,
+        allow_fxp,
         tx_control_chan: control_msg_tx,
         local_addr,
         storage_features,
         tx_proxy_loop: proxyloop_msg_tx,
         sitemd5,
+        list_max_depth,
+        list_max_entries,
+        list_hide_dotfiles,
+        virtual_hosts,
+        site_commands,
+    };
+
+    let event_chain = PermissionMiddleware {
+        session: shared_session.clone(),
+        next: event_chain,
     };
 
     let event_chain = AuthMiddleware {
@@ -145,30 +207,60 @@ where
         next: event_chain,
     };
 
-    let codec = FtpCodec::new();
-    let cmd_and_reply_stream: Framed<Box<dyn AsyncReadAsyncWriteSendUnpin>, FtpCodec> = codec.framed(Box::new(tcp_stream));
-    let (mut reply_sink, command_source) = cmd_and_reply_stream.split();
-
-    reply_sink.send(Reply::new(ReplyCode::ServiceReady, config.greeting)).await?;
-    reply_sink.flush().await?;
-
-    let mut command_source = command_source.fuse();
+    let greeting = config.greeting;
     let mut control_msg_rx = control_msg_rx.fuse();
 
     tokio::spawn(async move {
+        // With implicit FTPS the client starts the TLS handshake right after connecting, before
+        // we send the greeting.
+        let io: Box<dyn AsyncReadAsyncWriteSendUnpin> = if ftps_implicit {
+            match tokio::time::timeout(idle_session_timeout, accept_tls(Box::new(tcp_stream), &shared_session)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    slog::warn!(logger, "Closing control channel. TLS handshake failed: {}", err);
+                    return;
+                }
+                Err(_) => {
+                    slog::warn!(logger, "Closing control channel. Timed out waiting for the TLS handshake");
+                    return;
+                }
+            }
+        } else {
+            Box::new(tcp_stream)
+        };
+
+        let codec = FtpCodec::new(charset.clone());
+        let cmd_and_reply_stream: Framed<Box<dyn AsyncReadAsyncWriteSendUnpin>, FtpCodec> = codec.framed(io);
+        let (mut reply_sink, command_source) = cmd_and_reply_stream.split();
+
+        if let Err(err) = reply_sink.send(Reply::new(ReplyCode::ServiceReady, greeting)).await {
+            slog::warn!(logger, "Could not send greeting to client: {:?}", err);
+            return;
+        }
+
+        let mut command_source = command_source.fuse();
+
         // The control channel event loop
         slog::info!(logger, "Starting control loop");
+        // Set once the server shuts down. Until then a running transfer may still finish.
+        let mut shutdown_deadline: Option<Instant> = None;
         loop {
             let incoming = {
                 #[allow(unused_assignments)]
                 let mut incoming = None;
                 let mut timeout_delay = Box::pin(tokio::time::sleep(idle_session_timeout));
                 tokio::select! {
+                    grace_period = shutdown.listen(), if shutdown_deadline.is_none() => {
+                        slog::info!(logger, "Server is shutting down");
+                        shutdown_deadline = Some(Instant::now() + grace_period);
+                    },
+                    // Checks if the transfer finished.
+                    _ = tokio::time::sleep(Duration::from_secs(1)), if shutdown_deadline.is_some() => {},
                     Some(cmd_result) = command_source.next() => {
-                        incoming = Some(cmd_result.map(Event::Command));
+                        incoming = Some(cmd_result.map(|(command, request)| (Event::Command(command), Some(request))));
                     },
                     Some(msg) = control_msg_rx.next() => {
-                        incoming = Some(Ok(Event::InternalMsg(msg)));
+                        incoming = Some(Ok((Event::InternalMsg(msg), None)));
                     },
                     _ = &mut timeout_delay => {
                         let session = shared_session.lock().await;
@@ -182,7 +274,7 @@ where
             };
             match incoming {
                 None => {}
-                Some(Ok(event)) => {
+                Some(Ok((event, request))) => {
                     if let Event::InternalMsg(ControlChanMsg::Quit) = event {
                         return;
                     }
@@ -195,20 +287,8 @@ where
                         let io = codec_io.into_inner();
 
                         // Wrap in TLS Stream
-                        let acceptor: tokio_rustls::TlsAcceptor = match ftps_config.clone() {
-                            FtpsConfig::On { tls_config } => tls_config.into(),
-                            _ => panic!("Could not create TLS acceptor. Illegal program state"),
-                        };
-                        let accepted = acceptor.accept(io).await;
-                        let io: Box<dyn AsyncReadAsyncWriteSendUnpin> = match accepted {
-                            Ok(stream) => {
-                                let s: &ServerSession = stream.get_ref().1;
-                                if let Some(certs) = s.get_peer_certificates() {
-                                    let mut session = shared_session.lock().await;
-                                    session.cert_chain = Some(certs.iter().map(|c| crate::auth::ClientCert(c.0.clone())).collect());
-                                }
-                                Box::new(stream)
-                            }
+                        let io = match accept_tls(io, &shared_session).await {
+                            Ok(stream) => stream,
                             Err(err) => {
                                 slog::warn!(logger, "Closing control channel. Could not upgrade to TLS: {}", err);
                                 return;
@@ -216,7 +296,7 @@ where
                         };
 
                         // Wrap in codec again and get sink + source
-                        let codec = FtpCodec::new();
+                        let codec = FtpCodec::new(charset.clone());
                         let cmd_and_reply_stream = codec.framed(io);
                         let (sink, src) = cmd_and_reply_stream.split();
                         let src = src.fuse();
@@ -226,7 +306,13 @@ where
 
                     // TODO: Handle Event::InternalMsg(InternalMsg::PlaintextControlChannel)
 
-                    match event_chain.handle(event).await {
+                    let result = match (event, request) {
+                        (Event::Command(command), Some(request)) if !middlewares.is_empty() => {
+                            handle_with_middlewares(&middlewares, &shared_session, &mut event_chain, command, request, &logger).await
+                        }
+                        (event, _) => event_chain.handle(event).await,
+                    };
+                    match result {
                         Err(e) => {
                             slog::warn!(logger, "Event handler chain error: {:?}. Closing control connection", e);
                             return;
@@ -252,12 +338,117 @@ where
                     }
                 }
             }
+            if let Some(deadline) = shutdown_deadline {
+                let mut session = shared_session.lock().await;
+                if session.data_busy && Instant::now() < deadline {
+                    continue;
+                }
+                if session.data_busy {
+                    slog::warn!(logger, "Interrupting transfer at the end of the shutdown grace period");
+                    if let Some(mut tx) = session.data_abort_tx.take() {
+                        let _ = tx.try_send(());
+                    }
+                }
+                drop(session);
+                // The data channel may have queued the reply of a transfer that just finished. That
+                // reply goes out before the 421.
+                while let Some(Some(msg)) = control_msg_rx.next().now_or_never() {
+                    let reply = match event_chain.handle(Event::InternalMsg(msg)).await {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            slog::warn!(logger, "Event handler chain error during shutdown: {:?}", e);
+                            break;
+                        }
+                    };
+                    if let Err(err) = reply_sink.send(reply).await {
+                        slog::warn!(logger, "Could not send reply to client: {:?}", err);
+                        return;
+                    }
+                }
+                if let Err(err) = reply_sink.send(Reply::new(ReplyCode::ServiceNotAvailable, "Server is shutting down")).await {
+                    slog::warn!(logger, "Could not send shutdown reply to client: {:?}", err);
+                }
+                return;
+            }
         }
     });
 
     Ok(())
 }
 
+// Does the server side of the TLS handshake on the control channel. The session's config may differ
+// from the server's if the client selected a virtual host.
+async fn accept_tls<Storage, User>(
+    io: Box<dyn AsyncReadAsyncWriteSendUnpin>,
+    shared_session: &SharedSession<Storage, User>,
+) -> std::io::Result<Box<dyn AsyncReadAsyncWriteSendUnpin>>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+{
+    let session_ftps_config = shared_session.lock().await.ftps_config.clone();
+    let acceptor: tokio_rustls::TlsAcceptor = match session_ftps_config {
+        FtpsConfig::On { tls_config } => tls_config.current().into(),
+        _ => panic!("Could not create TLS acceptor. Illegal program state"),
+    };
+    let stream = acceptor.accept(io).await?;
+    let s: &ServerSession = stream.get_ref().1;
+    let mut session = shared_session.lock().await;
+    if let Some(certs) = s.get_peer_certificates() {
+        session.cert_chain = Some(certs.iter().map(|c| crate::auth::ClientCert(c.0.clone())).collect());
+    }
+    session.sni_hostname = s.get_sni_hostname().map(String::from);
+    drop(session);
+    Ok(Box::new(stream))
+}
+
+// Runs the user's middleware for a command from the client. After the last one the command goes
+// through our own chain. If a middleware rewrote the request it is parsed again.
+async fn handle_with_middlewares<Storage, User, Chain>(
+    middlewares: &[Arc<dyn ControlChanMiddleware<User>>],
+    session: &SharedSession<Storage, User>,
+    event_chain: &mut Chain,
+    command: Command,
+    request: Request,
+    logger: &slog::Logger,
+) -> Result<Reply, ControlChanError>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Chain: EventHandler,
+{
+    let session_info = {
+        let session = session.lock().await;
+        SessionInfo {
+            user: session.user.clone(),
+            username: session.username.clone(),
+            logged_in: session.state == SessionState::WaitCmd,
+            client: session.source,
+            cwd: session.cwd.clone(),
+            tls: session.cmd_tls,
+            sni_hostname: session.sni_hostname.clone(),
+        }
+    };
+    let original = request.clone();
+    let logger = logger.clone();
+    let handler = Box::new(move |request: Request| -> BoxFuture<'_, Result<Reply, ControlChanError>> {
+        Box::pin(async move {
+            let command = if request == original {
+                command
+            } else {
+                match line_parser::parse(request.to_line()) {
+                    Ok(command) => command,
+                    Err(err) => return Ok(handle_control_channel_error::<Storage, User>(logger, err.into()).0),
+                }
+            };
+            event_chain.handle(Event::Command(command)).await
+        })
+    });
+    Next::new(middlewares, &session_info, handler).run(request).await
+}
+
 // gets the reply to be sent to the client and tells if the connection should be closed.
 fn handle_control_channel_error<Storage, User>(logger: slog::Logger, error: ControlChanError) -> (Reply, bool)
 where
@@ -291,11 +482,18 @@ where
     tls_configured: bool,
     passive_ports: Range<u16>,
     passive_host: PassiveHost,
+    active_passive_mode: ActivePassiveMode,
+    allow_fxp: bool,
     tx_control_chan: Sender<ControlChanMsg>,
     local_addr: SocketAddr,
     storage_features: u32,
     tx_proxy_loop: Option<ProxyLoopSender<Storage, User>>,
     sitemd5: SiteMd5,
+    list_max_depth: u32,
+    list_max_entries: usize,
+    list_hide_dotfiles: bool,
+    virtual_hosts: Arc<HashMap<String, VirtualHost<Storage, User>>>,
+    site_commands: Arc<HashMap<String, Arc<dyn SiteCommand<Storage, User>>>>,
 }
 
 impl<Storage, User> PrimaryEventHandler<Storage, User>
@@ -319,6 +517,11 @@ where
             }
             WriteFailed => Ok(Reply::new(ReplyCode::TransientFileError, "Failed to write file")),
             ConnectionReset => Ok(Reply::new(ReplyCode::ConnectionClosed, "Datachannel unexpectedly closed")),
+            TransferAborted => {
+                let mut session = self.session.lock().await;
+                session.start_pos = 0;
+                Ok(Reply::new(ReplyCode::ConnectionClosed, "Connection closed; transfer aborted"))
+            }
             WrittenData { .. } => {
                 let mut session = self.session.lock().await;
                 session.start_pos = 0;
@@ -359,6 +562,7 @@ where
             StorageError(error_type) => match error_type.kind() {
                 ErrorKind::ExceededStorageAllocationError => Ok(Reply::new(ReplyCode::ExceededStorageAllocation, "Exceeded storage allocation")),
                 ErrorKind::FileNameNotAllowedError => Ok(Reply::new(ReplyCode::BadFileName, "File name not allowed")),
+                ErrorKind::FileAlreadyExists => Ok(Reply::new(ReplyCode::BadFileName, "File already exists")),
                 ErrorKind::InsufficientStorageSpaceError => Ok(Reply::new(ReplyCode::OutOfSpace, "Insufficient storage space")),
                 ErrorKind::LocalError => Ok(Reply::new(ReplyCode::LocalError, "Local error")),
                 ErrorKind::PageTypeUnknown => Ok(Reply::new(ReplyCode::PageTypeUnknown, "Page type unknown")),
@@ -380,12 +584,19 @@ where
             tls_configured: self.tls_configured,
             passive_ports: self.passive_ports.clone(),
             passive_host: self.passive_host.clone(),
+            active_passive_mode: self.active_passive_mode,
+            allow_fxp: self.allow_fxp,
             tx_control_chan: self.tx_control_chan.clone(),
             local_addr: self.local_addr,
             storage_features: self.storage_features,
             tx_proxyloop: self.tx_proxy_loop.clone(),
             logger: self.logger.clone(),
             sitemd5: self.sitemd5,
+            list_max_depth: self.list_max_depth,
+            list_max_entries: self.list_max_entries,
+            list_hide_dotfiles: self.list_hide_dotfiles,
+            virtual_hosts: self.virtual_hosts.clone(),
+            site_commands: self.site_commands.clone(),
         };
 
         let handler: Box<dyn CommandHandler<Storage, User>> = match cmd {
@@ -394,17 +605,22 @@ where
             Command::Syst => Box::new(commands::Syst),
             Command::Stat { path } => Box::new(commands::Stat::new(path)),
             Command::Acct { .. } => Box::new(commands::Acct),
-            Command::Type => Box::new(commands::Type),
+            Command::Type { param } => Box::new(commands::Type::new(param)),
             Command::Stru { structure } => Box::new(commands::Stru::new(structure)),
             Command::Mode { mode } => Box::new(commands::Mode::new(mode)),
             Command::Help => Box::new(commands::Help),
             Command::Noop => Box::new(commands::Noop),
             Command::Pasv => Box::new(commands::Pasv::new()),
-            Command::Port => Box::new(commands::Port),
+            Command::Epsv { all, protocol } => Box::new(commands::Epsv::new(all, protocol)),
+            Command::Port { addr } => Box::new(commands::Port::new(addr)),
+            Command::Eprt { addr } => Box::new(commands::Eprt::new(addr)),
             Command::Retr { .. } => Box::new(commands::Retr),
             Command::Stor { .. } => Box::new(commands::Stor),
+            Command::Appe { .. } => Box::new(commands::Appe),
             Command::List { .. } => Box::new(commands::List),
             Command::Nlst { .. } => Box::new(commands::Nlst),
+            Command::Mlsd { .. } => Box::new(commands::Mlsd),
+            Command::Mlst { path } => Box::new(commands::Mlst::new(path)),
             Command::Feat => Box::new(commands::Feat),
             Command::Pwd => Box::new(commands::Pwd),
             Command::Cwd { path } => Box::new(commands::Cwd::new(path)),
@@ -426,7 +642,12 @@ where
             Command::Size { file } => Box::new(commands::Size::new(file)),
             Command::Rest { offset } => Box::new(commands::Rest::new(offset)),
             Command::Mdtm { file } => Box::new(commands::Mdtm::new(file)),
+            Command::Mfmt { modified, file } => Box::new(commands::Mfmt::new(modified, file)),
             Command::Md5 { file } => Box::new(commands::Md5::new(file)),
+            Command::Hash { file } => Box::new(commands::Hash::new(file)),
+            Command::Rang { start, end } => Box::new(commands::Rang::new(start, end)),
+            Command::Host { host } => Box::new(commands::Host::new(host)),
+            Command::Site { command_name, arguments } => Box::new(commands::Site::new(command_name, arguments)),
             Command::Other { .. } => return Ok(Reply::new(ReplyCode::CommandSyntaxError, "Command not implemented")),
         };
 
@@ -435,7 +656,7 @@ where
 }
 
 #[async_trait]
-impl<Storage, User> ControlChanMiddleware for PrimaryEventHandler<Storage, User>
+impl<Storage, User> EventHandler for PrimaryEventHandler<Storage, User>
 where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
@@ -3,8 +3,8 @@ use async_trait::async_trait;
 use crate::{
     auth::UserDetail,
     server::{
-        controlchan::error::ControlChanError, controlchan::middleware::ControlChanMiddleware, ftpserver::options::FtpsRequired, session::SharedSession,
-        Command, ControlChanErrorKind, Event, Reply, ReplyCode,
+        controlchan::error::ControlChanError, controlchan::middleware::EventHandler, ftpserver::options::FtpsRequired, session::SharedSession, Command,
+        ControlChanErrorKind, Event, Reply, ReplyCode,
     },
     storage::{Metadata, StorageBackend},
 };
@@ -15,7 +15,7 @@ where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     pub session: SharedSession<Storage, User>,
     pub ftps_requirement: FtpsRequired,
@@ -23,12 +23,12 @@ where
 }
 
 #[async_trait]
-impl<Storage, User, Next> ControlChanMiddleware for FtpsControlChanEnforcerMiddleware<Storage, User, Next>
+impl<Storage, User, Next> EventHandler for FtpsControlChanEnforcerMiddleware<Storage, User, Next>
 where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         match (self.ftps_requirement, event) {
@@ -91,7 +91,7 @@ where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     pub session: SharedSession<Storage, User>,
     pub ftps_requirement: FtpsRequired,
@@ -99,18 +99,21 @@ where
 }
 
 #[async_trait]
-impl<Storage, User, Next> ControlChanMiddleware for FtpsDataChanEnforcerMiddleware<Storage, User, Next>
+impl<Storage, User, Next> EventHandler for FtpsDataChanEnforcerMiddleware<Storage, User, Next>
 where
     User: UserDetail + 'static,
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         match (self.ftps_requirement, event) {
             (FtpsRequired::None, event) => self.next.handle(event).await,
             (FtpsRequired::All, event) => match event {
-                Event::Command(Command::Pasv) => {
+                Event::Command(Command::Pasv)
+                | Event::Command(Command::Epsv { .. })
+                | Event::Command(Command::Port { .. })
+                | Event::Command(Command::Eprt { .. }) => {
                     let is_tls = async {
                         let session = self.session.lock().await;
                         session.data_tls
@@ -124,7 +127,10 @@ where
                 _ => self.next.handle(event).await,
             },
             (FtpsRequired::Accounts, event) => match event {
-                Event::Command(Command::Pasv) => {
+                Event::Command(Command::Pasv)
+                | Event::Command(Command::Epsv { .. })
+                | Event::Command(Command::Port { .. })
+                | Event::Command(Command::Eprt { .. }) => {
                     let (is_tls, username_opt) = async {
                         let session = self.session.lock().await;
                         (session.cmd_tls, session.username.clone())
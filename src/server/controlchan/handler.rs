@@ -3,7 +3,11 @@ use crate::{
     server::{
         chancomms::ProxyLoopSender,
         controlchan::{command::Command, error::ControlChanError, Reply},
-        ftpserver::options::{PassiveHost, SiteMd5},
+        ftpserver::{
+            options::{ActivePassiveMode, PassiveHost, SiteMd5},
+            site::SiteCommand,
+            virtual_host::VirtualHost,
+        },
         session::SharedSession,
         ControlChanMsg,
     },
@@ -11,7 +15,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use futures::channel::mpsc::Sender;
-use std::{ops::Range, result::Result, sync::Arc};
+use std::{collections::HashMap, ops::Range, result::Result, sync::Arc};
 
 // Common interface for all handlers of `Commands`
 #[async_trait]
@@ -43,10 +47,17 @@ where
     pub tls_configured: bool,
     pub passive_ports: Range<u16>,
     pub passive_host: PassiveHost,
+    pub active_passive_mode: ActivePassiveMode,
+    pub allow_fxp: bool,
     pub tx_control_chan: Sender<ControlChanMsg>,
     pub local_addr: std::net::SocketAddr,
     pub storage_features: u32,
     pub tx_proxyloop: Option<ProxyLoopSender<Storage, User>>,
     pub logger: slog::Logger,
     pub sitemd5: SiteMd5,
+    pub list_max_depth: u32,
+    pub list_max_entries: usize,
+    pub list_hide_dotfiles: bool,
+    pub virtual_hosts: Arc<HashMap<String, VirtualHost<Storage, User>>>,
+    pub site_commands: Arc<HashMap<String, Arc<dyn SiteCommand<Storage, User>>>>,
 }
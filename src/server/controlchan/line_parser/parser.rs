@@ -2,13 +2,19 @@ use super::error::{ParseErrorKind, Result};
 use crate::server::{
     controlchan::{
         command::Command,
-        commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam},
+        commands::{AuthParam, ModeParam, Opt, ProtParam, StruParam, TypeParam},
     },
+    mlsx::Facts,
     password::Password,
 };
 
 use bytes::Bytes;
-use std::str;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str,
+    time::{Duration, SystemTime},
+};
 
 /// Parse the given bytes into a [`Command`].
 ///
@@ -43,9 +49,15 @@ where
             Command::Stat { path }
         }
         "TYPE" => {
-            // We don't care about text format conversion, so we'll ignore the params and we're
-            // just always in binary mode.
-            Command::Type
+            let params = parse_to_eol(cmd_params)?.to_ascii_uppercase();
+            let params: Vec<&[u8]> = params.split(|&b| b == b' ').filter(|p| !p.is_empty()).collect();
+            let param = match &params[..] {
+                [b"A"] | [b"A", b"N"] => TypeParam::Ascii,
+                [b"E"] | [b"E", b"N"] => TypeParam::Ebcdic,
+                [b"I"] | [b"L", b"8"] => TypeParam::Image,
+                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+            };
+            Command::Type { param }
         }
         "STRU" => {
             let params = parse_to_eol(cmd_params)?;
@@ -68,6 +80,7 @@ where
                 Some(b'S') => Command::Mode { mode: ModeParam::Stream },
                 Some(b'B') => Command::Mode { mode: ModeParam::Block },
                 Some(b'C') => Command::Mode { mode: ModeParam::Compressed },
+                Some(b'Z') => Command::Mode { mode: ModeParam::Deflate },
                 _ => return Err(ParseErrorKind::InvalidCommand.into()),
             }
         }
@@ -92,7 +105,27 @@ where
             if params.is_empty() {
                 return Err(ParseErrorKind::InvalidCommand.into());
             }
-            Command::Port
+            let addr = parse_host_port(&params)?;
+            Command::Port { addr }
+        }
+        "EPRT" => {
+            let params = parse_to_eol(cmd_params)?;
+            if params.is_empty() {
+                return Err(ParseErrorKind::InvalidCommand.into());
+            }
+            let addr = parse_extended_host_port(&params)?;
+            Command::Eprt { addr }
+        }
+        "EPSV" => {
+            let params = parse_to_eol(cmd_params)?;
+            match str::from_utf8(&params)?.to_uppercase().as_str() {
+                "" => Command::Epsv { all: false, protocol: None },
+                "ALL" => Command::Epsv { all: true, protocol: None },
+                protocol => Command::Epsv {
+                    all: false,
+                    protocol: Some(protocol.parse().map_err(|_| ParseErrorKind::InvalidCommand)?),
+                },
+            }
         }
         "RETR" => {
             let path = parse_to_eol(cmd_params)?;
@@ -112,6 +145,14 @@ where
             let path = String::from_utf8_lossy(&path);
             Command::Stor { path: path.to_string() }
         }
+        "APPE" => {
+            let path = parse_to_eol(cmd_params)?;
+            if path.is_empty() {
+                return Err(ParseErrorKind::InvalidCommand.into());
+            }
+            let path = String::from_utf8_lossy(&path);
+            Command::Appe { path: path.to_string() }
+        }
         "LIST" => {
             let line = parse_to_eol(cmd_params)?;
             let path = line
@@ -119,8 +160,13 @@ where
                 .filter(|s| !line.is_empty() && !s.starts_with(b"-"))
                 .map(|s| String::from_utf8_lossy(&s).to_string())
                 .next();
-            // Note that currently we just throw arguments away.
-            Command::List { options: None, path }
+            let options: Vec<String> = line
+                .split(|&b| b == b' ')
+                .filter(|s| s.starts_with(b"-"))
+                .map(|s| String::from_utf8_lossy(s).to_string())
+                .collect();
+            let options = if options.is_empty() { None } else { Some(options.join(" ")) };
+            Command::List { options, path }
         }
         "NLST" => {
             let path = parse_to_eol(cmd_params)?;
@@ -131,6 +177,24 @@ where
             };
             Command::Nlst { path }
         }
+        "MLSD" => {
+            let path = parse_to_eol(cmd_params)?;
+            let path = if path.is_empty() {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&path).to_string())
+            };
+            Command::Mlsd { path }
+        }
+        "MLST" => {
+            let path = parse_to_eol(cmd_params)?;
+            let path = if path.is_empty() {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&path).to_string())
+            };
+            Command::Mlst { path }
+        }
         "FEAT" => {
             let params = parse_to_eol(cmd_params)?;
             if !params.is_empty() {
@@ -174,7 +238,33 @@ where
                 b"UTF8 OFF" => Command::Opts {
                     option: Opt::Utf8 { on: false },
                 },
-                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                _ => {
+                    let mut parts = str::from_utf8(&params)?.splitn(2, ' ');
+                    match (parts.next(), parts.next()) {
+                        (Some(name), facts) if name.eq_ignore_ascii_case("MLST") => Command::Opts {
+                            option: Opt::Mlst {
+                                facts: Facts::parse(facts.unwrap_or("")),
+                            },
+                        },
+                        (Some(name), Some(args)) if name.eq_ignore_ascii_case("MODE") => {
+                            let args: Vec<&str> = args.split_whitespace().collect();
+                            match &args[..] {
+                                [mode, keyword, level] if mode.eq_ignore_ascii_case("Z") && keyword.eq_ignore_ascii_case("LEVEL") => Command::Opts {
+                                    option: Opt::ModeZ {
+                                        level: level.parse().map_err(|_| ParseErrorKind::InvalidCommand)?,
+                                    },
+                                },
+                                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                            }
+                        }
+                        (Some(name), algorithm) if name.eq_ignore_ascii_case("HASH") => Command::Opts {
+                            option: Opt::Hash {
+                                algorithm: algorithm.map(str::trim).filter(|a| !a.is_empty()).map(String::from),
+                            },
+                        },
+                        _ => return Err(ParseErrorKind::InvalidCommand.into()),
+                    }
+                }
             }
         }
         "DELE" => {
@@ -327,6 +417,47 @@ where
             let file = String::from_utf8_lossy(&params).to_string().into();
             Command::Mdtm { file }
         }
+        "MFMT" => {
+            let params = parse_to_eol(cmd_params)?;
+            let mut params = params.splitn(2, |&b| b == b' ');
+            match (params.next(), params.next()) {
+                (Some(time), Some(file)) if !file.is_empty() => {
+                    let modified = parse_time_val(str::from_utf8(time)?).ok_or(ParseErrorKind::InvalidCommand)?;
+                    let file = String::from_utf8_lossy(file).to_string().into();
+                    Command::Mfmt { modified, file }
+                }
+                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+            }
+        }
+        "HASH" => {
+            let params = parse_to_eol(cmd_params)?;
+            if params.is_empty() {
+                return Err(ParseErrorKind::InvalidCommand.into());
+            }
+
+            let file = String::from_utf8_lossy(&params).to_string().into();
+            Command::Hash { file }
+        }
+        "RANG" => {
+            let params = parse_to_eol(cmd_params)?;
+            let params: Vec<&str> = str::from_utf8(&params)?.split_whitespace().collect();
+            match &params[..] {
+                [start, end] => Command::Rang {
+                    start: start.parse().map_err(|_| ParseErrorKind::InvalidCommand)?,
+                    end: end.parse().map_err(|_| ParseErrorKind::InvalidCommand)?,
+                },
+                _ => return Err(ParseErrorKind::InvalidCommand.into()),
+            }
+        }
+        "HOST" => {
+            let params = parse_to_eol(cmd_params)?;
+            if params.is_empty() {
+                return Err(ParseErrorKind::InvalidCommand.into());
+            }
+
+            let host = String::from_utf8_lossy(&params).to_string();
+            Command::Host { host }
+        }
         "SITE" => {
             let (cmd_token, cmd_params) = split_token_params(&cmd_params);
             let cmd_token = normalize(cmd_token)?;
@@ -341,13 +472,20 @@ where
                     let file = String::from_utf8_lossy(&params).to_string().into();
                     Command::Md5 { file }
                 }
-                _ => {
+                "" => {
                     let params = parse_to_eol(cmd_params)?;
                     Command::Other {
                         command_name: cmd_token,
                         arguments: String::from_utf8_lossy(&params).to_string(),
                     }
                 }
+                _ => {
+                    let params = parse_to_eol(cmd_params)?;
+                    Command::Site {
+                        command_name: cmd_token,
+                        arguments: String::from_utf8_lossy(&params).to_string(),
+                    }
+                }
             }
         }
         _ => {
@@ -413,6 +551,57 @@ fn parse_to_eol(line: &[u8]) -> Result<Bytes> {
     Err(ParseErrorKind::InvalidEol.into())
 }
 
+// Parses the RFC 959 host-port argument of the PORT command e.g. `127,0,0,1,4,1`
+fn parse_host_port(params: &[u8]) -> Result<SocketAddr> {
+    let params = str::from_utf8(params)?;
+    let fields: Vec<u8> = params
+        .split(',')
+        .map(|field| field.trim().parse::<u8>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| ParseErrorKind::InvalidCommand)?;
+    if fields.len() != 6 {
+        return Err(ParseErrorKind::InvalidCommand.into());
+    }
+    let ip = Ipv4Addr::new(fields[0], fields[1], fields[2], fields[3]);
+    let port = (u16::from(fields[4]) << 8) | u16::from(fields[5]);
+    Ok(SocketAddr::new(IpAddr::V4(ip), port))
+}
+
+// Parses the RFC 2428 argument of the EPRT command e.g. `|1|132.235.1.2|6275|` or `|2|::1|6275|`
+fn parse_extended_host_port(params: &[u8]) -> Result<SocketAddr> {
+    let params = str::from_utf8(params)?;
+    // The delimiter is the first character and should be in the ASCII range 33-126.
+    let delimiter = match params.chars().next() {
+        Some(c) if ('!'..='~').contains(&c) => c,
+        _ => return Err(ParseErrorKind::InvalidCommand.into()),
+    };
+    let fields: Vec<&str> = params.split(delimiter).collect();
+    // Splitting "|1|addr|port|" gives an empty string on both ends.
+    if fields.len() != 5 || !fields[0].is_empty() || !fields[4].is_empty() {
+        return Err(ParseErrorKind::InvalidCommand.into());
+    }
+    let ip: IpAddr = match fields[1] {
+        "1" => IpAddr::V4(fields[2].parse().map_err(|_| ParseErrorKind::InvalidCommand)?),
+        "2" => IpAddr::V6(fields[2].parse().map_err(|_| ParseErrorKind::InvalidCommand)?),
+        _ => return Err(ParseErrorKind::InvalidCommand.into()),
+    };
+    let port: u16 = fields[3].parse().map_err(|_| ParseErrorKind::InvalidCommand)?;
+    Ok(SocketAddr::new(ip, port))
+}
+
 fn normalize(token: &[u8]) -> Result<String> {
     Ok(str::from_utf8(token).map(|t| t.to_uppercase())?)
 }
+
+// Parses an RFC 3659 time-val i.e. YYYYMMDDHHMMSS with optional fractions of a second. It is
+// always in UTC.
+fn parse_time_val(time_val: &str) -> Option<SystemTime> {
+    let mut parts = time_val.splitn(2, '.');
+    let (time, fraction) = (parts.next()?, parts.next().unwrap_or(""));
+    if time.len() != 14 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let time = NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S").ok()?;
+    let nanos: u64 = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse().ok()?;
+    Some(SystemTime::from(Utc.from_utc_datetime(&time)) + Duration::from_nanos(nanos))
+}
@@ -1,11 +1,13 @@
 use super::error::{ParseError, ParseErrorKind, Result};
 use crate::server::controlchan::{
     command::Command,
-    commands::{AuthParam, ModeParam, Opt, StruParam},
+    commands::{AuthParam, ModeParam, Opt, StruParam, TypeParam},
     line_parser::parser::parse,
 };
+use crate::server::mlsx::Facts;
 
 use pretty_assertions::assert_eq;
+use std::time::{Duration, UNIX_EPOCH};
 
 #[test]
 fn parse_user_cmd_crnl() {
@@ -94,6 +96,48 @@ fn parse_stru_no_params() {
     assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
 }
 
+#[test]
+fn parse_type() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "TYPE A\r\n",
+            expected: Ok(Command::Type { param: TypeParam::Ascii }),
+        },
+        Test {
+            input: "TYPE A N\r\n",
+            expected: Ok(Command::Type { param: TypeParam::Ascii }),
+        },
+        Test {
+            input: "type i\r\n",
+            expected: Ok(Command::Type { param: TypeParam::Image }),
+        },
+        Test {
+            input: "TYPE L 8\r\n",
+            expected: Ok(Command::Type { param: TypeParam::Image }),
+        },
+        Test {
+            input: "TYPE E\r\n",
+            expected: Ok(Command::Type { param: TypeParam::Ebcdic }),
+        },
+        Test {
+            input: "TYPE A T\r\n",
+            expected: Err(ParseError::from(ParseErrorKind::InvalidCommand)),
+        },
+        Test {
+            input: "TYPE\r\n",
+            expected: Err(ParseError::from(ParseErrorKind::InvalidCommand)),
+        },
+    ];
+
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
 #[test]
 fn parse_stru_f() {
     let input = "STRU F\r\n";
@@ -142,6 +186,12 @@ fn parse_mode_c() {
     assert_eq!(parse(input).unwrap(), Command::Mode { mode: ModeParam::Compressed });
 }
 
+#[test]
+fn parse_mode_z() {
+    let input = "MODE Z\r\n";
+    assert_eq!(parse(input).unwrap(), Command::Mode { mode: ModeParam::Deflate });
+}
+
 #[test]
 fn parse_mode_garbage() {
     let input = "MODE SKDJF\r\n";
@@ -187,7 +237,109 @@ fn parse_port() {
     assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
 
     let input = "PORT a1,a2,a3,a4,p1,p2\r\n";
-    assert_eq!(parse(input).unwrap(), Command::Port);
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "PORT 127,0,0,1,4\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "PORT 127,0,0,256,4,1\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "PORT 127,0,0,1,4,1\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Port {
+            addr: "127.0.0.1:1025".parse().unwrap()
+        })
+    );
+}
+
+#[test]
+fn parse_eprt() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "EPRT\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "EPRT |1|132.235.1.2|6275|\r\n",
+            expected: Ok(Command::Eprt {
+                addr: "132.235.1.2:6275".parse().unwrap(),
+            }),
+        },
+        Test {
+            input: "EPRT |2|1080::8:800:200C:417A|5282|\r\n",
+            expected: Ok(Command::Eprt {
+                addr: "[1080::8:800:200C:417A]:5282".parse().unwrap(),
+            }),
+        },
+        Test {
+            input: "EPRT !1!132.235.1.2!6275!\r\n",
+            expected: Ok(Command::Eprt {
+                addr: "132.235.1.2:6275".parse().unwrap(),
+            }),
+        },
+        Test {
+            input: "EPRT |3|132.235.1.2|6275|\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "EPRT |2|132.235.1.2|6275|\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "EPRT |1|132.235.1.2|6275\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "EPRT |1|132.235.1.2|70000|\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
+#[test]
+fn parse_epsv() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "EPSV\r\n",
+            expected: Ok(Command::Epsv { all: false, protocol: None }),
+        },
+        Test {
+            input: "EPSV ALL\r\n",
+            expected: Ok(Command::Epsv { all: true, protocol: None }),
+        },
+        Test {
+            input: "epsv all\r\n",
+            expected: Ok(Command::Epsv { all: true, protocol: None }),
+        },
+        Test {
+            input: "EPSV 2\r\n",
+            expected: Ok(Command::Epsv { all: false, protocol: Some(2) }),
+        },
+        Test {
+            input: "EPSV 3\r\n",
+            expected: Ok(Command::Epsv { all: false, protocol: Some(3) }),
+        },
+        Test {
+            input: "EPSV NONE\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
 }
 
 #[test]
@@ -195,32 +347,39 @@ fn parse_list() {
     struct Test {
         input: &'static str,
         expected_path: Option<&'static str>,
+        expected_options: Option<&'static str>,
     }
 
     let tests = [
         Test {
             input: "LIST\r\n",
             expected_path: None,
+            expected_options: None,
         },
         Test {
             input: "LIST tmp\r\n",
             expected_path: Some("tmp"),
+            expected_options: None,
         },
         Test {
             input: "LIST -la\r\n",
             expected_path: None,
+            expected_options: Some("-la"),
         },
         Test {
             input: "LIST -la tmp\r\n",
             expected_path: Some("tmp"),
+            expected_options: Some("-la"),
         },
         Test {
             input: "LIST -la -x tmp\r\n",
             expected_path: Some("tmp"),
+            expected_options: Some("-la -x"),
         },
         Test {
             input: "LIST -la -x tmp*\r\n",
             expected_path: Some("tmp*"),
+            expected_options: Some("-la -x"),
         },
     ];
 
@@ -228,7 +387,7 @@ fn parse_list() {
         assert_eq!(
             parse(test.input),
             Ok(Command::List {
-                options: None,
+                options: test.expected_options.map(|s| s.to_string()),
                 path: test.expected_path.map(|s| s.to_string()),
             })
         );
@@ -302,6 +461,106 @@ fn parse_opts() {
     );
 }
 
+#[test]
+fn parse_opts_mode_z() {
+    let input = "OPTS MODE Z LEVEL 9\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::ModeZ { level: 9 }
+        })
+    );
+
+    let input = "opts mode z level 1\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::ModeZ { level: 1 }
+        })
+    );
+
+    let input = "OPTS MODE Z LEVEL high\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "OPTS MODE Z\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+}
+
+#[test]
+fn parse_opts_hash() {
+    let input = "OPTS HASH\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::Hash { algorithm: None }
+        })
+    );
+
+    let input = "opts hash sha-512\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::Hash {
+                algorithm: Some("sha-512".to_string())
+            }
+        })
+    );
+}
+
+#[test]
+fn parse_opts_mlst() {
+    let input = "OPTS MLST type;size;\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::Mlst {
+                facts: Facts::TYPE | Facts::SIZE
+            }
+        })
+    );
+
+    // Selecting no facts at all is allowed
+    let input = "opts mlst\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Opts {
+            option: Opt::Mlst { facts: Facts::empty() }
+        })
+    );
+
+    let input = "OPTS MLSTX type;\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+}
+
+#[test]
+fn parse_mlsd_mlst() {
+    let input = "MLSD\r\n";
+    assert_eq!(parse(input), Ok(Command::Mlsd { path: None }));
+
+    let input = "MLSD some dir\r\n";
+    assert_eq!(parse(input), Ok(Command::Mlsd { path: Some("some dir".into()) }));
+
+    let input = "MLST\r\n";
+    assert_eq!(parse(input), Ok(Command::Mlst { path: None }));
+
+    let input = "MLST some_file\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Mlst {
+            path: Some("some_file".into())
+        })
+    );
+}
+
+#[test]
+fn parse_appe() {
+    let input = "APPE\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "APPE app.log\r\n";
+    assert_eq!(parse(input), Ok(Command::Appe { path: "app.log".into() }));
+}
+
 #[test]
 fn parse_dele() {
     let input = "DELE\r\n";
@@ -507,6 +766,92 @@ fn parse_mdtm() {
     }
 }
 
+#[test]
+fn parse_mfmt() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "MFMT 20210713101530 file.txt\r\n",
+            expected: Ok(Command::Mfmt {
+                modified: UNIX_EPOCH + Duration::from_secs(1_626_171_330),
+                file: "file.txt".into(),
+            }),
+        },
+        Test {
+            input: "MFMT 20210713101530.25 my file.txt\r\n",
+            expected: Ok(Command::Mfmt {
+                modified: UNIX_EPOCH + Duration::from_millis(1_626_171_330_250),
+                file: "my file.txt".into(),
+            }),
+        },
+        Test {
+            input: "MFMT 20210713101530\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "MFMT 2021-07-13 file.txt\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "MFMT 20211313101530 file.txt\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
+#[test]
+fn parse_hash() {
+    struct Test {
+        input: &'static str,
+        expected: Result<Command>,
+    }
+    let tests = [
+        Test {
+            input: "HASH\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "HASH my file.txt\r\n",
+            expected: Ok(Command::Hash { file: "my file.txt".into() }),
+        },
+        Test {
+            input: "RANG 10 20\r\n",
+            expected: Ok(Command::Rang { start: 10, end: 20 }),
+        },
+        Test {
+            input: "RANG 10\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+        Test {
+            input: "RANG 10 -1\r\n",
+            expected: Err(ParseErrorKind::InvalidCommand.into()),
+        },
+    ];
+    for test in tests.iter() {
+        assert_eq!(parse(test.input), test.expected);
+    }
+}
+
+#[test]
+fn parse_host() {
+    let input = "HOST\r\n";
+    assert_eq!(parse(input), Err(ParseError::from(ParseErrorKind::InvalidCommand)));
+
+    let input = "HOST ftp.example.com\r\n";
+    assert_eq!(
+        parse(input),
+        Ok(Command::Host {
+            host: "ftp.example.com".to_string()
+        })
+    );
+}
+
 #[test]
 fn parse_md5() {
     struct Test {
@@ -553,6 +898,13 @@ fn parse_site() {
                 arguments: "".to_string(),
             }),
         },
+        Test {
+            input: "SITE chmod 755 file.txt\r\n",
+            expected: Ok(Command::Site {
+                command_name: "CHMOD".to_string(),
+                arguments: "755 file.txt".to_string(),
+            }),
+        },
     ];
     for test in tests.iter() {
         assert_eq!(parse(test.input), test.expected);
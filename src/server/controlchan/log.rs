@@ -1,5 +1,5 @@
 use crate::server::{
-    controlchan::{error::ControlChanError, middleware::ControlChanMiddleware},
+    controlchan::{error::ControlChanError, middleware::EventHandler},
     Event, Reply,
 };
 
@@ -8,7 +8,7 @@ use async_trait::async_trait;
 // Control channel middleware that logs all control channel events
 pub struct LoggingMiddleware<Next>
 where
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     pub logger: slog::Logger,
     pub sequence_nr: u64,
@@ -16,9 +16,9 @@ where
 }
 
 #[async_trait]
-impl<Next> ControlChanMiddleware for LoggingMiddleware<Next>
+impl<Next> EventHandler for LoggingMiddleware<Next>
 where
-    Next: ControlChanMiddleware,
+    Next: EventHandler,
 {
     async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
         self.sequence_nr += 1;
@@ -6,8 +6,10 @@ use async_trait::async_trait;
 use std::{future::Future, pin::Pin};
 
 // Defines the requirements for code that wants to intercept and do something with control channel events.
+// The built-in middleware (authentication, FTPS enforcement, logging and metrics) is chained with it. The
+// middleware of library users works on requests instead, see `crate::middleware::ControlChanMiddleware`.
 #[async_trait]
-pub trait ControlChanMiddleware: Send + Sync {
+pub trait EventHandler: Send + Sync {
     // Handles the specified `Event` and returns a `Reply` for the user or a `ControlChanError` if
     // some unexpected error occurred.
     async fn handle(&mut self, e: Event) -> Result<Reply, ControlChanError>;
@@ -20,7 +22,7 @@ pub trait ControlChanMiddleware: Send + Sync {
 
 // Allows plain functions to be middleware. Experimental...
 #[async_trait]
-impl<Function> ControlChanMiddleware for Function
+impl<Function> EventHandler for Function
 where
     Function: Send + Sync + Fn(Event) -> Pin<Box<dyn Future<Output = Result<Reply, ControlChanError>> + Send>>,
 {
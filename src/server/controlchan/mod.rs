@@ -11,15 +11,16 @@ pub(super) mod commands;
 mod auth;
 mod codecs;
 mod control_loop;
-mod error;
+pub(crate) mod error;
 mod ftps;
 mod line_parser;
 mod log;
 mod middleware;
+mod permission;
 
 use command::Command;
 pub(crate) use control_loop::{spawn as spawn_loop, Config as LoopConfig};
 pub(crate) use error::{ControlChanError, ControlChanErrorKind};
 pub(crate) use event::Event;
-pub(crate) use middleware::ControlChanMiddleware;
+pub(crate) use middleware::EventHandler;
 pub(crate) use reply::{Reply, ReplyCode};
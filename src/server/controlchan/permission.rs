@@ -0,0 +1,100 @@
+use crate::{
+    auth::{Permissions, UserDetail},
+    server::{
+        controlchan::{error::ControlChanError, middleware::EventHandler},
+        ftpserver::acl::{effective_permissions, normalize},
+        session::SharedSession,
+        {Command, Event, Reply, ReplyCode},
+    },
+    storage::{Metadata, StorageBackend},
+};
+
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+// PermissionMiddleware refuses the commands that the logged in user doesn't have permission for
+// before they reach the storage back-end. The permissions of the user are limited by the access
+// control list for the path the command works on.
+pub struct PermissionMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: EventHandler,
+{
+    pub session: SharedSession<Storage, User>,
+    pub next: Next,
+}
+
+#[async_trait]
+impl<Storage, User, Next> EventHandler for PermissionMiddleware<Storage, User, Next>
+where
+    User: UserDetail + 'static,
+    Storage: StorageBackend<User> + 'static,
+    Storage::Metadata: Metadata,
+    Next: EventHandler,
+{
+    async fn handle(&mut self, event: Event) -> Result<Reply, ControlChanError> {
+        let required = match &event {
+            Event::Command(command) => required_permission(command),
+            Event::InternalMsg(_) => None,
+        };
+        // Files in a dropbox can't be changed once they're uploaded.
+        let changes_file = matches!(event, Event::Command(Command::Appe { .. }) | Event::Command(Command::Mfmt { .. }));
+        if let Some((required, path)) = required {
+            let allowed = async {
+                let session = self.session.lock().await;
+                // Not logged in users are stopped by the AuthMiddleware.
+                let user = match &*session.user {
+                    Some(user) => user,
+                    None => return true,
+                };
+                let path = match path {
+                    Some(path) => normalize(&session.cwd.join(path)),
+                    None => normalize(&session.cwd),
+                };
+                let username = session.username.as_deref().unwrap_or_default();
+                if let (true, Some(acl)) = (changes_file, &session.acl) {
+                    if acl.dropbox_collision(username, user.groups(), &path).is_some() {
+                        return false;
+                    }
+                }
+                let permissions = effective_permissions(session.acl.as_deref(), user, username, &path);
+                // Changing into a directory only needs some permission on it.
+                if required.is_empty() {
+                    !permissions.is_empty()
+                } else {
+                    permissions.contains(required)
+                }
+            }
+            .await;
+            if !allowed {
+                return Ok(Reply::new(ReplyCode::FileError, "Permission denied"));
+            }
+        }
+        self.next.handle(event).await
+    }
+}
+
+// Returns the permission a command needs and the path it needs it on. No path means the current
+// working directory.
+fn required_permission(command: &Command) -> Option<(Permissions, Option<PathBuf>)> {
+    match command {
+        Command::List { path, .. } | Command::Nlst { path } | Command::Mlsd { path } | Command::Mlst { path } => {
+            Some((Permissions::LIST, path.as_ref().map(PathBuf::from)))
+        }
+        Command::Stat { path: Some(path) } => Some((Permissions::LIST, Some(PathBuf::from(String::from_utf8_lossy(path).as_ref())))),
+        Command::Size { file } | Command::Mdtm { file } => Some((Permissions::LIST, Some(file.clone()))),
+        Command::Retr { path } => Some((Permissions::READ, Some(PathBuf::from(path)))),
+        Command::Stor { path } | Command::Appe { path } => Some((Permissions::WRITE, Some(PathBuf::from(path)))),
+        Command::Stou => Some((Permissions::WRITE, None)),
+        Command::Mfmt { file, .. } => Some((Permissions::WRITE, Some(file.clone()))),
+        Command::Dele { path } | Command::Rmd { path } => Some((Permissions::DELETE, Some(PathBuf::from(path)))),
+        Command::Mkd { path } => Some((Permissions::MKDIR, Some(path.clone()))),
+        Command::Rnfr { file } | Command::Rnto { file } => Some((Permissions::RENAME, Some(file.clone()))),
+        Command::Md5 { file } | Command::Hash { file } => Some((Permissions::MD5, Some(file.clone()))),
+        Command::Cwd { path } => Some((Permissions::empty(), Some(path.clone()))),
+        Command::Cdup => Some((Permissions::empty(), Some(PathBuf::from("..")))),
+        _ => None,
+    }
+}
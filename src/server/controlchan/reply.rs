@@ -1,9 +1,22 @@
 /// A reply to the FTP client
 #[derive(Debug, Clone, PartialEq)]
 pub enum Reply {
+    /// Nothing is sent. Used when the reply is sent later e.g. after a data transfer.
     None,
-    CodeAndMsg { code: ReplyCode, msg: String },
-    MultiLine { code: ReplyCode, lines: Vec<String> },
+    /// A single line reply
+    CodeAndMsg {
+        /// The reply code
+        code: ReplyCode,
+        /// The text following the code
+        msg: String,
+    },
+    /// A multi-line reply
+    MultiLine {
+        /// The reply code
+        code: ReplyCode,
+        /// The lines of text
+        lines: Vec<String>,
+    },
 }
 
 /// The reply codes according to RFC 959.
@@ -40,64 +53,107 @@ pub enum Reply {
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u32)]
 #[allow(dead_code)]
+#[non_exhaustive]
 pub enum ReplyCode {
-    NoReply = 0,
-
-    GroupPreliminaryReply = 1,
-    GroupPositiveCompletion = 2,
-
+    /// 110 Restart marker reply
     RestartMarker = 110,
+    /// 120 Service ready in nnn minutes
     InNMinutes = 120,
+    /// 125 Data connection already open; transfer starting
     ConnectionAlreadyOpen = 125,
+    /// 150 File status okay; about to open data connection
     FileStatusOkay = 150,
 
+    /// 200 Command okay
     CommandOkay = 200,
+    /// 202 Command not implemented, superfluous at this site
     CommandOkayNotImplemented = 202,
+    /// 211 System status, or system help reply
     SystemStatus = 211,
+    /// 212 Directory status
     DirectoryStatus = 212,
+    /// 213 File status
     FileStatus = 213,
+    /// 214 Help message
     HelpMessage = 214,
+    /// 215 NAME system type
     SystemType = 215,
+    /// 220 Service ready for new user
     ServiceReady = 220,
+    /// 221 Service closing control connection
     ClosingControlConnection = 221,
+    /// 225 Data connection open; no transfer in progress
     DataConnectionOpen = 225,
+    /// 226 Closing data connection
     ClosingDataConnection = 226,
+    /// 227 Entering Passive Mode
     EnteringPassiveMode = 227,
+    /// 229 Entering Extended Passive Mode
     EnteringExtendedPassiveMode = 229,
+    /// 230 User logged in, proceed
     UserLoggedIn = 230,
+    /// 232 User logged in, authorized by security data exchange
     UserLoggedInViaCert = 232,
+    /// 234 Security data exchange complete
     AuthOkayNoDataNeeded = 234,
+    /// 250 Requested file action okay, completed
     FileActionOkay = 250,
+    /// 257 "PATHNAME" created
     DirCreated = 257,
 
+    /// 331 User name okay, need password
     NeedPassword = 331,
+    /// 332 Need account for login
     NeedAccount = 332,
+    /// 350 Requested file action pending further information
     FileActionPending = 350,
 
+    /// 421 Service not available, closing control connection
     ServiceNotAvailable = 421,
+    /// 425 Can't open data connection
     CantOpenDataConnection = 425,
+    /// 426 Connection closed; transfer aborted
     ConnectionClosed = 426,
+    /// 450 Requested file action not taken
     TransientFileError = 450,
+    /// 451 Requested action aborted: local error in processing
     LocalError = 451,
+    /// 452 Requested action not taken. Insufficient storage space
     OutOfSpace = 452,
 
+    /// 500 Syntax error, command unrecognized
     CommandSyntaxError = 500,
+    /// 501 Syntax error in parameters or arguments
     ParameterSyntaxError = 501,
+    /// 502 Command not implemented
     CommandNotImplemented = 502,
+    /// 503 Bad sequence of commands
     BadCommandSequence = 503,
+    /// 504 Command not implemented for that parameter
     CommandNotImplementedForParameter = 504,
+    /// 522 Network protocol not supported
+    NetworkProtocolNotSupported = 522,
+    /// 530 Not logged in
     NotLoggedIn = 530,
+    /// 532 Need account for storing files
     NeedAccountToStore = 532,
+    /// 534 Request denied for policy reasons
     FtpsRequired = 534, // Could Not Connect to Server - Policy Requires SSL
+    /// 550 Requested action not taken. File unavailable
     FileError = 550,
+    /// 551 Requested action aborted: page type unknown
     PageTypeUnknown = 551,
+    /// 552 Requested file action aborted. Exceeded storage allocation
     ExceededStorageAllocation = 552,
+    /// 553 Requested action not taken. File name not allowed
     BadFileName = 553,
 
+    /// 533 Command protection level denied for policy reasons
     Resp533 = 533,
 }
 
 impl Reply {
+    /// Creates a single line reply
     pub fn new(code: ReplyCode, message: &str) -> Self {
         Reply::CodeAndMsg {
             code,
@@ -105,10 +161,12 @@ impl Reply {
         }
     }
 
+    /// Creates a single line reply from an owned string
     pub fn new_with_string(code: ReplyCode, msg: String) -> Self {
         Reply::CodeAndMsg { code, msg }
     }
 
+    /// Creates a multi-line reply
     pub fn new_multiline<I>(code: ReplyCode, lines: I) -> Self
     where
         I: IntoIterator,
@@ -120,7 +178,7 @@ impl Reply {
         }
     }
 
-    // A no-reply
+    /// Creates a reply that sends nothing
     pub fn none() -> Self {
         Reply::None
     }
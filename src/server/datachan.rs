@@ -1,24 +1,55 @@
 //! Contains code pertaining to the FTP *data* channel
 
 use super::{
+    ascii::{AsciiReader, AsciiWriter},
     chancomms::{ControlChanMsg, DataChanMsg},
+    charset,
+    controlchan::commands::TypeParam,
+    ftpserver::{
+        acl::{self, Acl},
+        options::PartialUploadPolicy,
+    },
+    mlsx::{self, Facts},
     tls::FtpsConfig,
+    transfer::{MonitorGuard, Transfer},
 };
 use crate::server::session::SharedSession;
 use crate::{
     auth::UserDetail,
-    storage::{Error, ErrorKind, Metadata, StorageBackend},
+    storage::{Error, ErrorKind, ListFilter, ListOptions, Metadata, StorageBackend},
 };
 
-use crate::server::chancomms::DataChanCmd;
+use crate::server::{chancomms::DataChanCmd, Reply, ReplyCode};
+use async_compression::{
+    tokio::{bufread::ZlibDecoder, write::ZlibEncoder},
+    Level,
+};
+use encoding_rs::Encoding;
 use futures::{
     channel::mpsc::{Receiver, Sender},
     prelude::*,
 };
-use std::{path::PathBuf, sync::Arc};
-use tokio::io::AsyncWriteExt;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
 use tokio_rustls::TlsAcceptor;
 
+// How long we wait for the client to accept an active mode data connection.
+const ACTIVE_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The socket of a data connection. In passive mode the client already connected to us by the time
+/// the data loop starts. In active mode (`PORT`/`EPRT`) we only know the client's address and
+/// connect to it once a data command arrives from the control channel.
+#[derive(Debug)]
+pub enum DataSocket {
+    /// An established connection
+    Connected(TcpStream),
+    /// The address of the client to connect to
+    Pending(SocketAddr),
+}
+
 #[derive(Debug)]
 struct DataCommandExecutor<Storage, User>
 where
@@ -27,12 +58,28 @@ where
     User: UserDetail,
 {
     pub user: Arc<Option<User>>,
-    pub socket: tokio::net::TcpStream,
+    pub socket: DataSocket,
     pub control_msg_tx: Sender<ControlChanMsg>,
     pub storage: Arc<Storage>,
     pub cwd: PathBuf,
     pub start_pos: u64,
     pub ftps_mode: FtpsConfig,
+    // The deflate compression level if the transfer is done in `MODE Z`.
+    pub compression: Option<u32>,
+    // Tells if files are transferred in ASCII mode (`TYPE A`).
+    pub ascii: bool,
+    // The legacy charset to convert directory listings to if the client switched UTF-8 off.
+    pub charset: Option<&'static Encoding>,
+    // What to do with the partial file if a `STOR` is aborted.
+    pub partial_upload_policy: PartialUploadPolicy,
+    // Hides the entries of directory listings that the access control list doesn't let the user see.
+    pub list_filter: Option<ListFilter>,
+    // The user name and access control list, to tell what the user may do with the entries of
+    // `MLSD` listings.
+    pub username: String,
+    pub acl: Option<Arc<Acl>>,
+    // The progress of the file transfer if the command is one.
+    pub transfer: Option<Transfer>,
     pub logger: slog::Logger,
     pub data_cmd_rx: Option<Receiver<DataChanCmd>>,
     pub data_abort_rx: Option<Receiver<()>>,
@@ -47,21 +94,90 @@ where
     async fn execute(mut self, session_arc: SharedSession<Storage, User>) {
         let mut data_cmd_rx = self.data_cmd_rx.take().unwrap().fuse();
         let mut data_abort_rx = self.data_abort_rx.take().unwrap().fuse();
+        let mut control_msg_tx = self.control_msg_tx.clone();
+        let logger = self.logger.clone();
         let mut timeout_delay = Box::pin(tokio::time::sleep(std::time::Duration::from_secs(5 * 60)));
         // TODO: Use configured timeout
-        tokio::select! {
+        let aborted = tokio::select! {
             Some(command) = data_cmd_rx.next() => {
-                self.handle_incoming(DataChanMsg::ExternalCommand(command)).await;
+                self.read_transfer_settings(&session_arc).await;
+                let _monitor_guard = self.start_transfer(&command, &session_arc).await;
+                let partial_upload = self.partial_upload(&command);
+                let storage = Arc::clone(&self.storage);
+                let user = Arc::clone(&self.user);
+                // An ABOR drops the running command which closes the data connection and stops the copy.
+                tokio::select! {
+                    _ = self.handle_incoming(DataChanMsg::ExternalCommand(command)) => false,
+                    Some(_) = data_abort_rx.next() => {
+                        slog::info!(logger, "Data transfer aborted");
+                        if let Some(path) = partial_upload {
+                            if let Err(err) = storage.del((*user).as_ref().unwrap(), path).await {
+                                slog::warn!(logger, "Could not delete partial upload after ABOR: {}", err);
+                            }
+                        }
+                        if let Err(err) = control_msg_tx.send(ControlChanMsg::TransferAborted).await {
+                            slog::error!(logger, "Could not notify control channel of aborted transfer: {}", err);
+                        }
+                        true
+                    }
+                }
             },
             Some(_) = data_abort_rx.next() => {
                 self.handle_incoming(DataChanMsg::Abort).await;
+                true
             },
             _ = &mut timeout_delay => {
-                slog::warn!(self.logger, "Data channel connection timed out");
+                slog::warn!(logger, "Data channel connection timed out");
+                false
             }
         };
         let mut session = session_arc.lock().await;
         session.data_busy = false;
+        session.transfer = None;
+        // An ABOR may have come in just as the command completed. The receiver is dropped while we
+        // hold the session lock so that the ABOR command knows whether we'll still reply to it.
+        let aborted = aborted || matches!(data_abort_rx.next().now_or_never(), Some(Some(_)));
+        drop(data_abort_rx);
+        drop(session);
+        if aborted {
+            let reply = Reply::new(ReplyCode::ClosingDataConnection, "Closed data channel");
+            if let Err(err) = control_msg_tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+                slog::error!(logger, "Could not notify control channel of ABOR result: {}", err);
+            }
+        }
+    }
+
+    // Copies the transfer settings from the session. Clients may change them after PASV or EPSV, so
+    // they are read when the command arrives rather than when the data connection is set up.
+    async fn read_transfer_settings(&mut self, session_arc: &SharedSession<Storage, User>) {
+        let session = session_arc.lock().await;
+        self.compression = if session.mode_z { Some(session.mode_z_level) } else { None };
+        self.ascii = session.data_type == TypeParam::Ascii;
+        self.charset = session.charset.legacy();
+    }
+
+    // Publishes the progress of a file transfer to the session and the transfer monitor, if any.
+    async fn start_transfer(&mut self, command: &DataChanCmd, session_arc: &SharedSession<Storage, User>) -> Option<MonitorGuard> {
+        let (name, path) = match command {
+            DataChanCmd::Retr { path } => ("RETR", path),
+            DataChanCmd::Stor { path, .. } => ("STOR", path),
+            DataChanCmd::Appe { path } => ("APPE", path),
+            _ => return None,
+        };
+        let mut session = session_arc.lock().await;
+        let transfer = Transfer::new(session.username.clone(), session.source, name, self.cwd.join(path));
+        session.transfer = Some(transfer.clone());
+        let guard = session.transfer_monitor.as_ref().map(|monitor| monitor.register(transfer.clone()));
+        self.transfer = Some(transfer);
+        guard
+    }
+
+    // Returns the file to delete if the given command is an upload that gets aborted.
+    fn partial_upload(&self, command: &DataChanCmd) -> Option<PathBuf> {
+        match command {
+            DataChanCmd::Stor { path, .. } if self.start_pos == 0 && self.partial_upload_policy == PartialUploadPolicy::Delete => Some(self.cwd.join(path)),
+            _ => None,
+        }
     }
 
     #[tracing_attributes::instrument]
@@ -78,20 +194,42 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn execute_command(self, cmd: DataChanCmd) {
+    async fn execute_command(mut self, cmd: DataChanCmd) {
+        if let DataSocket::Pending(addr) = self.socket {
+            match tokio::time::timeout(ACTIVE_CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => self.socket = DataSocket::Connected(stream),
+                Ok(Err(err)) => return self.notify_connect_failure(addr, err.to_string()).await,
+                Err(_) => return self.notify_connect_failure(addr, String::from("timed out")).await,
+            }
+        }
         match cmd {
             DataChanCmd::Retr { path } => {
                 self.exec_retr(path).await;
             }
-            DataChanCmd::Stor { path } => {
-                self.exec_stor(path).await;
+            DataChanCmd::Stor { path, create_new } => {
+                self.exec_stor(path, create_new).await;
+            }
+            DataChanCmd::Appe { path } => {
+                self.exec_appe(path).await;
             }
-            DataChanCmd::List { path, .. } => {
-                self.exec_list(path).await;
+            DataChanCmd::List { path, options } => {
+                self.exec_list(path, options).await;
             }
             DataChanCmd::Nlst { path } => {
                 self.exec_nlst(path).await;
             }
+            DataChanCmd::Mlsd { path, facts } => {
+                self.exec_mlsd(path, facts).await;
+            }
+        }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn notify_connect_failure(mut self, addr: SocketAddr, reason: String) {
+        slog::warn!(self.logger, "Could not open active mode data connection to {}: {}", addr, reason);
+        let reply = Reply::new(ReplyCode::CantOpenDataConnection, "Can't open data connection");
+        if let Err(err) = self.control_msg_tx.send(ControlChanMsg::CommandChannelReply(reply)).await {
+            slog::error!(self.logger, "Could not notify control channel of failed data connection: {}", err);
         }
     }
 
@@ -100,7 +238,13 @@ where
         let path = self.cwd.join(path);
         let mut tx_sending: Sender<ControlChanMsg> = self.control_msg_tx.clone();
         let mut tx_error: Sender<ControlChanMsg> = self.control_msg_tx.clone();
-        let mut output = Self::writer(self.socket, self.ftps_mode).await;
+        let mut output = Self::writer(self.socket, self.ftps_mode, self.compression).await;
+        if self.ascii {
+            output = Box::new(AsciiWriter::new(output));
+        }
+        if let Some(transfer) = &self.transfer {
+            output = Box::new(transfer.count(output));
+        }
         let get_result = self.storage.get_into((*self.user).as_ref().unwrap(), path, self.start_pos, &mut output).await;
         match get_result {
             Ok(bytes_copied) => {
@@ -121,19 +265,23 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn exec_stor(self, path: String) {
+    async fn exec_stor(self, path: String, create_new: bool) {
         let path = self.cwd.join(path);
         let mut tx_ok = self.control_msg_tx.clone();
         let mut tx_error = self.control_msg_tx.clone();
-        let put_result = self
-            .storage
-            .put(
-                (*self.user).as_ref().unwrap(),
-                Self::reader(self.socket, self.ftps_mode).await,
-                path,
-                self.start_pos,
-            )
-            .await;
+        let mut input = Self::reader(self.socket, self.ftps_mode, self.compression).await;
+        if self.ascii {
+            input = Box::new(AsciiReader::new(input));
+        }
+        if let Some(transfer) = &self.transfer {
+            input = Box::new(transfer.count(input));
+        }
+        let user = (*self.user).as_ref().unwrap();
+        let put_result = if create_new {
+            self.storage.put_new(user, input, path).await
+        } else {
+            self.storage.put(user, input, path, self.start_pos).await
+        };
         match put_result {
             Ok(bytes) => {
                 if let Err(err) = tx_ok.send(ControlChanMsg::WrittenData { bytes }).await {
@@ -149,7 +297,33 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn exec_list(self, path: Option<String>) {
+    async fn exec_appe(self, path: String) {
+        let path = self.cwd.join(path);
+        let mut tx_ok = self.control_msg_tx.clone();
+        let mut tx_error = self.control_msg_tx.clone();
+        let mut input = Self::reader(self.socket, self.ftps_mode, self.compression).await;
+        if self.ascii {
+            input = Box::new(AsciiReader::new(input));
+        }
+        if let Some(transfer) = &self.transfer {
+            input = Box::new(transfer.count(input));
+        }
+        match self.storage.append((*self.user).as_ref().unwrap(), input, path).await {
+            Ok(bytes) => {
+                if let Err(err) = tx_ok.send(ControlChanMsg::WrittenData { bytes }).await {
+                    slog::error!(self.logger, "Could not notify control channel of successful APPE: {}", err);
+                }
+            }
+            Err(err) => {
+                if let Err(err) = tx_error.send(ControlChanMsg::StorageError(err)).await {
+                    slog::error!(self.logger, "Could not notify control channel of error with APPE: {}", err);
+                }
+            }
+        }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn exec_list(self, path: Option<String>, mut options: ListOptions) {
         let path = match path {
             Some(path) => {
                 if path == "." {
@@ -161,12 +335,13 @@ where
             None => self.cwd.clone(),
         };
         let mut tx_ok = self.control_msg_tx.clone();
-        let mut output = Self::writer(self.socket, self.ftps_mode).await;
-        let result = match self.storage.list_fmt((*self.user).as_ref().unwrap(), path).await {
+        options.filter = self.list_filter.clone();
+        let mut output = Self::writer(self.socket, self.ftps_mode, self.compression).await;
+        let result = match self.storage.list_fmt_with_options((*self.user).as_ref().unwrap(), path, &options).await {
             Ok(cursor) => {
                 slog::debug!(self.logger, "Copying future for List");
                 let mut input = cursor;
-                match tokio::io::copy(&mut input, &mut output).await {
+                match Self::copy_listing(&mut input, &mut output, self.charset).await {
                     Ok(_) => Ok(ControlChanMsg::DirectorySuccessfullyListed),
                     Err(e) => Err(e),
                 }
@@ -200,10 +375,18 @@ where
         };
         let mut tx_ok = self.control_msg_tx.clone();
         let mut tx_error = self.control_msg_tx.clone();
-        match self.storage.nlst((*self.user).as_ref().unwrap(), path).await {
+        match self.storage.nlst((*self.user).as_ref().unwrap(), &path).await {
             Ok(mut input) => {
-                let mut output = Self::writer(self.socket, self.ftps_mode).await;
-                match tokio::io::copy(&mut input, &mut output).await {
+                if let Some(filter) = &self.list_filter {
+                    let listing = String::from_utf8_lossy(input.get_ref())
+                        .lines()
+                        .filter(|name| filter.shows(path.join(name)))
+                        .map(|name| format!("{}\r\n", name))
+                        .collect::<String>();
+                    input = std::io::Cursor::new(listing.into_bytes());
+                }
+                let mut output = Self::writer(self.socket, self.ftps_mode, self.compression).await;
+                match Self::copy_listing(&mut input, &mut output, self.charset).await {
                     Ok(_) => {
                         if let Err(err) = output.shutdown().await {
                             slog::warn!(self.logger, "Could not shutdown output stream during NLIST: {}", err);
@@ -224,34 +407,117 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn writer(socket: tokio::net::TcpStream, ftps_mode: FtpsConfig) -> Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> {
-        match ftps_mode {
+    async fn exec_mlsd(self, path: Option<String>, facts: Facts) {
+        let path = match path {
+            Some(path) => self.cwd.join(path),
+            None => self.cwd.clone(),
+        };
+        let mut tx_ok = self.control_msg_tx.clone();
+        let mut tx_error = self.control_msg_tx.clone();
+        let user = (*self.user).as_ref().unwrap();
+        match self.storage.list(user, &path).await {
+            Ok(list) => {
+                let listing = list
+                    .iter()
+                    .filter(|fi| match &self.list_filter {
+                        Some(filter) => filter.shows(path.join(fi.path.file_name().unwrap_or_default())),
+                        None => true,
+                    })
+                    .map(|fi| {
+                        let name = fi.path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")).to_string_lossy();
+                        let permissions = acl::effective_permissions(self.acl.as_deref(), user, &self.username, &path.join(&*name));
+                        format!("{}\r\n", mlsx::format_entry(&name, &fi.metadata, permissions, facts))
+                    })
+                    .collect::<String>();
+                let bytes = match self.charset {
+                    Some(encoding) => charset::encode_listing(encoding, &listing),
+                    None => listing.into_bytes(),
+                };
+                let mut output = Self::writer(self.socket, self.ftps_mode, self.compression).await;
+                match output.write_all(&bytes).await {
+                    Ok(_) => {
+                        if let Err(err) = output.shutdown().await {
+                            slog::warn!(self.logger, "Could not shutdown output stream during MLSD: {}", err);
+                        }
+                        if let Err(err) = tx_ok.send(ControlChanMsg::DirectorySuccessfullyListed).await {
+                            slog::error!(self.logger, "Could not notify control channel of successful MLSD: {}", err);
+                        }
+                    }
+                    Err(err) => slog::warn!(self.logger, "Could not write directory listing during MLSD: {}", err),
+                }
+            }
+            Err(err) => {
+                if let Err(err) = tx_error.send(ControlChanMsg::StorageError(err)).await {
+                    slog::warn!(self.logger, "Could not notify control channel of error with MLSD: {}", err);
+                }
+            }
+        }
+    }
+
+    // Copies a directory listing to the client, converting it to the given legacy charset if any.
+    async fn copy_listing<R, W>(input: &mut R, output: &mut W, charset: Option<&'static Encoding>) -> std::io::Result<u64>
+    where
+        R: tokio::io::AsyncRead + Unpin + ?Sized,
+        W: tokio::io::AsyncWrite + Unpin + ?Sized,
+    {
+        match charset {
+            Some(encoding) => {
+                let mut listing = String::new();
+                input.read_to_string(&mut listing).await?;
+                let bytes = charset::encode_listing(encoding, &listing);
+                output.write_all(&bytes).await?;
+                Ok(bytes.len() as u64)
+            }
+            None => tokio::io::copy(input, output).await,
+        }
+    }
+
+    #[tracing_attributes::instrument]
+    async fn writer(socket: DataSocket, ftps_mode: FtpsConfig, compression: Option<u32>) -> Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync> {
+        let socket = match socket {
+            DataSocket::Connected(socket) => socket,
+            DataSocket::Pending(_) => panic!("Illegal state"),
+        };
+        let output = match ftps_mode {
             FtpsConfig::Off => Box::new(socket) as Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync>,
             FtpsConfig::Building { .. } => panic!("Illegal state"),
             FtpsConfig::On { tls_config } => {
                 let io = async move {
-                    let acceptor: TlsAcceptor = tls_config.into();
+                    let acceptor: TlsAcceptor = tls_config.current().into();
                     acceptor.accept(socket).await.unwrap()
                 }
                 .await;
                 Box::new(io) as Box<dyn tokio::io::AsyncWrite + Send + Unpin + Sync>
             }
+        };
+        // In MODE Z the compression happens before encryption.
+        match compression {
+            Some(level) => Box::new(ZlibEncoder::with_quality(output, Level::Precise(level))),
+            None => output,
         }
     }
 
     #[tracing_attributes::instrument]
-    async fn reader(socket: tokio::net::TcpStream, ftps_mode: FtpsConfig) -> Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> {
-        match ftps_mode {
+    async fn reader(socket: DataSocket, ftps_mode: FtpsConfig, compression: Option<u32>) -> Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync> {
+        let socket = match socket {
+            DataSocket::Connected(socket) => socket,
+            DataSocket::Pending(_) => panic!("Illegal state"),
+        };
+        let input = match ftps_mode {
             FtpsConfig::Off => Box::new(socket) as Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync>,
             FtpsConfig::Building { .. } => panic!("Illegal state"),
             FtpsConfig::On { tls_config } => {
                 let io = async move {
-                    let acceptor: TlsAcceptor = tls_config.into();
+                    let acceptor: TlsAcceptor = tls_config.current().into();
                     acceptor.accept(socket).await.unwrap()
                 }
                 .await;
                 Box::new(io) as Box<dyn tokio::io::AsyncRead + Send + Unpin + Sync>
             }
+        };
+        match compression {
+            Some(_) => Box::new(ZlibDecoder::new(BufReader::new(input))),
+            None => input,
         }
     }
 }
@@ -262,9 +528,9 @@ where
 ///
 /// logger: logger set up with needed context for use by the data channel.
 /// session_arc: the user session that is also shared with the control channel.
-/// socket: the data socket we'll be working with.
+/// socket: the data socket we'll be working with or, in active mode, the address to connect to.
 #[tracing_attributes::instrument]
-pub async fn spawn_processing<Storage, User>(logger: slog::Logger, session_arc: SharedSession<Storage, User>, mut socket: tokio::net::TcpStream)
+pub async fn spawn_processing<Storage, User>(logger: slog::Logger, session_arc: SharedSession<Storage, User>, mut socket: DataSocket)
 where
     Storage: StorageBackend<User> + 'static,
     Storage::Metadata: Metadata,
@@ -276,33 +542,36 @@ where
     let command_executor = {
         let mut session = session_arc.lock().await;
 
-        match socket.peer_addr() {
-            Ok(datachan_addr) => {
-                let controlcahn_ip = session.source.ip();
-                if controlcahn_ip != datachan_addr.ip() {
-                    if let Err(err) = socket.shutdown().await {
-                        slog::error!(
-                            logger,
-                            "Couldn't close datachannel for ip ({}) that does not match the ip({}) of the control channel.\n{:?}",
-                            datachan_addr.ip(),
-                            controlcahn_ip,
-                            err
-                        )
-                    } else {
-                        slog::warn!(
-                            logger,
-                            "Closing datachannel for ip ({}) that does not match the ip({}) of the control channel.",
-                            datachan_addr.ip(),
-                            controlcahn_ip
-                        )
+        // In active mode the client address was already checked by the PORT/EPRT command.
+        if let DataSocket::Connected(stream) = &mut socket {
+            match stream.peer_addr() {
+                Ok(datachan_addr) => {
+                    let controlcahn_ip = session.source.ip();
+                    if controlcahn_ip != datachan_addr.ip() {
+                        if let Err(err) = stream.shutdown().await {
+                            slog::error!(
+                                logger,
+                                "Couldn't close datachannel for ip ({}) that does not match the ip({}) of the control channel.\n{:?}",
+                                datachan_addr.ip(),
+                                controlcahn_ip,
+                                err
+                            )
+                        } else {
+                            slog::warn!(
+                                logger,
+                                "Closing datachannel for ip ({}) that does not match the ip({}) of the control channel.",
+                                datachan_addr.ip(),
+                                controlcahn_ip
+                            )
+                        }
+                        return;
                     }
+                }
+                Err(err) => {
+                    slog::error!(logger, "Couldn't determine data channel address.\n{:?}", err);
                     return;
                 }
             }
-            Err(err) => {
-                slog::error!(logger, "Couldn't determine data channel address.\n{:?}", err);
-                return;
-            }
         }
 
         let username = session.username.as_ref().cloned().unwrap_or_else(|| String::from("unknown"));
@@ -337,6 +606,17 @@ where
             cwd: session.cwd.clone(),
             start_pos: session.start_pos,
             ftps_mode,
+            compression: None,
+            ascii: false,
+            charset: None,
+            partial_upload_policy: session.partial_upload_policy,
+            list_filter: session
+                .acl
+                .clone()
+                .map(|acl| acl::list_filter(acl, session.user.clone(), session.username.clone().unwrap_or_default())),
+            username: session.username.clone().unwrap_or_default(),
+            acl: session.acl.clone(),
+            transfer: None,
             logger,
             data_abort_rx: Some(data_abort_rx),
             data_cmd_rx: Some(data_cmd_rx),
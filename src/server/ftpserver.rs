@@ -1,31 +1,44 @@
+pub mod acl;
 pub mod error;
+pub mod middleware;
 pub mod options;
+pub mod site;
+pub(crate) mod virtual_host;
 
 use super::{
     chancomms::{ControlChanMsg, ProxyLoopMsg, ProxyLoopReceiver, ProxyLoopSender},
-    controlchan,
-    datachan::spawn_processing,
-    ftpserver::{error::ServerError, options::FtpsRequired, options::SiteMd5},
-    tls::FtpsConfig,
+    charset, controlchan,
+    datachan::{spawn_processing, DataSocket},
+    ftpserver::{
+        acl::Acl,
+        error::ServerError,
+        options::{ActivePassiveMode, FtpsRequired, Listener, OverwritePolicy, PartialUploadPolicy, Shutdown, SiteMd5},
+    },
+    shutdown,
+    tls::{FtpsConfig, SniCertificate, TlsReloader},
+    transfer::TransferMonitor,
 };
 use crate::{
     auth::{anonymous::AnonymousAuthenticator, Authenticator, UserDetail},
     server::{
         proxy_protocol::{get_peer_from_proxy_header, ConnectionTuple, ProxyMode, ProxyProtocolSwitchboard},
         session::SharedSession,
-        Reply,
+        Reply, ReplyCode,
     },
-    storage::{Metadata, StorageBackend},
+    storage::{self, Metadata, StorageBackend, FEATURE_RENAME},
 };
 
 use crate::options::{FtpsClientAuth, TlsFlags};
 use crate::server::tls;
 use futures::{channel::mpsc::channel, SinkExt};
+use middleware::ControlChanMiddleware;
 use options::{PassiveHost, DEFAULT_GREETING, DEFAULT_IDLE_SESSION_TIMEOUT_SECS};
+use site::SiteCommand;
 use slog::*;
-use std::{fmt::Debug, net::IpAddr, ops::Range, path::PathBuf, sync::Arc, time::Duration};
+use std::{collections::HashMap, fmt::Debug, future::Future, net::SocketAddr, ops::Range, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
 use tokio::io::AsyncWriteExt;
-use tokio_stream::StreamExt;
+use tokio_stream::{Stream, StreamExt};
+use virtual_host::VirtualHost;
 
 /// An instance of an FTP(S) server. It aggregates an [`Authenticator`](crate::auth::Authenticator)
 /// implementation that will be used for authentication, and a [`StorageBackend`](crate::storage::StorageBackend)
@@ -59,18 +72,36 @@ where
     authenticator: Arc<dyn Authenticator<User>>,
     passive_ports: Range<u16>,
     passive_host: PassiveHost,
+    active_passive_mode: ActivePassiveMode,
+    allow_fxp: bool,
     collect_metrics: bool,
     ftps_mode: FtpsConfig,
+    ftps_sni_certificates: Vec<SniCertificate>,
+    ftps_implicit: bool,
     ftps_required_control_chan: FtpsRequired,
     ftps_required_data_chan: FtpsRequired,
     ftps_tls_flags: TlsFlags,
     ftps_client_auth: FtpsClientAuth,
     ftps_trust_store: PathBuf,
+    ftps_reloader: Option<TlsReloader>,
+    ftps_reload_interval: Option<Duration>,
     idle_session_timeout: std::time::Duration,
     proxy_protocol_mode: ProxyMode,
     proxy_protocol_switchboard: Option<ProxyProtocolSwitchboard<Storage, User>>,
     logger: slog::Logger,
     sitemd5: SiteMd5,
+    list_max_depth: u32,
+    list_max_entries: usize,
+    list_hide_dotfiles: bool,
+    virtual_hosts: Arc<HashMap<String, VirtualHost<Storage, User>>>,
+    site_commands: Arc<HashMap<String, Arc<dyn SiteCommand<Storage, User>>>>,
+    middlewares: Arc<Vec<Arc<dyn ControlChanMiddleware<User>>>>,
+    legacy_charset: Option<String>,
+    partial_upload_policy: PartialUploadPolicy,
+    overwrite_policy: OverwritePolicy,
+    transfer_monitor: Option<TransferMonitor>,
+    acl: Option<Arc<Acl>>,
+    shutdown: Pin<Box<dyn Future<Output = Shutdown> + Send + Sync>>,
 }
 
 impl<Storage, User> Debug for Server<Storage, User>
@@ -87,15 +118,32 @@ where
             .field("metrics", &self.collect_metrics)
             .field("passive_ports", &self.passive_ports)
             .field("passive_host", &self.passive_host)
+            .field("active_passive_mode", &self.active_passive_mode)
+            .field("allow_fxp", &self.allow_fxp)
             .field("ftps_client_auth", &self.ftps_client_auth)
             .field("ftps_mode", &self.ftps_mode)
+            .field("ftps_sni_certificates", &self.ftps_sni_certificates)
+            .field("ftps_implicit", &self.ftps_implicit)
             .field("ftps_required_control_chan", &self.ftps_required_control_chan)
             .field("ftps_required_data_chan", &self.ftps_required_data_chan)
             .field("ftps_tls_flags", &self.ftps_tls_flags)
             .field("ftps_trust_store", &self.ftps_trust_store)
+            .field("ftps_reloader", &self.ftps_reloader)
+            .field("ftps_reload_interval", &self.ftps_reload_interval)
             .field("idle_session_timeout", &self.idle_session_timeout)
             .field("proxy_protocol_mode", &self.proxy_protocol_mode)
             .field("proxy_protocol_switchboard", &self.proxy_protocol_switchboard)
+            .field("list_max_depth", &self.list_max_depth)
+            .field("list_max_entries", &self.list_max_entries)
+            .field("list_hide_dotfiles", &self.list_hide_dotfiles)
+            .field("virtual_hosts", &self.virtual_hosts)
+            .field("site_commands", &self.site_commands)
+            .field("middlewares", &self.middlewares)
+            .field("legacy_charset", &self.legacy_charset)
+            .field("partial_upload_policy", &self.partial_upload_policy)
+            .field("overwrite_policy", &self.overwrite_policy)
+            .field("transfer_monitor", &self.transfer_monitor)
+            .field("acl", &self.acl)
             .finish()
     }
 }
@@ -130,7 +178,11 @@ where
             authenticator,
             passive_ports: options::DEFAULT_PASSIVE_PORTS,
             passive_host: options::DEFAULT_PASSIVE_HOST,
+            active_passive_mode: options::DEFAULT_ACTIVE_PASSIVE_MODE,
+            allow_fxp: false,
             ftps_mode: FtpsConfig::Off,
+            ftps_sni_certificates: Vec::new(),
+            ftps_implicit: false,
             collect_metrics: false,
             idle_session_timeout: Duration::from_secs(DEFAULT_IDLE_SESSION_TIMEOUT_SECS),
             proxy_protocol_mode: ProxyMode::Off,
@@ -141,10 +193,62 @@ where
             ftps_tls_flags: TlsFlags::default(),
             ftps_client_auth: FtpsClientAuth::default(),
             ftps_trust_store: options::DEFAULT_FTPS_TRUST_STORE.into(),
+            ftps_reloader: None,
+            ftps_reload_interval: None,
             sitemd5: SiteMd5::default(),
+            list_max_depth: storage::DEFAULT_LIST_MAX_DEPTH,
+            list_max_entries: storage::DEFAULT_LIST_MAX_ENTRIES,
+            list_hide_dotfiles: false,
+            virtual_hosts: Arc::new(HashMap::new()),
+            site_commands: Arc::new(HashMap::new()),
+            middlewares: Arc::new(Vec::new()),
+            legacy_charset: None,
+            partial_upload_policy: PartialUploadPolicy::default(),
+            overwrite_policy: OverwritePolicy::default(),
+            transfer_monitor: None,
+            acl: None,
+            shutdown: Box::pin(futures::future::pending()),
         }
     }
 
+    /// Specifies whether clients may use active mode (`PORT` and `EPRT`), passive mode (`PASV`) or
+    /// both to set up data connections. Only passive mode is allowed by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::ActivePassiveMode;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .active_passive_mode(ActivePassiveMode::ActiveAndPassive);
+    /// ```
+    pub fn active_passive_mode<M: Into<ActivePassiveMode>>(mut self, mode: M) -> Self {
+        self.active_passive_mode = mode.into();
+        self
+    }
+
+    /// Allows active mode data connections to hosts other than the one the control connection
+    /// originates from i.e. server to server (FXP) transfers. This is off by default since it
+    /// exposes the server to FTP bounce attacks (see [RFC 2577](https://tools.ietf.org/html/rfc2577)).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::ActivePassiveMode;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .active_passive_mode(ActivePassiveMode::ActiveAndPassive)
+    ///              .allow_fxp(true);
+    /// ```
+    pub fn allow_fxp(mut self, allow: bool) -> Self {
+        self.allow_fxp = allow;
+        self
+    }
+
     /// Set the [`Authenticator`] that will be used for authentication.
     ///
     /// # Example
@@ -185,6 +289,54 @@ where
         self
     }
 
+    /// Adds a certificate for FTPS that is used when the client asks for the given host name with
+    /// TLS SNI (Server Name Indication) on the control or data channel. Clients that ask for another
+    /// host name or don't send one get the certificate configured with [ftps](crate::Server::ftps).
+    /// Both files should be in PEM format. The host name is passed to the
+    /// [`Authenticator`](crate::auth::Authenticator) in the
+    /// [`Credentials`](crate::auth::Credentials).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_sni_certificate("ftp.example.com", "/srv/unftp/example.com.certs", "/srv/unftp/example.com.key")
+    ///              .ftps_sni_certificate("ftp.example.org", "/srv/unftp/example.org.certs", "/srv/unftp/example.org.key");
+    /// ```
+    pub fn ftps_sni_certificate<H: Into<String>, P: Into<PathBuf>>(mut self, hostname: H, certs_file: P, key_file: P) -> Self {
+        self.ftps_sni_certificates.push(SniCertificate {
+            hostname: hostname.into(),
+            certs_file: certs_file.into(),
+            key_file: key_file.into(),
+        });
+        self
+    }
+
+    /// Switches on implicit FTPS. The TLS handshake then starts as soon as a client connects, before
+    /// the greeting is sent, and both the control and data channels are always encrypted. This is
+    /// the mode of the traditional FTPS port 990. FTPS needs to be configured with the
+    /// [ftps](crate::Server::ftps) method, otherwise [listen](crate::Server::listen) returns an
+    /// error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_implicit(true);
+    /// ```
+    pub fn ftps_implicit(mut self, implicit: bool) -> Self {
+        self.ftps_implicit = implicit;
+        self
+    }
+
     /// Allows switching on Mutual TLS. For this to work the trust anchors also needs to be set using
     /// the [ftps_trust_store](crate::Server::ftps_trust_store) method.
     ///
@@ -242,6 +394,45 @@ where
         self
     }
 
+    /// Makes it possible to reload the certificates, private keys and trust store from disk while
+    /// the server runs by calling [`TlsReloader::reload`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, TlsReloader};
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let reloader = TlsReloader::new();
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_reloader(reloader.clone());
+    /// ```
+    pub fn ftps_reloader(mut self, reloader: TlsReloader) -> Self {
+        self.ftps_reloader = Some(reloader);
+        self
+    }
+
+    /// Makes the server check the certificates, private keys and trust store files every given
+    /// interval and reload them when they changed. This way renewed certificates are picked up by
+    /// new connections without a restart. The files aren't checked by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp")
+    ///              .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+    ///              .ftps_reload_interval(Duration::from_secs(60));
+    /// ```
+    pub fn ftps_reload_interval(mut self, interval: Duration) -> Self {
+        self.ftps_reload_interval = Some(interval);
+        self
+    }
+
     /// Switches TLS features on or off.
     ///
     /// # Example
@@ -283,6 +474,27 @@ where
         self
     }
 
+    /// Sets the legacy character set, e.g. `windows-1252` or `Shift_JIS`, that clients can switch
+    /// to with `OPTS UTF8 OFF`. Paths in commands, replies and directory listings are then converted
+    /// from and to this charset. Without it `OPTS UTF8 OFF` is refused and UTF-8 is always used.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp").legacy_charset("windows-1252");
+    /// ```
+    ///
+    /// The label is one of the [WHATWG encoding labels](https://encoding.spec.whatwg.org/#names-and-labels).
+    /// [`listen`](Server::listen) fails if it is not known. Names that the charset can't represent
+    /// are left out of directory listings and replaced by `?` in replies.
+    pub fn legacy_charset<L: Into<String>>(mut self, label: L) -> Self {
+        self.legacy_charset = Some(label.into());
+        self
+    }
+
     /// Set the idle session timeout in seconds. The default is 600 seconds.
     ///
     /// # Example
@@ -373,6 +585,10 @@ where
     /// let server = Server::with_fs("/tmp")
     ///              .passive_host("ftp.myserver.org");
     /// ```
+    ///
+    /// The PASV reply can only carry an IPv4 address. If the address to advertise turns out to be an
+    /// IPv6 address, PASV is refused and clients have to use EPSV instead. EPSV only advertises the
+    /// port and is therefore not affected by this setting.
     pub fn passive_host<H: Into<PassiveHost>>(mut self, host_option: H) -> Self {
         self.passive_host = host_option.into();
         self
@@ -433,6 +649,9 @@ where
 
     /// Runs the main ftp process asynchronously. Should be started in a async runtime context.
     ///
+    /// The returned future runs until the [shutdown indicator](crate::Server::shutdown_indicator)
+    /// resolves and the sessions ended.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -447,35 +666,156 @@ where
     /// drop(rt);
     /// ```
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function panics when called with invalid addresses or when the process is unable to
-    /// `bind()` to the address.
+    /// This function returns an error when called with an invalid address or when the process is
+    /// unable to `bind()` to the address.
     #[tracing_attributes::instrument]
-    pub async fn listen<T: Into<String> + Debug>(mut self, bind_address: T) -> std::result::Result<(), ServerError> {
+    pub async fn listen<T: Into<String> + Debug>(self, bind_address: T) -> std::result::Result<(), ServerError> {
+        self.listen_on(vec![bind_address.into()]).await
+    }
+
+    /// Runs the main ftp process asynchronously on several addresses or listeners at once, for
+    /// instance on both an IPv4 and an IPv6 address. Listeners that are bound already can be used
+    /// for systemd socket activation or to bind to port 0 and find out which port was chosen.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{options::Listener, Server};
+    /// use unftp_sbe_fs::ServerExt;
+    /// use tokio::runtime::Runtime;
+    ///
+    /// let mut rt = Runtime::new().unwrap();
+    /// let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let port = listener.local_addr().unwrap().port();
+    /// let server = Server::with_fs("/srv/ftp");
+    /// rt.spawn(server.listen_on(vec![Listener::from(listener), Listener::from("[::1]:2121")]));
+    /// // ...
+    /// drop(rt);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error when no listeners are given, when called with an invalid
+    /// address or when the process is unable to `bind()` to one of the addresses.
+    #[tracing_attributes::instrument(skip(listeners))]
+    pub async fn listen_on<I, L>(mut self, listeners: I) -> std::result::Result<(), ServerError>
+    where
+        I: IntoIterator<Item = L>,
+        L: Into<Listener>,
+    {
+        let mut tls_configs = Vec::new();
         self.ftps_mode = match self.ftps_mode {
             FtpsConfig::Off => FtpsConfig::Off,
-            FtpsConfig::Building { certs_file, key_file } => FtpsConfig::On {
-                tls_config: tls::new_config(certs_file, key_file, self.ftps_tls_flags, self.ftps_client_auth, self.ftps_trust_store.clone())?,
-            },
+            FtpsConfig::Building { certs_file, key_file } => {
+                let tls_config = tls::ReloadableConfig::load(
+                    certs_file,
+                    key_file,
+                    self.ftps_sni_certificates.clone(),
+                    self.ftps_tls_flags,
+                    self.ftps_client_auth,
+                    self.ftps_trust_store.clone(),
+                )?;
+                tls_configs.push(tls_config.clone());
+                FtpsConfig::On { tls_config }
+            }
             FtpsConfig::On { tls_config } => FtpsConfig::On { tls_config },
         };
-        match self.proxy_protocol_mode {
-            ProxyMode::On { external_control_port } => self.listen_proxy_protocol_mode(bind_address, external_control_port).await,
-            ProxyMode::Off => self.listen_normal_mode(bind_address).await,
+        if self.ftps_implicit && matches!(self.ftps_mode, FtpsConfig::Off) {
+            return Err(tls::FtpsNotAvailable.into());
+        }
+        if let Some(label) = &self.legacy_charset {
+            charset::for_label(label)?;
+        }
+        if self.overwrite_policy == OverwritePolicy::Version && (self.storage)().supported_features() & FEATURE_RENAME == 0 {
+            return Err(ServerError::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the Version overwrite policy needs a storage back-end that can rename files",
+            )));
+        }
+        for vhost in Arc::make_mut(&mut self.virtual_hosts).values_mut() {
+            vhost.greeting = vhost.greeting.or(Some(self.greeting));
+            if let FtpsConfig::Building { certs_file, key_file } = &vhost.ftps_mode {
+                let tls_config = tls::ReloadableConfig::load(
+                    certs_file.clone(),
+                    key_file.clone(),
+                    Vec::new(),
+                    self.ftps_tls_flags,
+                    self.ftps_client_auth,
+                    self.ftps_trust_store.clone(),
+                )?;
+                tls_configs.push(tls_config.clone());
+                vhost.ftps_mode = FtpsConfig::On { tls_config };
+            }
+        }
+        if let Some(reloader) = &self.ftps_reloader {
+            for tls_config in &tls_configs {
+                reloader.register(tls_config.clone());
+            }
+        }
+        let logger = self.logger.clone();
+        let reload_interval = self.ftps_reload_interval;
+
+        let mut bound = Vec::new();
+        for listener in listeners {
+            let listener = listener.into().bind().await?;
+            slog::info!(logger, "Listening on {:?}", listener.local_addr());
+            bound.push(listener);
+        }
+        if bound.is_empty() {
+            return Err(ServerError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, "no listeners given")));
+        }
+
+        let shutdown_indicator = std::mem::replace(&mut self.shutdown, Box::pin(futures::future::pending()));
+        let notifier = shutdown::Notifier::new();
+        let notifier_ref = &notifier;
+        let listening = async move {
+            match self.proxy_protocol_mode {
+                ProxyMode::On { external_control_port } => {
+                    self.listen_proxy_protocol_mode(bound, external_control_port, notifier_ref, shutdown_indicator)
+                        .await
+                }
+                ProxyMode::Off => self.listen_normal_mode(bound, notifier_ref, shutdown_indicator).await,
+            }
+        };
+        // The TLS files are watched while the server listens.
+        let tls_watcher = match reload_interval {
+            Some(interval) if !tls_configs.is_empty() => Some(tokio::spawn(tls::watch(tls_configs, interval, logger.clone()))),
+            _ => None,
+        };
+        let result = listening.await;
+        if let Some(tls_watcher) = tls_watcher {
+            tls_watcher.abort();
         }
+        let shutdown = result?;
+        slog::info!(logger, "Shutting down. Waiting up to {:?} for sessions to end", shutdown.grace_period);
+        if notifier.notify_and_wait(shutdown.grace_period).await {
+            slog::info!(logger, "All sessions ended");
+        } else {
+            slog::warn!(logger, "Sessions were still running at the end of the grace period");
+        }
+        Ok(())
     }
 
-    #[tracing_attributes::instrument]
-    async fn listen_normal_mode<T: Into<String> + Debug>(self, bind_address: T) -> std::result::Result<(), ServerError> {
-        let addr: std::net::SocketAddr = bind_address.into().parse()?;
-        let listener = tokio::net::TcpListener::bind(addr).await?;
+    #[tracing_attributes::instrument(skip(shutdown_indicator))]
+    async fn listen_normal_mode(
+        self,
+        listeners: Vec<tokio::net::TcpListener>,
+        notifier: &shutdown::Notifier,
+        mut shutdown_indicator: Pin<Box<dyn Future<Output = Shutdown> + Send + Sync>>,
+    ) -> std::result::Result<Shutdown, ServerError> {
+        let mut incoming = incoming_connections(listeners);
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                Some(accepted) = incoming.next() => accepted,
+                shutdown = &mut shutdown_indicator => return Ok(shutdown),
+            };
+            match accepted {
                 Ok((tcp_stream, socket_addr)) => {
                     slog::info!(self.logger, "Incoming control connection from {:?}", socket_addr);
                     let params: controlchan::LoopConfig<Storage, User> = (&self).into();
-                    let result = controlchan::spawn_loop::<Storage, User>(params, tcp_stream, None, None).await;
+                    let result = controlchan::spawn_loop::<Storage, User>(params, tcp_stream, None, None, notifier.subscribe()).await;
                     if let Err(err) = result {
                         slog::error!(
                             self.logger,
@@ -492,14 +832,15 @@ where
         }
     }
 
-    #[tracing_attributes::instrument]
-    async fn listen_proxy_protocol_mode<T: Into<String> + Debug>(
+    #[tracing_attributes::instrument(skip(shutdown_indicator))]
+    async fn listen_proxy_protocol_mode(
         mut self,
-        bind_address: T,
+        listeners: Vec<tokio::net::TcpListener>,
         external_control_port: u16,
-    ) -> std::result::Result<(), ServerError> {
-        let addr: std::net::SocketAddr = bind_address.into().parse()?;
-        let listener = tokio::net::TcpListener::bind(addr).await?;
+        notifier: &shutdown::Notifier,
+        mut shutdown_indicator: Pin<Box<dyn Future<Output = Shutdown> + Send + Sync>>,
+    ) -> std::result::Result<Shutdown, ServerError> {
+        let mut incoming = incoming_connections(listeners);
 
         // this callback is used by all sessions, basically only to
         // request for a passive listening port.
@@ -512,7 +853,7 @@ where
 
             tokio::select! {
 
-                Ok((tcp_stream, _socket_addr)) = listener.accept() => {
+                Some(Ok((tcp_stream, _socket_addr))) = incoming.next() => {
                     let socket_addr = tcp_stream.peer_addr();
                     let mut tcp_stream = tcp_stream;
 
@@ -533,7 +874,7 @@ where
                         let source = connection.source;
                         slog::info!(self.logger, "Connection from {:?} is a control connection", source);
                         let params: controlchan::LoopConfig<Storage,User> = (&self).into();
-                        let result = controlchan::spawn_loop::<Storage,User>(params, tcp_stream, Some(source), Some(proxyloop_msg_tx.clone())).await;
+                        let result = controlchan::spawn_loop::<Storage,User>(params, tcp_stream, Some(source), Some(proxyloop_msg_tx.clone()), notifier.subscribe()).await;
                         if result.is_err() {
                             slog::warn!(self.logger, "Could not spawn control channel loop for connection: {:?}", result.err().unwrap())
                         }
@@ -551,10 +892,16 @@ where
                 Some(msg) = proxyloop_msg_rx.next() => {
                     match msg {
                         ProxyLoopMsg::AssignDataPortCommand (session_arc) => {
-                            self.select_and_register_passive_port(session_arc).await;
+                            self.select_and_register_passive_port(session_arc, false).await;
+                        },
+                        ProxyLoopMsg::AssignExtendedDataPortCommand (session_arc) => {
+                            self.select_and_register_passive_port(session_arc, true).await;
                         },
                     }
                 },
+                shutdown = &mut shutdown_indicator => {
+                    return Ok(shutdown);
+                },
             };
         }
     }
@@ -568,7 +915,7 @@ where
         if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
             match switchboard.get_session_by_incoming_data_connection(&connection).await {
                 Some(session) => {
-                    spawn_processing(self.logger.clone(), session, tcp_stream).await;
+                    spawn_processing(self.logger.clone(), session, DataSocket::Connected(tcp_stream)).await;
                     switchboard.unregister(&connection);
                 }
                 None => {
@@ -581,28 +928,32 @@ where
     }
 
     #[tracing_attributes::instrument]
-    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<Storage, User>) {
+    async fn select_and_register_passive_port(&mut self, session_arc: SharedSession<Storage, User>, extended: bool) {
         slog::info!(self.logger, "Received internal message to allocate data port");
         // 1. reserve a port
         // 2. put the session_arc and tx in the hashmap with srcip+dstport as key
         // 3. put expiry time in the LIFO list
-        // 4. send reply to client: "Entering Passive Mode ({},{},{},{},{},{})"
+        // 4. send reply to client: "Entering Passive Mode ({},{},{},{},{},{})" or, for EPSV,
+        //    "Entering Extended Passive Mode (|||{}|)"
 
-        let mut reserved_port: u16 = 0;
+        let mut reserved_port: Option<u16> = None;
         if let Some(switchboard) = &mut self.proxy_protocol_switchboard {
-            let port = switchboard.reserve_next_free_port(session_arc.clone()).await.unwrap();
-            slog::info!(self.logger, "Reserving data port: {:?}", port);
-            reserved_port = port
+            match switchboard.reserve_next_free_port(session_arc.clone()).await {
+                Ok(port) => {
+                    slog::info!(self.logger, "Reserving data port: {:?}", port);
+                    reserved_port = Some(port)
+                }
+                Err(err) => slog::warn!(self.logger, "Could not reserve a data port: {:?}", err),
+            }
         }
         let session = session_arc.lock().await;
         if let Some(destination) = session.destination {
-            let destination_ip = match destination.ip() {
-                IpAddr::V4(ip) => ip,
-                IpAddr::V6(_) => panic!("Won't happen since PASV only does IP V4."),
+            let reply: Reply = match reserved_port {
+                None => Reply::new(ReplyCode::CantOpenDataConnection, "No data connection established"),
+                Some(port) if extended => super::controlchan::commands::make_epsv_reply(port),
+                Some(port) => super::controlchan::commands::make_pasv_reply(self.passive_host.clone(), destination.ip(), port).await,
             };
 
-            let reply: Reply = super::controlchan::commands::make_pasv_reply(self.passive_host.clone(), &destination_ip, reserved_port).await;
-
             let tx_some = session.control_msg_tx.clone();
             if let Some(tx) = tx_some {
                 let mut tx = tx.clone();
@@ -633,6 +984,189 @@ where
         self.sitemd5 = sitemd5_option.into();
         self
     }
+
+    /// Sets what happens to the partially written file when a client aborts an upload with `ABOR`.
+    /// The file is kept by default so that the client can resume the upload.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::PartialUploadPolicy;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp").partial_upload_policy(PartialUploadPolicy::Delete);
+    /// ```
+    pub fn partial_upload_policy(mut self, policy: PartialUploadPolicy) -> Self {
+        self.partial_upload_policy = policy;
+        self
+    }
+
+    /// Sets what happens when a client uploads a file with `STOR` to a path where a file exists
+    /// already. Files are overwritten by default. Users can have their own policy through
+    /// [`UserDetail::overwrite_policy`](crate::auth::UserDetail::overwrite_policy).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use libunftp::options::OverwritePolicy;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp").overwrite_policy(OverwritePolicy::Refuse);
+    /// ```
+    pub fn overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = policy;
+        self
+    }
+
+    /// Registers the running file transfers of all sessions with the given [`TransferMonitor`] so
+    /// that you can inspect their progress.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, TransferMonitor};
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let monitor = TransferMonitor::new();
+    /// let server = Server::with_fs("/tmp").transfer_monitor(monitor.clone());
+    /// ```
+    pub fn transfer_monitor(mut self, monitor: TransferMonitor) -> Self {
+        self.transfer_monitor = Some(monitor);
+        self
+    }
+
+    /// Limits what users may do per path with an access control list. The server checks it for
+    /// every command that takes a path and leaves out the entries that users may not see from
+    /// directory listings. See [`Acl`] for an example.
+    ///
+    /// [`Acl`]: crate::acl::Acl
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(Arc::new(acl));
+        self
+    }
+
+    /// Limits the size of recursive directory listings (`LIST -R`). The listing descends at most
+    /// `max_depth` levels into subdirectories and stops after `max_entries` entries. Recursive
+    /// listings can be expensive, especially with remote storage back-ends, so keep these low.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::with_fs("/tmp").list_recursion_limits(2, 1000);
+    /// ```
+    pub fn list_recursion_limits(mut self, max_depth: u32, max_entries: usize) -> Self {
+        self.list_max_depth = max_depth;
+        self.list_max_entries = max_entries;
+        self
+    }
+
+    /// Leaves files and directories whose name starts with a dot out of `LIST` responses, unless
+    /// the client asks for them with `-a`, like `ls` does. Off by default, so that clients see
+    /// every file.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::Server;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// // Use it in a builder-like pattern:
+    /// let mut server = Server::with_fs("/tmp").list_hide_dotfiles(true);
+    /// ```
+    pub fn list_hide_dotfiles(mut self, hide: bool) -> Self {
+        self.list_hide_dotfiles = hide;
+        self
+    }
+
+    /// Registers a name-based virtual host. Clients select it by sending the `HOST` command
+    /// ([RFC 7151](https://tools.ietf.org/html/rfc7151)) before logging in. The session then uses
+    /// the greeting, authenticator, storage back-end and FTPS certificate configured for the virtual
+    /// host instead of those of the server. Clients that don't send `HOST` get the server's.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{Server, VirtualHost};
+    /// use unftp_sbe_fs::{Filesystem, ServerExt};
+    ///
+    /// let server = Server::with_fs("/srv/ftp/default")
+    ///     .virtual_host(VirtualHost::new("ftp.example.com").storage(Box::new(|| Filesystem::new("/srv/ftp/example.com"))))
+    ///     .virtual_host(VirtualHost::new("ftp.example.org").storage(Box::new(|| Filesystem::new("/srv/ftp/example.org"))));
+    /// ```
+    pub fn virtual_host(mut self, vhost: VirtualHost<Storage, User>) -> Self {
+        Arc::make_mut(&mut self.virtual_hosts).insert(vhost.hostname.to_ascii_lowercase(), vhost);
+        self
+    }
+
+    /// Registers a handler for a custom `SITE` subcommand e.g. `SITE CHMOD`. The name is matched
+    /// case insensitively and shows up in the `FEAT` and `HELP` replies. See [`SiteCommand`] for an
+    /// example.
+    ///
+    /// The built-in `SITE MD5` can't be replaced. Registering `MD5` panics in debug builds and the
+    /// handler is ignored in release builds.
+    ///
+    /// [`SiteCommand`]: crate::site::SiteCommand
+    pub fn site_command<N: Into<String>>(mut self, name: N, handler: Arc<dyn SiteCommand<Storage, User>>) -> Self {
+        let name = name.into().to_ascii_uppercase();
+        // `SITE MD5` is parsed as a command of its own, so a handler for it would never be called.
+        debug_assert!(name != "MD5", "SITE MD5 is built in and can't be replaced");
+        if name != "MD5" {
+            Arc::make_mut(&mut self.site_commands).insert(name, handler);
+        }
+        self
+    }
+
+    /// Adds a [`ControlChanMiddleware`] that intercepts the commands clients send on the control channel.
+    /// Middleware runs in the order it was added, before libunftp's own command handling. See
+    /// [`ControlChanMiddleware`] for an example.
+    ///
+    /// [`ControlChanMiddleware`]: crate::middleware::ControlChanMiddleware
+    pub fn middleware(mut self, middleware: Arc<dyn ControlChanMiddleware<User>>) -> Self {
+        Arc::make_mut(&mut self.middlewares).push(middleware);
+        self
+    }
+
+    /// Sets a future that tells the server to shut down when it resolves. The server then stops
+    /// accepting connections, closes idle sessions with a 421 reply and gives running transfers the
+    /// [grace period](crate::options::Shutdown::grace_period) to finish before it closes their
+    /// sessions too. The [listen](crate::Server::listen) future completes once all sessions ended or
+    /// the grace period passed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use libunftp::{options::Shutdown, Server};
+    /// use std::time::Duration;
+    /// use unftp_sbe_fs::ServerExt;
+    ///
+    /// let server = Server::with_fs("/tmp").shutdown_indicator(async {
+    ///     tokio::time::sleep(Duration::from_secs(60)).await; // Shut down after a minute
+    ///     Shutdown::new().grace_period(Duration::from_secs(30))
+    /// });
+    /// ```
+    pub fn shutdown_indicator<I>(mut self, indicator: I) -> Self
+    where
+        I: Future<Output = Shutdown> + Send + Sync + 'static,
+    {
+        self.shutdown = Box::pin(indicator);
+        self
+    }
+}
+
+// Merges the connections accepted by the given listeners into one stream.
+fn incoming_connections(listeners: Vec<tokio::net::TcpListener>) -> impl Stream<Item = std::io::Result<(tokio::net::TcpStream, SocketAddr)>> {
+    futures::stream::select_all(listeners.into_iter().map(|listener| {
+        Box::pin(futures::stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await;
+            Some((accepted, listener))
+        }))
+    }))
 }
 
 impl<Storage, User> From<&Server<Storage, User>> for controlchan::LoopConfig<Storage, User>
@@ -646,15 +1180,30 @@ where
             authenticator: server.authenticator.clone(),
             storage: (server.storage)(),
             ftps_config: server.ftps_mode.clone(),
+            ftps_implicit: server.ftps_implicit,
             collect_metrics: server.collect_metrics,
             greeting: server.greeting,
             idle_session_timeout: server.idle_session_timeout,
             passive_ports: server.passive_ports.clone(),
             passive_host: server.passive_host.clone(),
+            active_passive_mode: server.active_passive_mode,
+            allow_fxp: server.allow_fxp,
             logger: server.logger.new(slog::o!()),
             ftps_required_control_chan: server.ftps_required_control_chan,
             ftps_required_data_chan: server.ftps_required_data_chan,
             sitemd5: server.sitemd5,
+            list_max_depth: server.list_max_depth,
+            list_max_entries: server.list_max_entries,
+            list_hide_dotfiles: server.list_hide_dotfiles,
+            virtual_hosts: server.virtual_hosts.clone(),
+            site_commands: server.site_commands.clone(),
+            middlewares: server.middlewares.clone(),
+            // The label was checked by `listen_on`.
+            legacy_charset: server.legacy_charset.as_deref().and_then(|label| charset::for_label(label).ok()),
+            partial_upload_policy: server.partial_upload_policy,
+            overwrite_policy: server.overwrite_policy,
+            transfer_monitor: server.transfer_monitor.clone(),
+            acl: server.acl.clone(),
         }
     }
 }
@@ -0,0 +1,326 @@
+//! Contains the path based access control rules that are registered with
+//! [`Server::acl`](crate::Server::acl).
+//!
+//! An [`Acl`] is a list of [`Rule`]s. Each rule combines a path pattern, the users and groups it
+//! applies to and the [`Permissions`] those users get below the matching paths. For every command
+//! that takes a path the server looks for the first rule that matches the path and the user. The
+//! user may then only do what both the rule and [`UserDetail::permissions`] allow. Paths that no
+//! rule matches are only limited by [`UserDetail::permissions`].
+//!
+//! Entries on which a user has no permissions at all are left out of directory listings and can't
+//! be changed into with `CWD`. Neither are the entries of directories the user may not list.
+//!
+//! A [`Dropbox`] is a special rule for upload-only directories.
+
+use crate::{
+    auth::{Permissions, UserDetail},
+    options::OverwritePolicy,
+    storage::ListFilter,
+};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+/// An ordered list of access control [`Rule`]s. The first rule that matches wins.
+///
+/// # Example
+///
+/// Partner X may upload below `/incoming`, download below `/outgoing` and do nothing elsewhere. No
+/// one may see `/internal`.
+///
+/// ```rust
+/// use libunftp::{
+///     acl::{Acl, Rule},
+///     auth::Permissions,
+///     Server,
+/// };
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let acl = Acl::new()
+///     .rule(Rule::new("/internal/**", Permissions::empty()))
+///     .rule(Rule::new("/", Permissions::LIST).user("partner-x"))
+///     .rule(Rule::new("/incoming/**", Permissions::WRITE | Permissions::LIST).user("partner-x"))
+///     .rule(Rule::new("/outgoing/**", Permissions::READ_ONLY).user("partner-x"))
+///     .rule(Rule::new("/**", Permissions::empty()).user("partner-x"));
+/// let server = Server::with_fs("/srv/ftp").acl(acl);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+impl Acl {
+    /// Creates an empty access control list.
+    pub fn new() -> Self {
+        Acl::default()
+    }
+
+    /// Adds a rule after the ones added before.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Adds an upload-only directory after the rules added before.
+    pub fn dropbox(mut self, dropbox: Dropbox) -> Self {
+        let mut rule = dropbox.rule;
+        rule.dropbox = Some(if dropbox.unique_names {
+            OverwritePolicy::UniqueName
+        } else {
+            OverwritePolicy::Refuse
+        });
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the permissions that the first matching rule gives the user on the path, or `None`
+    /// if no rule matches. The path is absolute e.g. `/incoming/report.csv`.
+    pub fn permissions<P: AsRef<Path>>(&self, username: &str, groups: &[String], path: P) -> Option<Permissions> {
+        let path = normalize(path.as_ref());
+        self.rules
+            .iter()
+            .find(|rule| rule.applies_to(username, groups) && rule.matches(&path))
+            .map(|rule| rule.permissions)
+    }
+
+    // Tells what to do with uploads to existing files if the first matching rule is a dropbox.
+    pub(crate) fn dropbox_collision(&self, username: &str, groups: &[String], path: &Path) -> Option<OverwritePolicy> {
+        let path = normalize(path);
+        self.rules
+            .iter()
+            .find(|rule| rule.applies_to(username, groups) && rule.matches(&path))
+            .and_then(|rule| rule.dropbox)
+    }
+}
+
+/// A rule of an [`Acl`]. It applies to everyone unless users or groups are added to it.
+///
+/// The pattern is matched against the absolute path of a file or directory. In it `*` matches any
+/// part of a file name and `**` matches any number of directories, including none. So
+/// `/incoming/**` matches `/incoming` and everything below it while `/incoming/*.csv` only matches
+/// the CSV files directly in `/incoming`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pattern: Vec<String>,
+    permissions: Permissions,
+    users: Vec<String>,
+    groups: Vec<String>,
+    dropbox: Option<OverwritePolicy>,
+}
+
+impl Rule {
+    /// Creates a rule that gives the permissions on the paths matching the pattern.
+    pub fn new<P: AsRef<str>>(pattern: P, permissions: Permissions) -> Self {
+        Rule {
+            pattern: pattern.as_ref().split('/').filter(|part| !part.is_empty()).map(String::from).collect(),
+            permissions,
+            users: vec![],
+            groups: vec![],
+            dropbox: None,
+        }
+    }
+
+    /// Applies the rule to the user with the given login name.
+    pub fn user<N: Into<String>>(mut self, username: N) -> Self {
+        self.users.push(username.into());
+        self
+    }
+
+    /// Applies the rule to the users in the given group. See [`UserDetail::groups`].
+    pub fn group<N: Into<String>>(mut self, group: N) -> Self {
+        self.groups.push(group.into());
+        self
+    }
+
+    fn applies_to(&self, username: &str, groups: &[String]) -> bool {
+        (self.users.is_empty() && self.groups.is_empty()) || self.users.iter().any(|u| u == username) || self.groups.iter().any(|g| groups.contains(g))
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let names: Vec<&str> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+        let pattern: Vec<&str> = self.pattern.iter().map(String::as_str).collect();
+        matches_path(&pattern, &names)
+    }
+}
+
+/// An upload-only "dropbox" directory. Users can upload files into it with `STOR` and `STOU` but
+/// can't list it, download from it, append to its files or overwrite the files that are already in
+/// it. Add it to an [`Acl`] with [`Acl::dropbox`]. Like a [`Rule`] it applies to everyone unless
+/// users or groups are added to it and its pattern is matched in the same way.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{
+///     acl::{Acl, Dropbox},
+///     Server,
+/// };
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let acl = Acl::new().dropbox(Dropbox::new("/incoming/**").group("partners").unique_names(true));
+/// let server = Server::with_fs("/srv/ftp").acl(acl);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dropbox {
+    rule: Rule,
+    unique_names: bool,
+}
+
+impl Dropbox {
+    /// Creates an upload-only directory for the paths matching the pattern.
+    pub fn new<P: AsRef<str>>(pattern: P) -> Self {
+        Dropbox {
+            rule: Rule::new(pattern, Permissions::WRITE),
+            unique_names: false,
+        }
+    }
+
+    /// Applies the dropbox to the user with the given login name.
+    pub fn user<N: Into<String>>(mut self, username: N) -> Self {
+        self.rule = self.rule.user(username);
+        self
+    }
+
+    /// Applies the dropbox to the users in the given group. See [`UserDetail::groups`].
+    pub fn group<N: Into<String>>(mut self, group: N) -> Self {
+        self.rule = self.rule.group(group);
+        self
+    }
+
+    /// Stores uploads to a file name that is already taken under a new unique name, like `STOU`
+    /// does, instead of refusing them with 553. Off by default.
+    pub fn unique_names(mut self, unique_names: bool) -> Self {
+        self.unique_names = unique_names;
+        self
+    }
+}
+
+fn matches_path(pattern: &[&str], names: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((&"**", rest)) => (0..=names.len()).any(|skip| matches_path(rest, &names[skip..])),
+        Some((part, rest)) => match names.split_first() {
+            Some((name, names)) => matches_name(part.as_bytes(), name.as_bytes()) && matches_path(rest, names),
+            None => false,
+        },
+    }
+}
+
+fn matches_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| matches_name(rest, &name[skip..])),
+        Some((c, rest)) => match name.split_first() {
+            Some((n, name)) => n == c && matches_name(rest, name),
+            None => false,
+        },
+    }
+}
+
+// Resolves sequences like '../' without touching the storage back-end and makes the path absolute.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+// Returns what the user may do on the path: its own permissions limited by the ACL, if any.
+pub(crate) fn effective_permissions<User: UserDetail>(acl: Option<&Acl>, user: &User, username: &str, path: &Path) -> Permissions {
+    let permissions = user.permissions();
+    match acl.and_then(|acl| acl.permissions(username, user.groups(), path)) {
+        Some(allowed) => permissions & allowed,
+        None => permissions,
+    }
+}
+
+// Hides the entries of directory listings on which the user has no permissions. Recursive listings
+// also leave out the entries of directories the user may not list, like dropboxes.
+pub(crate) fn list_filter<User: UserDetail + 'static>(acl: Arc<Acl>, user: Arc<Option<User>>, username: String) -> ListFilter {
+    ListFilter::new(move |path| match &*user {
+        Some(user) => {
+            let parent = path.parent().unwrap_or(path);
+            !effective_permissions(Some(&acl), user, &username, path).is_empty()
+                && effective_permissions(Some(&acl), user, &username, parent).contains(Permissions::LIST)
+        }
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn patterns() {
+        let rule = Rule::new("/incoming/**", Permissions::WRITE);
+        assert!(rule.matches(Path::new("/incoming")));
+        assert!(rule.matches(Path::new("/incoming/a/b.txt")));
+        assert!(!rule.matches(Path::new("/incoming2")));
+        assert!(!rule.matches(Path::new("/")));
+
+        let rule = Rule::new("/incoming/*.csv", Permissions::WRITE);
+        assert!(rule.matches(Path::new("/incoming/report.csv")));
+        assert!(!rule.matches(Path::new("/incoming/report.txt")));
+        assert!(!rule.matches(Path::new("/incoming/a/report.csv")));
+
+        let rule = Rule::new("/**/secret", Permissions::empty());
+        assert!(rule.matches(Path::new("/secret")));
+        assert!(rule.matches(Path::new("/a/b/secret")));
+        assert!(!rule.matches(Path::new("/a/secret/b")));
+
+        assert!(Rule::new("/", Permissions::LIST).matches(Path::new("/")));
+        assert!(Rule::new("/**", Permissions::LIST).matches(Path::new("/")));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let acl = Acl::new()
+            .rule(Rule::new("/internal/**", Permissions::empty()))
+            .rule(Rule::new("/incoming/**", Permissions::WRITE).user("partner"))
+            .rule(Rule::new("/outgoing/**", Permissions::READ_ONLY).group("partners"))
+            .rule(Rule::new("/**", Permissions::empty()).user("partner"));
+        let groups = vec![String::from("partners")];
+
+        assert_eq!(acl.permissions("partner", &groups, "/incoming/a.txt"), Some(Permissions::WRITE));
+        assert_eq!(acl.permissions("partner", &groups, "/outgoing/a.txt"), Some(Permissions::READ_ONLY));
+        assert_eq!(acl.permissions("partner", &groups, "/incoming/../internal/a.txt"), Some(Permissions::empty()));
+        assert_eq!(acl.permissions("partner", &groups, "/other"), Some(Permissions::empty()));
+        assert_eq!(acl.permissions("partner", &[], "/outgoing/a.txt"), Some(Permissions::empty()));
+        assert_eq!(acl.permissions("employee", &[], "/other"), None);
+        assert_eq!(acl.permissions("employee", &[], "/internal"), Some(Permissions::empty()));
+    }
+
+    #[test]
+    fn dropboxes() {
+        let acl = Acl::new()
+            .dropbox(Dropbox::new("/incoming/**").user("partner"))
+            .dropbox(Dropbox::new("/uploads/**").unique_names(true));
+
+        assert_eq!(acl.permissions("partner", &[], "/incoming/a.txt"), Some(Permissions::WRITE));
+        assert_eq!(
+            acl.dropbox_collision("partner", &[], Path::new("/incoming/a.txt")),
+            Some(OverwritePolicy::Refuse)
+        );
+        assert_eq!(acl.dropbox_collision("employee", &[], Path::new("/incoming/a.txt")), None);
+        assert_eq!(
+            acl.dropbox_collision("employee", &[], Path::new("/uploads/a.txt")),
+            Some(OverwritePolicy::UniqueName)
+        );
+    }
+}
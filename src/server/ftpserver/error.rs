@@ -1,6 +1,9 @@
 //! Contains the error type used by `Server`
 
-use crate::BoxError;
+use crate::{
+    server::{charset::UnknownCharset, tls::FtpsNotAvailable},
+    BoxError,
+};
 
 use std::net::AddrParseError;
 use thiserror::Error;
@@ -29,6 +32,18 @@ impl From<std::net::AddrParseError> for ServerError {
     }
 }
 
+impl From<FtpsNotAvailable> for ServerError {
+    fn from(e: FtpsNotAvailable) -> Self {
+        ServerError::new("implicit FTPS needs FTPS to be configured", e)
+    }
+}
+
+impl From<UnknownCharset> for ServerError {
+    fn from(e: UnknownCharset) -> Self {
+        ServerError::new("could not set the legacy charset", e)
+    }
+}
+
 impl From<std::io::Error> for ServerError {
     fn from(e: std::io::Error) -> Self {
         ServerError::new("io error", e)
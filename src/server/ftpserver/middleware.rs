@@ -0,0 +1,274 @@
+//! Contains the extension point for control channel middleware that is registered with
+//! [`Server::middleware`](crate::Server::middleware).
+//!
+//! A middleware sees every command a client sends before libunftp handles it. It can pass the
+//! command on unchanged, rewrite it, reject it with its own [`Reply`] or inspect the reply that
+//! comes back. This can be used for things like command filtering, auditing and per-command
+//! authorization.
+
+pub use crate::server::controlchan::{
+    error::{ControlChanError, ControlChanErrorKind},
+    reply::{Reply, ReplyCode},
+};
+
+use crate::auth::UserDetail;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Intercepts the commands sent by clients on the control channel.
+///
+/// # Example
+///
+/// A middleware that refuses to delete files:
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::{
+///     auth::DefaultUser,
+///     middleware::{ControlChanError, ControlChanMiddleware, Next, Reply, ReplyCode, Request, SessionInfo},
+///     Server,
+/// };
+/// use std::sync::Arc;
+/// use unftp_sbe_fs::ServerExt;
+///
+/// #[derive(Debug)]
+/// struct NoDeletes;
+///
+/// #[async_trait]
+/// impl ControlChanMiddleware<DefaultUser> for NoDeletes {
+///     async fn handle(&self, _session: &SessionInfo<DefaultUser>, request: Request, next: Next<'_, DefaultUser>) -> Result<Reply, ControlChanError> {
+///         match request.verb() {
+///             "DELE" | "RMD" => Ok(Reply::new(ReplyCode::FileError, "Deleting is not allowed")),
+///             _ => next.run(request).await,
+///         }
+///     }
+/// }
+///
+/// let server = Server::with_fs("/srv/ftp").middleware(Arc::new(NoDeletes));
+/// ```
+#[async_trait]
+pub trait ControlChanMiddleware<User>: Send + Sync + Debug
+where
+    User: UserDetail,
+{
+    /// Handles a command from the client. Call [`Next::run`] to pass it, or a rewritten one, on.
+    ///
+    /// Returning an error closes the control connection.
+    async fn handle(&self, session: &SessionInfo<User>, request: Request, next: Next<'_, User>) -> Result<Reply, ControlChanError>;
+}
+
+/// A command line sent by the client e.g. `RETR file.txt`.
+///
+/// The password sent with `PASS` is not returned by [`argument`](Request::argument) or shown by
+/// `Debug` so that it doesn't end up in audit logs by accident. Use [`password`](Request::password)
+/// to get it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Request {
+    verb: String,
+    argument: String,
+}
+
+impl Request {
+    /// Creates a request from a command verb e.g. `RETR` and its argument. Use an empty argument
+    /// for commands without one.
+    pub fn new<V: Into<String>, A: Into<String>>(verb: V, argument: A) -> Self {
+        Request {
+            verb: verb.into().to_ascii_uppercase(),
+            argument: argument.into(),
+        }
+    }
+
+    /// Returns the command verb in upper case e.g. `RETR` or `SITE`.
+    pub fn verb(&self) -> &str {
+        &self.verb
+    }
+
+    /// Returns everything after the verb e.g. the path for `RETR`. For `SITE` commands this
+    /// includes the subcommand. For `PASS` this is empty, see [`password`](Request::password).
+    pub fn argument(&self) -> &str {
+        if self.is_pass() {
+            ""
+        } else {
+            &self.argument
+        }
+    }
+
+    /// Returns the password if this is a `PASS` request. Take care not to log it.
+    pub fn password(&self) -> Option<&str> {
+        if self.is_pass() {
+            Some(&self.argument)
+        } else {
+            None
+        }
+    }
+
+    fn is_pass(&self) -> bool {
+        self.verb == "PASS"
+    }
+
+    // Splits a command line as received from the client.
+    pub(crate) fn from_line(line: &str) -> Self {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        let mut parts = line.splitn(2, ' ');
+        Request::new(parts.next().unwrap_or_default(), parts.next().unwrap_or_default())
+    }
+
+    // Turns the request back into a line that can be parsed.
+    pub(crate) fn to_line(&self) -> String {
+        if self.argument.is_empty() {
+            format!("{}\r\n", self.verb)
+        } else {
+            format!("{} {}\r\n", self.verb, self.argument)
+        }
+    }
+}
+
+impl Debug for Request {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("verb", &self.verb)
+            .field("argument", &if self.is_pass() { "<redacted>" } else { &self.argument })
+            .finish()
+    }
+}
+
+/// The state of the session a [`Request`] was sent on.
+pub struct SessionInfo<User>
+where
+    User: UserDetail,
+{
+    pub(crate) user: Arc<Option<User>>,
+    pub(crate) username: Option<String>,
+    pub(crate) logged_in: bool,
+    pub(crate) client: SocketAddr,
+    pub(crate) cwd: PathBuf,
+    pub(crate) tls: bool,
+    pub(crate) sni_hostname: Option<String>,
+}
+
+impl<User> SessionInfo<User>
+where
+    User: UserDetail,
+{
+    /// Returns the logged in user or `None` if the client didn't log in yet.
+    pub fn user(&self) -> Option<&User> {
+        (*self.user).as_ref()
+    }
+
+    /// Returns the name given with `USER`, if any. It is set before the password was checked.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Tells if the client logged in successfully.
+    pub fn logged_in(&self) -> bool {
+        self.logged_in
+    }
+
+    /// Returns the address of the client.
+    pub fn client(&self) -> SocketAddr {
+        self.client
+    }
+
+    /// Returns the current working directory of the session.
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Tells if the control channel is encrypted.
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Returns the host name the client asked for with TLS SNI, if any.
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.sni_hostname.as_deref()
+    }
+}
+
+impl<User> Debug for SessionInfo<User>
+where
+    User: UserDetail,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionInfo")
+            .field("user", &self.user)
+            .field("username", &self.username)
+            .field("logged_in", &self.logged_in)
+            .field("client", &self.client)
+            .field("cwd", &self.cwd)
+            .field("tls", &self.tls)
+            .field("sni_hostname", &self.sni_hostname)
+            .finish()
+    }
+}
+
+/// The rest of the middleware chain, followed by libunftp's own handling of the command.
+pub struct Next<'a, User>
+where
+    User: UserDetail,
+{
+    middlewares: &'a [Arc<dyn ControlChanMiddleware<User>>],
+    session: &'a SessionInfo<User>,
+    handler: Box<dyn FnOnce(Request) -> BoxFuture<'a, Result<Reply, ControlChanError>> + Send + 'a>,
+}
+
+impl<'a, User> Next<'a, User>
+where
+    User: UserDetail,
+{
+    pub(crate) fn new(
+        middlewares: &'a [Arc<dyn ControlChanMiddleware<User>>],
+        session: &'a SessionInfo<User>,
+        handler: Box<dyn FnOnce(Request) -> BoxFuture<'a, Result<Reply, ControlChanError>> + Send + 'a>,
+    ) -> Self {
+        Next { middlewares, session, handler }
+    }
+
+    /// Passes the request on and returns the reply for it.
+    pub async fn run(self, request: Request) -> Result<Reply, ControlChanError> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                let next = Next {
+                    middlewares: rest,
+                    session: self.session,
+                    handler: self.handler,
+                };
+                middleware.handle(self.session, request, next).await
+            }
+            None => (self.handler)(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn request_from_line() {
+        let request = Request::from_line("site chmod 755 a b.txt\r\n");
+        assert_eq!(request.verb(), "SITE");
+        assert_eq!(request.argument(), "chmod 755 a b.txt");
+        assert_eq!(request.to_line(), "SITE chmod 755 a b.txt\r\n");
+        assert_eq!(Request::from_line("PWD\r\n"), Request::new("pwd", ""));
+        assert_eq!(Request::new("pwd", "").to_line(), "PWD\r\n");
+    }
+
+    #[test]
+    fn request_hides_password() {
+        let request = Request::from_line("PASS secret\r\n");
+        assert_eq!(request.argument(), "");
+        assert_eq!(request.password(), Some("secret"));
+        assert!(!format!("{:?}", request).contains("secret"));
+        assert_eq!(request.to_line(), "PASS secret\r\n");
+        assert_eq!(Request::from_line("USER koen\r\n").password(), None);
+    }
+}
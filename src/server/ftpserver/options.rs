@@ -1,11 +1,13 @@
 //! Contains code pertaining to the setup options that can be given to the [`Server`](crate::Server)
 
+use super::error::ServerError;
 use bitflags::bitflags;
 use std::fmt::Formatter;
 use std::ops::Range;
 use std::{
     fmt::{self, Debug, Display},
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 
 // Once we're sure about the types of these I think its good to expose it to the API user so that
@@ -16,16 +18,25 @@ pub(crate) const DEFAULT_PASSIVE_HOST: PassiveHost = PassiveHost::FromConnection
 pub(crate) const DEFAULT_PASSIVE_PORTS: Range<u16> = 49152..65535;
 pub(crate) const DEFAULT_FTPS_REQUIRE: FtpsRequired = FtpsRequired::None;
 pub(crate) const DEFAULT_FTPS_TRUST_STORE: &str = "./trusted.pem";
+pub(crate) const DEFAULT_ACTIVE_PASSIVE_MODE: ActivePassiveMode = ActivePassiveMode::PassiveOnly;
+pub(crate) const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 10;
 
 /// The option to `Server.passive_host`. It allows the user to specify how the IP address
 /// communicated in the _PASV_ response is determined.
+///
+/// Note that the _PASV_ response can only carry an IPv4 address. Clients connecting over IPv6 should
+/// use _EPSV_ instead, which only communicates the port.
 #[derive(Debug, PartialEq, Clone)]
 pub enum PassiveHost {
     /// Use the IP address of the control connection
     FromConnection,
     /// Advertise this specific IP address
     Ip(Ipv4Addr),
-    /// Resolve this DNS name into an IPv4 address.
+    /// Advertise this specific IPv6 address. Since _PASV_ can't carry it, clients are told to use
+    /// _EPSV_ instead.
+    Ipv6(Ipv6Addr),
+    /// Resolve this DNS name into an IP address. Both IPv4 and IPv6 addresses are considered but
+    /// one of the same family as the control connection is preferred.
     Dns(String),
     // We also be nice to have:
     // - PerUser(Box<dyn (Fn(Box<dyn UserDetail>) -> Ipv4Addr) + Send + Sync>) or something like
@@ -40,27 +51,74 @@ impl Default for PassiveHost {
     }
 }
 
+impl From<IpAddr> for PassiveHost {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => PassiveHost::Ip(ip),
+            IpAddr::V6(ip) => PassiveHost::Ipv6(ip),
+        }
+    }
+}
+
 impl From<Ipv4Addr> for PassiveHost {
     fn from(ip: Ipv4Addr) -> Self {
         PassiveHost::Ip(ip)
     }
 }
 
+impl From<Ipv6Addr> for PassiveHost {
+    fn from(ip: Ipv6Addr) -> Self {
+        PassiveHost::Ipv6(ip)
+    }
+}
+
 impl From<[u8; 4]> for PassiveHost {
     fn from(ip: [u8; 4]) -> Self {
         PassiveHost::Ip(ip.into())
     }
 }
 
+impl From<[u8; 16]> for PassiveHost {
+    fn from(ip: [u8; 16]) -> Self {
+        PassiveHost::Ipv6(ip.into())
+    }
+}
+
 impl From<&str> for PassiveHost {
     fn from(dns_or_ip: &str) -> Self {
-        match dns_or_ip.parse() {
-            Ok(IpAddr::V4(ip)) => PassiveHost::Ip(ip),
+        match dns_or_ip.parse::<IpAddr>() {
+            Ok(ip) => ip.into(),
             _ => PassiveHost::Dns(dns_or_ip.to_string()),
         }
     }
 }
 
+/// The option to `Server.active_passive_mode`. It allows the user to specify which data connection
+/// modes clients may use i.e. whether the client connects to us (passive mode, the `PASV` command)
+/// or whether we connect to the client (active mode, the `PORT` and `EPRT` commands).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ActivePassiveMode {
+    /// Only passive mode is allowed. This is the default.
+    #[default]
+    PassiveOnly,
+    /// Only active mode is allowed.
+    ActiveOnly,
+    /// Clients may choose between active and passive mode.
+    ActiveAndPassive,
+}
+
+impl ActivePassiveMode {
+    pub(crate) fn active_allowed(self) -> bool {
+        self != ActivePassiveMode::PassiveOnly
+    }
+
+    pub(crate) fn passive_allowed(self) -> bool {
+        self != ActivePassiveMode::ActiveOnly
+    }
+}
+
+impl Eq for ActivePassiveMode {}
+
 /// The option to `Server.ftps_required`. It allows the user to specify whether clients are required
 /// to upgrade a to secure TLS connection i.e. use FTPS.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -173,3 +231,157 @@ impl Default for SiteMd5 {
         SiteMd5::Accounts
     }
 }
+
+/// The option to `Server.partial_upload_policy`. It tells what happens to the file written so far
+/// when a client aborts an upload (`STOR`) with `ABOR`.
+///
+/// Uploads done with `APPE` or resumed with `REST` are always kept since the file already held data
+/// before the transfer started.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum PartialUploadPolicy {
+    /// Keep the partial file so that the client can resume the upload with `REST`.
+    #[default]
+    Keep,
+    /// Delete the partial file.
+    Delete,
+}
+
+/// The option to `Server.overwrite_policy` and [`UserDetail::overwrite_policy`]. It tells what
+/// happens when a client uploads a file with `STOR` to a path where a file exists already.
+///
+/// [`UserDetail::overwrite_policy`]: crate::auth::UserDetail::overwrite_policy
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum OverwritePolicy {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Refuse the upload with a 553 reply. The storage back-end creates the file only if it doesn't
+    /// exist so that two concurrent uploads can't both succeed.
+    Refuse,
+    /// Store the upload under a new unique name in the same directory. The name is sent to the
+    /// client in the 150 reply, like `STOU` does.
+    UniqueName,
+    /// Keep the existing file by renaming it to `<name>.1`, `<name>.2` and so on before the upload
+    /// starts. This needs a storage back-end that supports renaming files i.e. one that sets
+    /// [`FEATURE_RENAME`](crate::storage::FEATURE_RENAME). [`Server::listen`](crate::Server::listen)
+    /// fails if it is the server's policy and the back-end can't rename. Users that have it as
+    /// their own policy get [`Refuse`](OverwritePolicy::Refuse) instead.
+    Version,
+}
+
+/// The option to `Server.shutdown_indicator`. It tells how the server shuts down once the
+/// indicator resolves.
+///
+/// The server then stops accepting connections and closes idle sessions with a 421 reply. Sessions
+/// that are transferring a file may finish it within the grace period, after which they are closed
+/// as well.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Shutdown {
+    pub(crate) grace_period: Duration,
+}
+
+impl Shutdown {
+    /// Creates a shutdown with the default grace period of 10 seconds.
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    /// Sets how long running transfers may take to finish before their sessions are closed.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown {
+            grace_period: Duration::from_secs(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS),
+        }
+    }
+}
+
+/// The option to `Server.listen_on`. It is either an address for the server to bind to or a
+/// listener that is bound already, for instance one that was passed by systemd socket activation.
+///
+/// It can be created from a string like `"127.0.0.1:2121"`, a [`SocketAddr`], a
+/// [`std::net::TcpListener`] or a [`tokio::net::TcpListener`].
+pub struct Listener {
+    kind: ListenerKind,
+}
+
+enum ListenerKind {
+    Address(String),
+    SocketAddr(SocketAddr),
+    Std(std::net::TcpListener),
+    Tokio(tokio::net::TcpListener),
+}
+
+impl Listener {
+    // Binds to the address if needed and returns the listener to accept connections from.
+    pub(crate) async fn bind(self) -> Result<tokio::net::TcpListener, ServerError> {
+        match self.kind {
+            ListenerKind::Address(address) => {
+                let address: SocketAddr = address.parse()?;
+                Ok(tokio::net::TcpListener::bind(address).await?)
+            }
+            ListenerKind::SocketAddr(address) => Ok(tokio::net::TcpListener::bind(address).await?),
+            ListenerKind::Std(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(tokio::net::TcpListener::from_std(listener)?)
+            }
+            ListenerKind::Tokio(listener) => Ok(listener),
+        }
+    }
+}
+
+impl Debug for Listener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ListenerKind::Address(address) => write!(f, "Listener({})", address),
+            ListenerKind::SocketAddr(address) => write!(f, "Listener({})", address),
+            ListenerKind::Std(listener) => write!(f, "Listener({:?})", listener.local_addr()),
+            ListenerKind::Tokio(listener) => write!(f, "Listener({:?})", listener.local_addr()),
+        }
+    }
+}
+
+impl From<&str> for Listener {
+    fn from(address: &str) -> Self {
+        Listener {
+            kind: ListenerKind::Address(address.to_string()),
+        }
+    }
+}
+
+impl From<String> for Listener {
+    fn from(address: String) -> Self {
+        Listener {
+            kind: ListenerKind::Address(address),
+        }
+    }
+}
+
+impl From<SocketAddr> for Listener {
+    fn from(address: SocketAddr) -> Self {
+        Listener {
+            kind: ListenerKind::SocketAddr(address),
+        }
+    }
+}
+
+impl From<std::net::TcpListener> for Listener {
+    fn from(listener: std::net::TcpListener) -> Self {
+        Listener {
+            kind: ListenerKind::Std(listener),
+        }
+    }
+}
+
+impl From<tokio::net::TcpListener> for Listener {
+    fn from(listener: tokio::net::TcpListener) -> Self {
+        Listener {
+            kind: ListenerKind::Tokio(listener),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Contains the extension point for custom `SITE` subcommands that are registered with
+//! [`Server::site_command`](crate::Server::site_command).
+
+use crate::{
+    auth::{Permissions, UserDetail},
+    server::ftpserver::acl::{self, Acl},
+    storage::{self, StorageBackend},
+};
+use async_trait::async_trait;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// Handles a custom `SITE` subcommand, e.g. `SITE CHMOD` or `SITE QUOTA`.
+///
+/// The handler's reply is sent to the client with code 200. Multi-line replies are sent as a
+/// multi-line reply. The name of the subcommand is shown in the `FEAT` and `HELP` replies.
+///
+/// **libunftp doesn't check the permissions of `SITE` commands.** The storage back-end in the
+/// [`SiteContext`] is not limited by the [access control list](crate::acl) or
+/// [`UserDetail::permissions`]. A handler that works on paths must check
+/// [`SiteContext::permissions`] for each of them itself.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use libunftp::{
+///     auth::{DefaultUser, Permissions},
+///     site::{SiteCommand, SiteContext, SiteError},
+///     storage::StorageBackend,
+///     Server,
+/// };
+/// use std::sync::Arc;
+/// use unftp_sbe_fs::{Filesystem, ServerExt};
+///
+/// #[derive(Debug)]
+/// struct Quota;
+///
+/// #[async_trait]
+/// impl SiteCommand<Filesystem, DefaultUser> for Quota {
+///     async fn handle(&self, context: SiteContext<Filesystem, DefaultUser>, _args: &str) -> Result<String, SiteError> {
+///         Ok(format!("No quota for {}", context.user()))
+///     }
+/// }
+///
+/// #[derive(Debug)]
+/// struct Touch;
+///
+/// #[async_trait]
+/// impl SiteCommand<Filesystem, DefaultUser> for Touch {
+///     async fn handle(&self, context: SiteContext<Filesystem, DefaultUser>, args: &str) -> Result<String, SiteError> {
+///         if !context.permissions(args).contains(Permissions::WRITE) {
+///             return Err(SiteError::Failed(String::from("Permission denied")));
+///         }
+///         let path = context.cwd().join(args);
+///         context.storage().put(context.user(), &b""[..], path, 0).await?;
+///         Ok(String::from("Touched"))
+///     }
+/// }
+///
+/// let server = Server::with_fs("/srv/ftp").site_command("QUOTA", Arc::new(Quota)).site_command("TOUCH", Arc::new(Touch));
+/// ```
+#[async_trait]
+pub trait SiteCommand<Storage, User>: Send + Sync + Debug
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    /// Handles the subcommand. The arguments are the text after the name of the subcommand.
+    async fn handle(&self, context: SiteContext<Storage, User>, args: &str) -> Result<String, SiteError>;
+}
+
+/// The session state passed to a [`SiteCommand`].
+#[derive(Debug)]
+pub struct SiteContext<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    user: Arc<Option<User>>,
+    username: String,
+    cwd: PathBuf,
+    storage: Arc<Storage>,
+    acl: Option<Arc<Acl>>,
+}
+
+impl<Storage, User> SiteContext<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    pub(crate) fn new(user: Arc<Option<User>>, username: String, cwd: PathBuf, storage: Arc<Storage>, acl: Option<Arc<Acl>>) -> Self {
+        SiteContext {
+            user,
+            username,
+            cwd,
+            storage,
+            acl,
+        }
+    }
+
+    /// Returns the logged in user.
+    pub fn user(&self) -> &User {
+        // SITE commands are only accepted after login.
+        (*self.user).as_ref().unwrap()
+    }
+
+    /// Returns the current working directory of the session.
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Returns the storage back-end of the session. Paths given by the client should be joined
+    /// with the [`cwd`](SiteContext::cwd) before passing them to it.
+    ///
+    /// The back-end doesn't check any permissions. Use [`permissions`](SiteContext::permissions)
+    /// before touching a path.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Returns what the user may do on the given path according to [`UserDetail::permissions`]
+    /// and the [access control list](crate::acl). A relative path is taken relative to the
+    /// [`cwd`](SiteContext::cwd), like the built-in commands do.
+    pub fn permissions<P: AsRef<Path>>(&self, path: P) -> Permissions {
+        let path = acl::normalize(&self.cwd.join(path));
+        acl::effective_permissions(self.acl.as_deref(), self.user(), &self.username, &path)
+    }
+}
+
+/// The error returned by a [`SiteCommand`].
+#[derive(Debug, Error)]
+pub enum SiteError {
+    /// The arguments of the subcommand are invalid. Replied with code 501.
+    #[error("invalid arguments: {0}")]
+    InvalidArguments(String),
+    /// The subcommand failed. Replied with code 550.
+    #[error("{0}")]
+    Failed(String),
+    /// The storage back-end returned an error. Replied in the same way as storage errors of the
+    /// built-in commands.
+    #[error(transparent)]
+    Storage(#[from] storage::Error),
+}
@@ -0,0 +1,124 @@
+//! Contains the configuration of the name-based virtual hosts that clients select with the `HOST`
+//! command (RFC 7151).
+
+use crate::{
+    auth::{Authenticator, UserDetail},
+    server::tls::FtpsConfig,
+    storage::StorageBackend,
+};
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+
+/// The configuration of a virtual host that can be registered with
+/// [`Server::virtual_host`](crate::Server::virtual_host). Clients select it by sending the
+/// `HOST` command (see [RFC 7151](https://tools.ietf.org/html/rfc7151)) before logging in.
+///
+/// Everything that is not configured for the virtual host is taken from the [`Server`](crate::Server).
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{auth::AnonymousAuthenticator, Server, VirtualHost};
+/// use unftp_sbe_fs::{Filesystem, ServerExt};
+/// use std::sync::Arc;
+///
+/// let server = Server::with_fs("/srv/ftp/default")
+///     .virtual_host(
+///         VirtualHost::new("ftp.example.com")
+///             .greeting("Welcome to example.com")
+///             .authenticator(Arc::new(AnonymousAuthenticator {}))
+///             .storage(Box::new(|| Filesystem::new("/srv/ftp/example.com"))),
+///     );
+/// ```
+pub struct VirtualHost<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    pub(crate) hostname: String,
+    pub(crate) greeting: Option<&'static str>,
+    pub(crate) authenticator: Option<Arc<dyn Authenticator<User>>>,
+    pub(crate) storage: Option<Arc<dyn (Fn() -> Storage) + Send + Sync>>,
+    pub(crate) ftps_mode: FtpsConfig,
+}
+
+impl<Storage, User> VirtualHost<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    /// Creates the configuration of the virtual host with the given name. This is the name clients
+    /// pass to the `HOST` command and is matched case insensitively.
+    pub fn new<H: Into<String>>(hostname: H) -> Self {
+        VirtualHost {
+            hostname: hostname.into(),
+            greeting: None,
+            authenticator: None,
+            storage: None,
+            ftps_mode: FtpsConfig::Off,
+        }
+    }
+
+    /// Sets the greeting sent to the client in reply to the `HOST` command.
+    pub fn greeting(mut self, greeting: &'static str) -> Self {
+        self.greeting = Some(greeting);
+        self
+    }
+
+    /// Sets the [`Authenticator`](crate::auth::Authenticator) used to log in to the virtual host.
+    pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator<User> + Send + Sync>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Sets the [`StorageBackend`](crate::storage::StorageBackend) generator for sessions on the
+    /// virtual host. Note that the `FEAT` reply and feature checks are based on the storage back-end
+    /// of the server so the back-end should support the same features.
+    pub fn storage(mut self, sbe_generator: Box<dyn (Fn() -> Storage) + Send + Sync>) -> Self {
+        self.storage = Some(Arc::from(sbe_generator));
+        self
+    }
+
+    /// Sets the certificates file and the private key file, both in PEM format, used for FTPS on
+    /// the virtual host. The other FTPS settings are taken from the server and FTPS has to be
+    /// enabled on the server with [`Server::ftps`](crate::Server::ftps) for this to have any effect.
+    /// Clients need to send `HOST` before `AUTH TLS` to get this certificate.
+    pub fn ftps<P: Into<PathBuf>>(mut self, certs_file: P, key_file: P) -> Self {
+        self.ftps_mode = FtpsConfig::Building {
+            certs_file: certs_file.into(),
+            key_file: key_file.into(),
+        };
+        self
+    }
+}
+
+impl<Storage, User> Clone for VirtualHost<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    fn clone(&self) -> Self {
+        VirtualHost {
+            hostname: self.hostname.clone(),
+            greeting: self.greeting,
+            authenticator: self.authenticator.clone(),
+            storage: self.storage.clone(),
+            ftps_mode: self.ftps_mode.clone(),
+        }
+    }
+}
+
+impl<Storage, User> Debug for VirtualHost<Storage, User>
+where
+    Storage: StorageBackend<User>,
+    User: UserDetail,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualHost")
+            .field("hostname", &self.hostname)
+            .field("greeting", &self.greeting)
+            .field("authenticator", &self.authenticator)
+            .field("storage", &self.storage.is_some())
+            .field("ftps_mode", &self.ftps_mode)
+            .finish()
+    }
+}
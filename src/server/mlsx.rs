@@ -0,0 +1,222 @@
+//! Contains the machine readable listing format used by the RFC 3659 `MLSD` and `MLST` commands.
+//
+// Each entry is a list of facts followed by a space and the pathname e.g.
+//
+// type=file;size=1024;modify=20210713101530;perm=adfrw;unix.mode=0644; data.csv
+
+use crate::{auth::Permissions, storage::Metadata};
+use bitflags::bitflags;
+use chrono::prelude::{DateTime, Utc};
+use std::fmt::Write;
+
+bitflags! {
+    /// The facts we support in `MLSD` and `MLST` entries. The client can select the ones it wants
+    /// to see with `OPTS MLST`.
+    pub struct Facts: u32 {
+        const TYPE      = 0b00000001;
+        const SIZE      = 0b00000010;
+        const MODIFY    = 0b00000100;
+        const PERM      = 0b00001000;
+        const UNIQUE    = 0b00010000;
+        const UNIX_MODE = 0b00100000;
+    }
+}
+
+impl Default for Facts {
+    fn default() -> Facts {
+        Facts::all()
+    }
+}
+
+// The fact names in the order we list them.
+const FACT_NAMES: [(Facts, &str); 6] = [
+    (Facts::TYPE, "type"),
+    (Facts::SIZE, "size"),
+    (Facts::MODIFY, "modify"),
+    (Facts::PERM, "perm"),
+    (Facts::UNIQUE, "unique"),
+    (Facts::UNIX_MODE, "unix.mode"),
+];
+
+impl Facts {
+    /// Parses the argument of `OPTS MLST` e.g. `type;size;modify;`. Fact names are case insensitive
+    /// and unknown facts are ignored as required by RFC 3659.
+    pub(crate) fn parse(list: &str) -> Facts {
+        list.split(';')
+            .filter_map(|name| FACT_NAMES.iter().find(|(_, n)| n.eq_ignore_ascii_case(name.trim())))
+            .fold(Facts::empty(), |facts, (fact, _)| facts | *fact)
+    }
+
+    /// Returns the fact list as shown in the `FEAT` and `OPTS MLST` replies. In the `FEAT` form the
+    /// selected facts are marked with an asterisk.
+    pub(crate) fn to_feat_string(self, mark_selected: bool) -> String {
+        FACT_NAMES.iter().fold(String::new(), |mut s, (fact, name)| {
+            if mark_selected {
+                s.push_str(name);
+                if self.contains(*fact) {
+                    s.push('*');
+                }
+                s.push(';');
+            } else if self.contains(*fact) {
+                s.push_str(name);
+                s.push(';');
+            }
+            s
+        })
+    }
+}
+
+/// Formats a single `MLSD`/`MLST` entry (without line ending) for a file with the given name. The
+/// `perm` fact is derived from the permissions the user has on the file.
+pub(crate) fn format_entry<M: Metadata>(name: &str, metadata: &M, permissions: Permissions, facts: Facts) -> String {
+    let mut line = String::new();
+    if facts.contains(Facts::TYPE) {
+        let file_type = if metadata.is_dir() {
+            "dir"
+        } else if metadata.is_symlink() {
+            "OS.unix=slink"
+        } else {
+            "file"
+        };
+        let _ = write!(line, "type={};", file_type);
+    }
+    if facts.contains(Facts::SIZE) && !metadata.is_dir() {
+        let _ = write!(line, "size={};", metadata.len());
+    }
+    if facts.contains(Facts::MODIFY) {
+        if let Ok(modified) = metadata.modified() {
+            let _ = write!(line, "modify={};", DateTime::<Utc>::from(modified).format("%Y%m%d%H%M%S"));
+        }
+    }
+    if facts.contains(Facts::PERM) {
+        let _ = write!(line, "perm={};", perm_fact(metadata.is_dir(), permissions));
+    }
+    if facts.contains(Facts::UNIQUE) {
+        if let Some(unique) = metadata.unique() {
+            let _ = write!(line, "unique={};", unique);
+        }
+    }
+    if facts.contains(Facts::UNIX_MODE) {
+        if let Some(mode) = metadata.unix_mode() {
+            let _ = write!(line, "unix.mode={:04o};", mode & 0o7777);
+        }
+    }
+    line.push(' ');
+    line.push_str(name);
+    line
+}
+
+// Derives the RFC 3659 perm fact from what the user may do with the file or directory.
+fn perm_fact(is_dir: bool, permissions: Permissions) -> String {
+    let letters: &[(char, Permissions)] = if is_dir {
+        &[
+            ('c', Permissions::WRITE),
+            ('d', Permissions::DELETE),
+            ('e', Permissions::LIST),
+            ('f', Permissions::RENAME),
+            ('l', Permissions::LIST),
+            ('m', Permissions::MKDIR),
+            ('p', Permissions::DELETE),
+        ]
+    } else {
+        &[
+            ('a', Permissions::WRITE),
+            ('d', Permissions::DELETE),
+            ('f', Permissions::RENAME),
+            ('r', Permissions::READ),
+            ('w', Permissions::WRITE),
+        ]
+    };
+    letters
+        .iter()
+        .filter(|(_, needed)| permissions.contains(*needed))
+        .map(|(letter, _)| letter)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Result;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
+
+    struct Meta {
+        dir: bool,
+        mode: Option<u32>,
+    }
+
+    impl Metadata for Meta {
+        fn len(&self) -> u64 {
+            1024
+        }
+
+        fn is_dir(&self) -> bool {
+            self.dir
+        }
+
+        fn is_file(&self) -> bool {
+            !self.dir
+        }
+
+        fn is_symlink(&self) -> bool {
+            false
+        }
+
+        fn modified(&self) -> Result<SystemTime> {
+            Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(1_626_171_330))
+        }
+
+        fn gid(&self) -> u32 {
+            0
+        }
+
+        fn uid(&self) -> u32 {
+            0
+        }
+
+        fn unix_mode(&self) -> Option<u32> {
+            self.mode
+        }
+    }
+
+    #[test]
+    fn formats_file_entry() {
+        let meta = Meta { dir: false, mode: Some(0o644) };
+        assert_eq!(
+            format_entry("data.csv", &meta, Permissions::all(), Facts::default()),
+            "type=file;size=1024;modify=20210713101530;perm=adfrw;unix.mode=0644; data.csv"
+        );
+        assert_eq!(
+            format_entry("data.csv", &meta, Permissions::all(), Facts::TYPE | Facts::SIZE),
+            "type=file;size=1024; data.csv"
+        );
+    }
+
+    #[test]
+    fn formats_dir_entry() {
+        let meta = Meta { dir: true, mode: Some(0o555) };
+        assert_eq!(
+            format_entry("sub", &meta, Permissions::READ_ONLY, Facts::default()),
+            "type=dir;modify=20210713101530;perm=el;unix.mode=0555; sub"
+        );
+    }
+
+    #[test]
+    fn derives_perm_from_user_permissions() {
+        let meta = Meta { dir: false, mode: None };
+        assert_eq!(
+            format_entry("data.csv", &meta, Permissions::READ_ONLY, Facts::default()),
+            "type=file;size=1024;modify=20210713101530;perm=r; data.csv"
+        );
+        assert_eq!(format_entry("data.csv", &meta, Permissions::UPLOAD_ONLY, Facts::PERM), "perm=aw; data.csv");
+    }
+
+    #[test]
+    fn parses_fact_selection() {
+        assert_eq!(Facts::parse("type;Size;bogus;"), Facts::TYPE | Facts::SIZE);
+        assert_eq!(Facts::parse(""), Facts::empty());
+        assert_eq!((Facts::TYPE | Facts::MODIFY).to_feat_string(false), "type;modify;");
+        assert_eq!(Facts::TYPE.to_feat_string(true), "type*;size;modify;perm;unique;unix.mode;");
+    }
+}
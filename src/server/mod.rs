@@ -1,18 +1,23 @@
 //! Contains the [`Server`](crate::Server) struct that is used to configure and control an FTP server instance.
 
+mod ascii;
 mod chancomms;
+mod charset;
 pub(crate) mod controlchan;
 mod datachan;
 pub(crate) mod ftpserver;
+mod mlsx;
 mod password;
 mod proxy_protocol;
 mod session;
-mod tls;
+mod shutdown;
+pub(crate) mod tls;
+pub(crate) mod transfer;
 
 pub(crate) use chancomms::ControlChanMsg;
 pub(crate) use controlchan::command::Command;
 pub(crate) use controlchan::reply::{Reply, ReplyCode};
-pub(crate) use controlchan::ControlChanMiddleware;
 pub(crate) use controlchan::Event;
+pub(crate) use controlchan::EventHandler;
 pub(crate) use controlchan::{ControlChanError, ControlChanErrorKind};
 pub(self) use session::{Session, SessionState};
@@ -24,7 +24,7 @@ pub enum ProxyError {
     HeaderSize,
     NotProxyHdr,
     DecodeError,
-    IPv4Required,
+    UnsupportedAddressFamily,
     UnsupportedVersion,
 }
 
@@ -100,13 +100,13 @@ pub async fn get_peer_from_proxy_header(tcp_stream: &mut tokio::net::TcpStream)
             destination_port,
             ..
         } => {
-            if family == ProxyAddressFamily::IPv4 {
+            if family == ProxyAddressFamily::IPv4 || family == ProxyAddressFamily::IPv6 {
                 Ok(ConnectionTuple {
                     source: SocketAddr::new(source, source_port),
                     destination: SocketAddr::new(destination, destination_port),
                 })
             } else {
-                Err(ProxyError::IPv4Required)
+                Err(ProxyError::UnsupportedAddressFamily)
             }
         }
         _ => Err(ProxyError::UnsupportedVersion),
@@ -1,12 +1,23 @@
 //! The session module implements per-connection session handling and currently also
 //! implements the handling for the *data* channel.
 
-use super::{chancomms::ControlChanMsg, tls::FtpsConfig};
-use crate::auth::UserDetail;
+use super::{
+    chancomms::ControlChanMsg,
+    charset::Charset,
+    controlchan::commands::TypeParam,
+    ftpserver::{
+        acl::Acl,
+        options::{OverwritePolicy, PartialUploadPolicy},
+    },
+    mlsx::Facts,
+    tls::FtpsConfig,
+    transfer::{Transfer, TransferMonitor},
+};
+use crate::auth::{Authenticator, UserDetail};
 use crate::server::chancomms::DataChanCmd;
 use crate::{
     metrics,
-    storage::{Metadata, StorageBackend},
+    storage::{HashAlgorithm, Metadata, StorageBackend},
 };
 use futures::channel::mpsc::{Receiver, Sender};
 use std::{
@@ -16,6 +27,9 @@ use std::{
     sync::Arc,
 };
 
+// The compression level used in `MODE Z` until the client picks another one. It is the zlib default.
+const DEFAULT_MODE_Z_LEVEL: u32 = 6;
+
 // TraceId is an identifier used to correlate logs statements together.
 #[derive(PartialEq, Eq, Debug)]
 pub struct TraceId(u64);
@@ -91,6 +105,8 @@ where
     pub cmd_tls: bool,
     // True if the data channel is in secure mode at the moment. Changed by the PROT command.
     pub data_tls: bool,
+    // True if the session uses implicit FTPS. The data channel may then not be switched to plaintext.
+    pub ftps_implicit: bool,
     // True if metrics for prometheus are updated.
     pub collect_metrics: bool,
     // The starting byte for a STOR or RETR command. Set by the _Restart of Interrupted Transfer (REST)_
@@ -101,6 +117,41 @@ where
     pub data_busy: bool,
     // The client certificate chain if it was received.
     pub cert_chain: Option<Vec<crate::auth::ClientCert>>,
+    // The host name the client asked for with TLS SNI on the control channel.
+    pub sni_hostname: Option<String>,
+    // Set by `EPSV ALL`. From then on the client may only use EPSV to set up data connections.
+    pub epsv_all: bool,
+    // The facts to show in MLSD and MLST listings. Changed by `OPTS MLST`.
+    pub mlst_facts: Facts,
+    // Set by `MODE Z`. Data transfers are then compressed with deflate.
+    pub mode_z: bool,
+    // The compression level (0-9) to use in `MODE Z`. Changed by `OPTS MODE Z LEVEL`.
+    pub mode_z_level: u32,
+    // The representation type set by the `TYPE` command. Files are converted to and from CRLF line
+    // endings in ASCII mode.
+    pub data_type: TypeParam,
+    // The algorithm used by the `HASH` command. Changed by `OPTS HASH`.
+    pub hash_algorithm: HashAlgorithm,
+    // The inclusive byte range set by `RANG`. It only applies to the next `HASH` command.
+    pub hash_range: Option<(u64, u64)>,
+    // The name of the virtual host selected with the `HOST` command.
+    pub virtual_host: Option<String>,
+    // The authenticator of the selected virtual host if it has one. Otherwise the server's is used.
+    pub authenticator: Option<Arc<dyn Authenticator<User>>>,
+    // The charset used for paths on the control channel and in directory listings. Switched by
+    // `OPTS UTF8`.
+    pub charset: Charset,
+    // Tells what to do with the partial file when a `STOR` is aborted.
+    pub partial_upload_policy: PartialUploadPolicy,
+    // Tells what to do when a `STOR` targets a file that exists already, unless the user has their
+    // own policy.
+    pub overwrite_policy: OverwritePolicy,
+    // The file transfer in progress, reported by `STAT`.
+    pub transfer: Option<Transfer>,
+    // Where file transfers are registered so that library users can inspect them.
+    pub transfer_monitor: Option<TransferMonitor>,
+    // The access control list that limits what the user may do per path.
+    pub acl: Option<Arc<Acl>>,
 }
 
 impl<Storage, User> Session<Storage, User>
@@ -128,10 +179,27 @@ where
             ftps_config: FtpsConfig::Off,
             cmd_tls: false,
             data_tls: false,
+            ftps_implicit: false,
             collect_metrics: false,
             start_pos: 0,
             data_busy: false,
             cert_chain: None,
+            sni_hostname: None,
+            epsv_all: false,
+            mlst_facts: Facts::default(),
+            mode_z: false,
+            mode_z_level: DEFAULT_MODE_Z_LEVEL,
+            data_type: TypeParam::Image,
+            hash_algorithm: HashAlgorithm::Sha256,
+            hash_range: None,
+            virtual_host: None,
+            authenticator: None,
+            charset: Charset::new(None),
+            partial_upload_policy: PartialUploadPolicy::default(),
+            overwrite_policy: OverwritePolicy::default(),
+            transfer: None,
+            transfer_monitor: None,
+            acl: None,
         }
     }
 
@@ -140,6 +208,39 @@ where
         self
     }
 
+    // With implicit FTPS both channels are encrypted from the start.
+    pub fn ftps_implicit(mut self, implicit: bool) -> Self {
+        self.cmd_tls = implicit;
+        self.data_tls = implicit;
+        self.ftps_implicit = implicit;
+        self
+    }
+
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    pub fn partial_upload_policy(mut self, policy: PartialUploadPolicy) -> Self {
+        self.partial_upload_policy = policy;
+        self
+    }
+
+    pub fn overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = policy;
+        self
+    }
+
+    pub fn transfer_monitor(mut self, monitor: Option<TransferMonitor>) -> Self {
+        self.transfer_monitor = monitor;
+        self
+    }
+
+    pub fn acl(mut self, acl: Option<Arc<Acl>>) -> Self {
+        self.acl = acl;
+        self
+    }
+
     pub fn metrics(mut self, collect_metrics: bool) -> Self {
         if collect_metrics {
             metrics::inc_session();
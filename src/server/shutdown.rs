@@ -0,0 +1,84 @@
+//! Contains the signalling between the [`Server`](crate::Server) and its sessions when it shuts
+//! down.
+
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+// Tells the sessions that the server is shutting down and waits for them to end. Every session
+// holds a Listener. The Listeners keep a sender of the `done` channel alive so that receiving from
+// it only ends once all sessions ended.
+#[derive(Debug)]
+pub(crate) struct Notifier {
+    shutdown_tx: broadcast::Sender<Duration>,
+    done_tx: mpsc::Sender<()>,
+    done_rx: mpsc::Receiver<()>,
+}
+
+impl Notifier {
+    pub(crate) fn new() -> Self {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let (done_tx, done_rx) = mpsc::channel(1);
+        Notifier { shutdown_tx, done_tx, done_rx }
+    }
+
+    pub(crate) fn subscribe(&self) -> Listener {
+        Listener {
+            shutdown_rx: self.shutdown_tx.subscribe(),
+            _done_tx: self.done_tx.clone(),
+        }
+    }
+
+    // Notifies the sessions and waits for them to end. Returns false if sessions were still running
+    // after the grace period.
+    pub(crate) async fn notify_and_wait(self, grace_period: Duration) -> bool {
+        let Notifier {
+            shutdown_tx,
+            done_tx,
+            mut done_rx,
+        } = self;
+        // Fails if there are no sessions, which is fine.
+        let _ = shutdown_tx.send(grace_period);
+        drop(done_tx);
+        tokio::time::timeout(grace_period, done_rx.recv()).await.is_ok()
+    }
+}
+
+// The session side of the Notifier.
+#[derive(Debug)]
+pub(crate) struct Listener {
+    shutdown_rx: broadcast::Receiver<Duration>,
+    _done_tx: mpsc::Sender<()>,
+}
+
+impl Listener {
+    // Resolves with the grace period once the server shuts down.
+    pub(crate) async fn listen(&mut self) -> Duration {
+        match self.shutdown_rx.recv().await {
+            Ok(grace_period) => grace_period,
+            // The server stopped without shutting down e.g. because its listen future was dropped.
+            // Sessions then keep running like they did before graceful shutdown existed.
+            Err(_) => futures::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_listeners() {
+        let notifier = Notifier::new();
+        let mut listener = notifier.subscribe();
+        let session = tokio::spawn(async move {
+            let grace_period = listener.listen().await;
+            assert_eq!(grace_period, Duration::from_secs(5));
+        });
+        assert!(notifier.notify_and_wait(Duration::from_secs(5)).await);
+        session.await.unwrap();
+
+        let notifier = Notifier::new();
+        let _listener = notifier.subscribe();
+        assert!(!notifier.notify_and_wait(Duration::from_millis(10)).await);
+    }
+}
@@ -1,20 +1,24 @@
 use crate::options::{FtpsClientAuth, TlsFlags};
-use rustls::{internal::pemfile, Certificate, NoClientAuth, NoServerSessionStorage, PrivateKey, ProtocolVersion, RootCertStore, ServerConfig, Ticketer};
+use rustls::{
+    internal::pemfile, sign::CertifiedKey, Certificate, ClientHello, NoClientAuth, NoServerSessionStorage, PrivateKey, ProtocolVersion, ResolvesServerCert,
+    RootCertStore, ServerConfig, Ticketer,
+};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
 // FTPSConfig shows how TLS security is configured for the server or a particular channel.
 #[derive(Clone)]
 pub enum FtpsConfig {
     Off,
     Building { certs_file: PathBuf, key_file: PathBuf },
-    On { tls_config: Arc<ServerConfig> },
+    On { tls_config: ReloadableConfig },
 }
 
 impl fmt::Debug for FtpsConfig {
@@ -38,15 +42,221 @@ impl fmt::Display for FtpsNotAvailable {
 
 impl Error for FtpsNotAvailable {}
 
+// A certificate that is used when the client asks for a specific host name with TLS SNI.
+#[derive(Debug, Clone)]
+pub struct SniCertificate {
+    pub hostname: String,
+    pub certs_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+// The files and options a TLS configuration is built from.
+#[derive(Debug)]
+struct ConfigSource {
+    certs_file: PathBuf,
+    key_file: PathBuf,
+    sni_certificates: Vec<SniCertificate>,
+    trust_store: PathBuf,
+    flags: TlsFlags,
+    client_auth: FtpsClientAuth,
+}
+
+impl ConfigSource {
+    fn files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = vec![&self.certs_file, &self.key_file];
+        for sni in &self.sni_certificates {
+            files.push(&sni.certs_file);
+            files.push(&sni.key_file);
+        }
+        if self.client_auth != FtpsClientAuth::Off {
+            files.push(&self.trust_store);
+        }
+        files
+    }
+}
+
+// A TLS configuration that can be rebuilt from its files while the server runs. A snapshot of it is
+// taken for every TLS handshake so that connections that are secured already aren't affected by a
+// reload.
+#[derive(Clone)]
+pub struct ReloadableConfig {
+    source: Arc<ConfigSource>,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl ReloadableConfig {
+    pub fn load<P: Into<PathBuf>>(
+        certs_file: P,
+        key_file: P,
+        sni_certificates: Vec<SniCertificate>,
+        flags: TlsFlags,
+        client_auth: FtpsClientAuth,
+        trust_store: P,
+    ) -> std::io::Result<Self> {
+        let source = ConfigSource {
+            certs_file: certs_file.into(),
+            key_file: key_file.into(),
+            sni_certificates,
+            trust_store: trust_store.into(),
+            flags,
+            client_auth,
+        };
+        let config = new_config(
+            &source.certs_file,
+            &source.key_file,
+            &source.sni_certificates,
+            flags,
+            client_auth,
+            &source.trust_store,
+        )?;
+        Ok(ReloadableConfig {
+            source: Arc::new(source),
+            current: Arc::new(RwLock::new(config)),
+        })
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Rebuilds the configuration from its files. The previous one stays in use if that fails.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let source = &self.source;
+        let mut config = build_config(
+            &source.certs_file,
+            &source.key_file,
+            &source.sni_certificates,
+            source.flags,
+            source.client_auth,
+            &source.trust_store,
+        )?;
+        // Keep the TLS sessions so that clients can still resume them, for instance on the data
+        // channel of a session that started before the reload.
+        let previous = self.current();
+        config.session_storage = previous.session_storage.clone();
+        config.ticketer = previous.ticketer.clone();
+        *self.current.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    // Returns the time the most recently changed file of the configuration was modified.
+    fn modified(&self) -> Option<SystemTime> {
+        self.source
+            .files()
+            .into_iter()
+            .filter_map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .max()
+    }
+}
+
+impl fmt::Debug for ReloadableConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableConfig").field("source", &self.source).finish()
+    }
+}
+
+/// Reloads the FTPS certificates, private keys and trust stores of a [`Server`](crate::Server) and
+/// its virtual hosts from disk. New connections use the reloaded files while connections that are
+/// secured already keep working. Pass a clone of it to
+/// [`Server::ftps_reloader`](crate::Server::ftps_reloader) and call [`reload`](TlsReloader::reload)
+/// e.g. after your certificate was renewed.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{Server, TlsReloader};
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let reloader = TlsReloader::new();
+/// let server = Server::with_fs("/srv/ftp")
+///     .ftps("/srv/unftp/server.certs", "/srv/unftp/server.key")
+///     .ftps_reloader(reloader.clone());
+/// // ...
+/// if let Err(err) = reloader.reload() {
+///     eprintln!("Could not reload the certificates: {}", err);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TlsReloader {
+    configs: Arc<Mutex<Vec<ReloadableConfig>>>,
+}
+
+impl TlsReloader {
+    /// Creates a reloader that isn't used by any server yet.
+    pub fn new() -> Self {
+        TlsReloader::default()
+    }
+
+    /// Reloads the TLS configurations of the servers that use this reloader. If the files of a
+    /// configuration can't be loaded, the configuration that was in use stays in use and the error
+    /// is returned after the others were reloaded.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let configs = self.configs.lock().unwrap();
+        let mut result = Ok(());
+        for config in configs.iter() {
+            if let Err(err) = config.reload() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    pub(crate) fn register(&self, config: ReloadableConfig) {
+        self.configs.lock().unwrap().push(config);
+    }
+}
+
+// Checks the files of the given configurations every interval and reloads the configurations of
+// which the files changed. Never returns.
+pub async fn watch(configs: Vec<ReloadableConfig>, interval: Duration, logger: slog::Logger) {
+    let mut last_modified: Vec<Option<SystemTime>> = configs.iter().map(ReloadableConfig::modified).collect();
+    loop {
+        tokio::time::sleep(interval).await;
+        for (config, last_modified) in configs.iter().zip(last_modified.iter_mut()) {
+            let modified = config.modified();
+            if modified == *last_modified {
+                continue;
+            }
+            // On failure the files are checked again after the next interval since they may have
+            // been in the middle of being replaced.
+            match config.reload() {
+                Ok(()) => {
+                    slog::info!(logger, "Reloaded TLS configuration {:?}", config.source);
+                    *last_modified = modified;
+                }
+                Err(err) => slog::warn!(logger, "Could not reload TLS configuration {:?}: {}", config.source, err),
+            }
+        }
+    }
+}
+
 pub fn new_config<P: AsRef<Path>>(
     certs_file: P,
     key_file: P,
+    sni_certificates: &[SniCertificate],
     flags: TlsFlags,
     client_auth: FtpsClientAuth,
     trust_store: P,
 ) -> std::io::Result<Arc<ServerConfig>> {
-    let certs: Vec<Certificate> = load_certs(certs_file)?;
-    let privkey: PrivateKey = load_private_key(key_file)?;
+    Ok(Arc::new(build_config(certs_file, key_file, sni_certificates, flags, client_auth, trust_store)?))
+}
+
+fn build_config<P: AsRef<Path>>(
+    certs_file: P,
+    key_file: P,
+    sni_certificates: &[SniCertificate],
+    flags: TlsFlags,
+    client_auth: FtpsClientAuth,
+    trust_store: P,
+) -> std::io::Result<ServerConfig> {
+    let mut resolver = SniResolver {
+        default: load_certified_key(certs_file, key_file)?,
+        by_hostname: HashMap::new(),
+    };
+    for sni in sni_certificates {
+        let key = load_certified_key(&sni.certs_file, &sni.key_file)?;
+        resolver.by_hostname.insert(sni.hostname.to_ascii_lowercase(), key);
+    }
 
     let client_auther = match client_auth {
         FtpsClientAuth::Off => NoClientAuth::new(),
@@ -73,10 +283,8 @@ pub fn new_config<P: AsRef<Path>>(
     };
     // Don't allow dumping session keys
     config.key_log = Arc::new(rustls::NoKeyLog {});
-    // No SNI, single certificate
-    config
-        .set_single_cert(certs, privkey)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // The certificate is chosen by the host name the client asks for with SNI
+    config.cert_resolver = Arc::new(resolver);
 
     let mut versions: Vec<ProtocolVersion> = vec![];
     if flags.contains(TlsFlags::V1_2) {
@@ -87,7 +295,39 @@ pub fn new_config<P: AsRef<Path>>(
     }
     config.versions = versions;
 
-    Ok(Arc::new(config))
+    Ok(config)
+}
+
+// Picks the certificate for the host name that the client sent with SNI, falling back to the default
+// certificate.
+struct SniResolver {
+    default: CertifiedKey,
+    by_hostname: HashMap<String, CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let key = client_hello
+            .server_name()
+            .and_then(|name| {
+                let name: &str = name.into();
+                self.by_hostname.get(&name.to_ascii_lowercase())
+            })
+            .unwrap_or(&self.default);
+        Some(key.clone())
+    }
+}
+
+fn load_certified_key<P: AsRef<Path>>(certs_file: P, key_file: P) -> std::io::Result<CertifiedKey> {
+    let certs: Vec<Certificate> = load_certs(certs_file)?;
+    let key_file = key_file.as_ref();
+    let privkey: PrivateKey = load_private_key(key_file)?;
+    let signing_key = rustls::sign::any_supported_type(&privkey)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("unsupported private key in {:?}", key_file)))?;
+    let key = CertifiedKey::new(certs, Arc::new(signing_key));
+    key.cross_check_end_entity_cert(None)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(key)
 }
 
 fn root_cert_store<P: AsRef<Path>>(trust_pem: P) -> std::io::Result<RootCertStore> {
@@ -0,0 +1,217 @@
+//! Contains the progress tracking of file transfers that is reported by `STAT` and the
+//! [`TransferMonitor`].
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A snapshot of the progress of a running file transfer (`RETR`, `STOR` or `APPE`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferStatus {
+    /// The name of the user that started the transfer, if logged in.
+    pub username: Option<String>,
+    /// The address of the client on the control channel.
+    pub client: SocketAddr,
+    /// The FTP command that started the transfer e.g. `RETR`.
+    pub command: &'static str,
+    /// The path of the file being transferred.
+    pub path: PathBuf,
+    /// The number of bytes transferred so far.
+    pub bytes: u64,
+    /// When the transfer started.
+    pub started: SystemTime,
+}
+
+impl TransferStatus {
+    /// Returns how long the transfer has been running.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed().unwrap_or_default()
+    }
+
+    /// Returns the average throughput of the transfer so far in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+// A running transfer. The data loop counts the transferred bytes while the control channel and the
+// transfer monitor read them.
+#[derive(Debug, Clone)]
+pub(crate) struct Transfer {
+    username: Option<String>,
+    client: SocketAddr,
+    command: &'static str,
+    path: PathBuf,
+    started: SystemTime,
+    bytes: Arc<AtomicU64>,
+}
+
+impl Transfer {
+    pub(crate) fn new(username: Option<String>, client: SocketAddr, command: &'static str, path: PathBuf) -> Self {
+        Transfer {
+            username,
+            client,
+            command,
+            path,
+            started: SystemTime::now(),
+            bytes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn status(&self) -> TransferStatus {
+        TransferStatus {
+            username: self.username.clone(),
+            client: self.client,
+            command: self.command,
+            path: self.path.clone(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            started: self.started,
+        }
+    }
+
+    // Wraps the given data connection stream so that the bytes read from or written to it are counted.
+    pub(crate) fn count<T>(&self, inner: T) -> CountingStream<T> {
+        CountingStream {
+            inner,
+            bytes: Arc::clone(&self.bytes),
+        }
+    }
+}
+
+/// Keeps track of the file transfers running on a [`Server`](crate::Server) so that you can see
+/// what its sessions are doing. Pass a clone of it to
+/// [`Server::transfer_monitor`](crate::Server::transfer_monitor) and call
+/// [`transfers`](TransferMonitor::transfers) whenever you need the current state.
+///
+/// # Example
+///
+/// ```rust
+/// use libunftp::{Server, TransferMonitor};
+/// use unftp_sbe_fs::ServerExt;
+///
+/// let monitor = TransferMonitor::new();
+/// let server = Server::with_fs("/srv/ftp").transfer_monitor(monitor.clone());
+/// // ...
+/// for transfer in monitor.transfers() {
+///     println!("{} {:?}: {} bytes", transfer.command, transfer.path, transfer.bytes);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TransferMonitor {
+    inner: Arc<Mutex<MonitorState>>,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    next_id: u64,
+    transfers: HashMap<u64, Transfer>,
+}
+
+impl TransferMonitor {
+    /// Creates a monitor that doesn't track any transfers yet.
+    pub fn new() -> Self {
+        TransferMonitor::default()
+    }
+
+    /// Returns the status of all transfers running at the moment.
+    pub fn transfers(&self) -> Vec<TransferStatus> {
+        let state = self.inner.lock().unwrap();
+        state.transfers.values().map(Transfer::status).collect()
+    }
+
+    // Tracks the given transfer until the returned guard is dropped.
+    pub(crate) fn register(&self, transfer: Transfer) -> MonitorGuard {
+        let mut state = self.inner.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.transfers.insert(id, transfer);
+        MonitorGuard { monitor: self.clone(), id }
+    }
+}
+
+// Removes a transfer from the monitor when it goes out of scope.
+pub(crate) struct MonitorGuard {
+    monitor: TransferMonitor,
+    id: u64,
+}
+
+impl Drop for MonitorGuard {
+    fn drop(&mut self) {
+        self.monitor.inner.lock().unwrap().transfers.remove(&self.id);
+    }
+}
+
+/// Wraps a data connection stream and counts the bytes read from or written to it.
+pub(crate) struct CountingStream<T> {
+    inner: T,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountingStream<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        futures::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.bytes.fetch_add((buf.filled().len() - before) as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountingStream<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = futures::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn counts_bytes_and_unregisters() {
+        let monitor = TransferMonitor::new();
+        let transfer = Transfer::new(Some(String::from("alice")), "127.0.0.1:2121".parse().unwrap(), "RETR", PathBuf::from("/a.txt"));
+        let guard = monitor.register(transfer.clone());
+
+        let mut output = transfer.count(Vec::new());
+        output.write_all(b"hello").await.unwrap();
+        let mut input = transfer.count(&b"world!"[..]);
+        input.read_to_end(&mut Vec::new()).await.unwrap();
+
+        let transfers = monitor.transfers();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].bytes, 11);
+        assert_eq!(transfers[0].username, Some(String::from("alice")));
+        drop(guard);
+        assert_eq!(monitor.transfers(), vec![]);
+    }
+}
@@ -41,6 +41,7 @@ impl From<ErrorKind> for Error {
 ///
 /// [`StorageBackend`]: trait.StorageBackend.html
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Display)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// Error that will cause an FTP reply code of 450 to be returned to the FTP client.
     /// The storage back-end implementation should return this if a error occurred that my be
@@ -76,6 +77,13 @@ pub enum ErrorKind {
     /// the requested action was not taken due to an illegal file name.
     #[display(fmt = "553 File name not allowed error")]
     FileNameNotAllowedError,
+    /// Error that will cause an FTP reply code of 553 to be returned to the FTP client. The storage
+    /// back-end implementation should return this from [`StorageBackend::put_new`] if the file
+    /// exists already.
+    ///
+    /// [`StorageBackend::put_new`]: trait.StorageBackend.html#method.put_new
+    #[display(fmt = "553 File already exists")]
+    FileAlreadyExists,
     /// Error that will cause an FTP reply code of 502. The indicates to the client that the command
     /// is not implemented for the storage back-end. For instance the GCS back-end don't implement
     /// RMD (remove directory) but returns this error instead from its StorageBackend::rmd
@@ -0,0 +1,185 @@
+//! Contains the hash algorithms supported by the `HASH` command and the code to calculate them.
+
+use super::{Error, ErrorKind, Result};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::fmt::{self, Display, Formatter};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The hash algorithms a client can select with `OPTS HASH` and that [`StorageBackend::checksum`]
+/// should be able to calculate.
+///
+/// [`StorageBackend::checksum`]: crate::storage::StorageBackend::checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// MD5 as in RFC 1321
+    Md5,
+    /// SHA-1 as in FIPS 180-4
+    Sha1,
+    /// SHA-256 as in FIPS 180-4. This is the default.
+    Sha256,
+    /// SHA-512 as in FIPS 180-4
+    Sha512,
+    /// The CRC-32 checksum as used by zlib and gzip
+    Crc32,
+    /// The CRC-32C (Castagnoli) checksum as used by e.g. Google Cloud Storage
+    Crc32c,
+}
+
+impl HashAlgorithm {
+    /// All algorithms in the order we advertise them in the `FEAT` reply.
+    pub(crate) const ALL: [HashAlgorithm; 6] = [
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Md5,
+        HashAlgorithm::Crc32,
+        HashAlgorithm::Crc32c,
+    ];
+
+    /// Returns the algorithm with the given name as used in the `HASH` command e.g. `SHA-256`.
+    /// The name is case insensitive.
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        HashAlgorithm::ALL.iter().copied().find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the name of the algorithm as used in the `HASH` command.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha1 => "SHA-1",
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Sha512 => "SHA-512",
+            HashAlgorithm::Crc32 => "CRC32",
+            HashAlgorithm::Crc32c => "CRC32C",
+        }
+    }
+
+    /// Calculates the hash of the bytes read from `reader` in the lower case hexadecimal format. If
+    /// a range is given only the bytes from `start` up to and including `end` are hashed.
+    ///
+    /// Storage back-ends that override [`StorageBackend::checksum`] can use this as a fallback for
+    /// the cases they don't handle natively.
+    ///
+    /// [`StorageBackend::checksum`]: crate::storage::StorageBackend::checksum
+    pub async fn hash_reader<R>(self, reader: R, range: Option<(u64, u64)>) -> Result<String>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut reader = match range {
+            Some((start, end)) => {
+                let mut reader = reader;
+                let skipped = tokio::io::copy(&mut (&mut reader).take(start), &mut tokio::io::sink()).await?;
+                if skipped < start {
+                    return Err(Error::from(ErrorKind::PermanentFileNotAvailable));
+                }
+                reader.take(end.saturating_sub(start).saturating_add(1))
+            }
+            None => reader.take(u64::MAX),
+        };
+
+        let mut hasher = Hasher::new(self);
+        let mut buffer = vec![0_u8; 1024 * 1024];
+        loop {
+            let n = reader.read(&mut buffer[..]).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[0..n]);
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::Crc32c => Hasher::Crc32c(0),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(h) => h.update(data),
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Crc32(h) => h.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha1(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha512(h) => format!("{:x}", h.finalize()),
+            Hasher::Crc32(h) => format!("{:08x}", h.finalize()),
+            Hasher::Crc32c(crc) => format!("{:08x}", crc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn hashes_whole_input() {
+        let input: &[u8] = b"The quick brown fox jumps over the lazy dog";
+        let tests = [
+            (HashAlgorithm::Md5, "9e107d9d372bb6826bd81d3542a419d6"),
+            (HashAlgorithm::Sha1, "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12"),
+            (HashAlgorithm::Sha256, "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592"),
+            (
+                HashAlgorithm::Sha512,
+                "07e547d9586f6a73f73fbac0435ed76951218fb7d0c8d788a309d785436bbb642e93a252a954f23912547d1e8a3b5ed6e1bfd7097821233fa0538f3db854fee6",
+            ),
+            (HashAlgorithm::Crc32, "414fa339"),
+            (HashAlgorithm::Crc32c, "22620404"),
+        ];
+        for (algorithm, expected) in tests.iter() {
+            assert_eq!(algorithm.hash_reader(input, None).await.unwrap(), *expected, "{}", algorithm);
+        }
+    }
+
+    #[tokio::test]
+    async fn hashes_range() {
+        let input: &[u8] = b"xxThe quick brown fox jumps over the lazy dogxx";
+        assert_eq!(
+            HashAlgorithm::Md5.hash_reader(input, Some((2, 44))).await.unwrap(),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert!(HashAlgorithm::Md5.hash_reader(input, Some((100, 200))).await.is_err());
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(HashAlgorithm::from_name("sha-256"), Some(HashAlgorithm::Sha256));
+        assert_eq!(HashAlgorithm::from_name("CRC32C"), Some(HashAlgorithm::Crc32c));
+        assert_eq!(HashAlgorithm::from_name("SHA-384"), None);
+    }
+}
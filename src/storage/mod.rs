@@ -168,5 +168,12 @@
 pub(crate) mod error;
 pub use error::{Error, ErrorKind};
 
+pub(crate) mod hash;
+pub use hash::HashAlgorithm;
+
 pub(crate) mod storage_backend;
-pub use storage_backend::{Fileinfo, Metadata, Permissions, Result, StorageBackend, FEATURE_RESTART, FEATURE_SITEMD5};
+pub use storage_backend::{
+    Fileinfo, ListFilter, ListOptions, ListSort, Metadata, Permissions, Result, StorageBackend, FEATURE_APPEND, FEATURE_HASH, FEATURE_MFMT, FEATURE_RENAME,
+    FEATURE_RESTART, FEATURE_SITEMD5,
+};
+pub(crate) use storage_backend::{DEFAULT_LIST_MAX_DEPTH, DEFAULT_LIST_MAX_ENTRIES};
@@ -2,14 +2,16 @@
 
 use super::error::Error;
 use crate::auth::UserDetail;
-use crate::storage::ErrorKind;
+use crate::storage::{ErrorKind, HashAlgorithm};
 use async_trait::async_trait;
 use chrono::prelude::{DateTime, Utc};
 use md5::{Digest, Md5};
 use std::{
+    cmp::Ordering,
     fmt::{self, Debug, Formatter, Write},
-    path::Path,
+    path::{Path, PathBuf},
     result,
+    sync::Arc,
     time::SystemTime,
 };
 use tokio::io::AsyncReadExt;
@@ -19,6 +21,22 @@ use tokio::io::AsyncReadExt;
 pub const FEATURE_RESTART: u32 = 0b0000_0001;
 /// Whether or not this storage backend supports the SITE MD5 command
 pub const FEATURE_SITEMD5: u32 = 0b0000_0010;
+/// Whether or not this storage backend supports appending to files i.e. the APPE command. Back-ends
+/// that set this should implement [`StorageBackend::append`].
+pub const FEATURE_APPEND: u32 = 0b0000_0100;
+/// Whether or not this storage backend supports setting the modification time of files i.e. the
+/// MFMT command. Back-ends that set this should implement [`StorageBackend::set_modified`].
+pub const FEATURE_MFMT: u32 = 0b0000_1000;
+/// Whether or not this storage backend supports the HASH command. Back-ends that set this can rely
+/// on the default implementation of [`StorageBackend::checksum`] or provide their own.
+pub const FEATURE_HASH: u32 = 0b0001_0000;
+/// Whether or not this storage backend supports renaming files with [`StorageBackend::rename`].
+/// The [`OverwritePolicy::Version`](crate::options::OverwritePolicy::Version) needs it.
+pub const FEATURE_RENAME: u32 = 0b0010_0000;
+
+// The limits of recursive directory listings (`LIST -R`) unless the server is configured otherwise.
+pub(crate) const DEFAULT_LIST_MAX_DEPTH: u32 = 5;
+pub(crate) const DEFAULT_LIST_MAX_ENTRIES: usize = 10_000;
 
 /// Result type used by traits in this module
 pub type Result<T> = result::Result<T, Error>;
@@ -56,10 +74,24 @@ pub trait Metadata {
     fn permissions(&self) -> Permissions {
         Permissions(0o7755)
     }
+
+    /// Returns a value that uniquely identifies the file within the storage back-end e.g. the inode
+    /// number. It is reported in the `unique` fact of `MLSD` and `MLST` listings and left out if
+    /// `None`, which is what the default implementation returns.
+    fn unique(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the unix mode bits of the file e.g. `0o644`. They are reported in the `unix.mode`
+    /// fact of `MLSD` and `MLST` listings and left out if `None`, which is what the default
+    /// implementation returns. Unlike `permissions` they don't show up in `LIST` output.
+    fn unix_mode(&self) -> Option<u32> {
+        None
+    }
 }
 
 /// Represents the permissions of a _FTP File_
-pub struct Permissions(u32);
+pub struct Permissions(pub(crate) u32);
 
 const PERM_READ: u32 = 0b100100100;
 const PERM_WRITE: u32 = 0b010010010;
@@ -138,6 +170,110 @@ where
     }
 }
 
+/// Tells how [`StorageBackend::list_fmt_with_options`] should filter, sort and recurse through a directory
+/// listing. It is derived from the options given with the `LIST` command e.g. `LIST -laR`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListOptions {
+    /// Leave out entries whose name starts with a dot. The server only sets this if it is
+    /// configured to hide them and the client didn't pass `-a`.
+    pub hide_dotfiles: bool,
+    /// The order to list the entries in.
+    pub sort: ListSort,
+    /// Reverse the sort order (`-r`).
+    pub reverse: bool,
+    /// List subdirectories recursively (`-R`).
+    pub recursive: bool,
+    /// The maximum number of subdirectory levels a recursive listing descends into.
+    pub max_depth: u32,
+    /// The maximum number of entries in a recursive listing.
+    pub max_entries: usize,
+    /// Hides the entries the filter doesn't show. The server sets it to hide the entries the
+    /// [access control list](crate::acl) doesn't let the user see.
+    pub filter: Option<ListFilter>,
+}
+
+/// Decides which entries a directory listing shows. See [`ListOptions::filter`].
+#[derive(Clone)]
+pub struct ListFilter(Arc<dyn Fn(&Path) -> bool + Send + Sync>);
+
+impl ListFilter {
+    /// Creates a filter that shows the entries for which the function returns true. The function
+    /// gets the absolute path of the entry as the client sees it.
+    pub fn new<F: Fn(&Path) -> bool + Send + Sync + 'static>(shows: F) -> Self {
+        ListFilter(Arc::new(shows))
+    }
+
+    /// Tells if the entry at the given path should be shown.
+    pub fn shows<P: AsRef<Path>>(&self, path: P) -> bool {
+        (self.0)(path.as_ref())
+    }
+}
+
+impl Debug for ListFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ListFilter")
+    }
+}
+
+impl PartialEq for ListFilter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The sort order of a directory listing. See [`ListOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    /// Alphabetically by name. This is the default.
+    Name,
+    /// By modification time, newest first (`-t`).
+    Modified,
+    /// By size, largest first (`-S`).
+    Size,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions {
+            hide_dotfiles: false,
+            sort: ListSort::Name,
+            reverse: false,
+            recursive: false,
+            max_depth: DEFAULT_LIST_MAX_DEPTH,
+            max_entries: DEFAULT_LIST_MAX_ENTRIES,
+            filter: None,
+        }
+    }
+}
+
+impl ListOptions {
+    /// Removes the entries that should not be shown and sorts the rest according to these options.
+    pub fn filter_and_sort<P, M>(&self, list: &mut Vec<Fileinfo<P, M>>)
+    where
+        P: AsRef<Path>,
+        M: Metadata,
+    {
+        fn name<P: AsRef<Path>>(path: &P) -> std::borrow::Cow<'_, str> {
+            path.as_ref().file_name().unwrap_or_default().to_string_lossy()
+        }
+
+        if self.hide_dotfiles {
+            list.retain(|fi| !name(&fi.path).starts_with('.'));
+        }
+        list.sort_by(|a, b| {
+            let order = match self.sort {
+                ListSort::Name => Ordering::Equal,
+                ListSort::Modified => b.metadata.modified().ok().cmp(&a.metadata.modified().ok()),
+                ListSort::Size => b.metadata.len().cmp(&a.metadata.len()),
+            };
+            order.then_with(|| name(&a.path).cmp(&name(&b.path)))
+        });
+        if self.reverse {
+            list.reverse();
+        }
+    }
+}
+
 /// The `StorageBackend` trait can be implemented to create custom FTP virtual file systems. Once
 /// implemented it needs to be registered with the [`Server`] on construction.
 ///
@@ -192,6 +328,18 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         Ok(format!("{:x}", md5sum.finalize()))
     }
 
+    /// Returns the hash of the given file in the lower case hexadecimal format. If a range is given
+    /// only the bytes from `start` up to and including `end` are hashed.
+    ///
+    /// Like with the md5 method you must explicitly enable this via the
+    /// [supported_features](crate::storage::StorageBackend::supported_features) method by setting
+    /// FEATURE_HASH. The default implementation reads the file and passes it to
+    /// [`HashAlgorithm::hash_reader`].
+    async fn checksum<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P, algorithm: HashAlgorithm, range: Option<(u64, u64)>) -> Result<String> {
+        let reader = self.get(user, path, 0).await?;
+        algorithm.hash_reader(reader, range).await
+    }
+
     /// Returns the list of files in the given directory.
     async fn list<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<Vec<Fileinfo<std::path::PathBuf, Self::Metadata>>>
     where
@@ -212,6 +360,59 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         Ok(std::io::Cursor::new(file_infos))
     }
 
+    /// Like [`list_fmt`](StorageBackend::list_fmt) but the given options tell which entries to
+    /// include, how to sort them and whether subdirectories should be listed too. The server uses
+    /// this for `LIST` and `STAT`. If the options don't ask for anything beyond a plain listing,
+    /// the default implementation calls `list_fmt` so that back-ends that override it keep working.
+    #[allow(clippy::type_complexity)]
+    #[tracing_attributes::instrument]
+    async fn list_fmt_with_options<P>(&self, user: &User, path: P, options: &ListOptions) -> std::result::Result<std::io::Cursor<Vec<u8>>, Error>
+    where
+        P: AsRef<Path> + Send + Debug,
+        Self::Metadata: Metadata + 'static,
+    {
+        if !options.hide_dotfiles && options.sort == ListSort::Name && !options.reverse && !options.recursive && options.filter.is_none() {
+            return self.list_fmt(user, path).await;
+        }
+
+        let mut listing = String::new();
+        let mut entries: usize = 0;
+        // The directories still to list: the path to list, the name to show above its entries and
+        // its depth. This is a stack so that subdirectories are listed depth first like `ls -R` does.
+        let mut dirs: Vec<(PathBuf, Option<PathBuf>, u32)> = vec![(path.as_ref().to_path_buf(), None, 0)];
+        'dirs: while let Some((dir, header, depth)) = dirs.pop() {
+            let mut list = match self.list(user, &dir).await {
+                Ok(list) => list,
+                // Skip subdirectories we cannot list rather than failing the entire listing.
+                Err(_) if depth > 0 => continue,
+                Err(err) => return Err(err),
+            };
+            if let Some(filter) = &options.filter {
+                list.retain(|fi| filter.shows(dir.join(fi.path.file_name().unwrap_or_default())));
+            }
+            options.filter_and_sort(&mut list);
+            if let Some(header) = &header {
+                let _ = write!(listing, "\r\n{}:\r\n", header.display());
+            }
+            let mut subdirs = vec![];
+            for fi in list {
+                if options.recursive && entries >= options.max_entries {
+                    break 'dirs;
+                }
+                let _ = write!(listing, "{}\r\n", fi);
+                entries += 1;
+                if options.recursive && depth < options.max_depth && fi.metadata.is_dir() {
+                    let name = fi.path.file_name().unwrap_or_default();
+                    let header = header.as_ref().map_or_else(|| PathBuf::from(name), |h| h.join(name));
+                    subdirs.push((dir.join(name), Some(header), depth + 1));
+                }
+            }
+            dirs.extend(subdirs.into_iter().rev());
+        }
+
+        Ok(std::io::Cursor::new(listing.into_bytes()))
+    }
+
     /// Returns some bytes that make up a NLST directory listing (only the basename) that can
     /// immediately be sent to the client.
     #[allow(clippy::type_complexity)]
@@ -262,6 +463,50 @@ pub trait StorageBackend<User: UserDetail>: Send + Sync + Debug {
         start_pos: u64,
     ) -> Result<u64>;
 
+    /// Writes bytes from the given reader to a new file at the specified path. Fails with
+    /// [`ErrorKind::FileAlreadyExists`] if the file exists already. Returns the number of bytes
+    /// written.
+    ///
+    /// This is called instead of `put` when the server is configured not to overwrite files. This
+    /// default implementation checks if the file exists before calling `put`. Storage back-ends
+    /// should override it if they can do the check and the write atomically.
+    async fn put_new<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        user: &User,
+        input: R,
+        path: P,
+    ) -> Result<u64> {
+        match self.metadata(user, path.as_ref()).await {
+            Ok(_) => Err(Error::from(ErrorKind::FileAlreadyExists)),
+            Err(err) if err.kind() == ErrorKind::PermanentFileNotAvailable => self.put(user, input, path, 0).await,
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes bytes from the given reader to the end of the file at the specified path, creating
+    /// the file if it doesn't exist. Returns the number of bytes appended.
+    ///
+    /// This is only called if the storage back-end advertises support for it through the
+    /// supported_features method i.e. the result from supported_features yield a non-zero value
+    /// if a logical and operation is applied with FEATURE_APPEND.
+    async fn append<P: AsRef<Path> + Send + Debug, R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static>(
+        &self,
+        _user: &User,
+        _input: R,
+        _path: P,
+    ) -> Result<u64> {
+        Err(Error::from(ErrorKind::CommandNotImplemented))
+    }
+
+    /// Sets the modification time of the file at the given path.
+    ///
+    /// This is only called if the storage back-end advertises support for it through the
+    /// supported_features method i.e. the result from supported_features yield a non-zero value
+    /// if a logical and operation is applied with FEATURE_MFMT.
+    async fn set_modified<P: AsRef<Path> + Send + Debug>(&self, _user: &User, _path: P, _modified: SystemTime) -> Result<()> {
+        Err(Error::from(ErrorKind::CommandNotImplemented))
+    }
+
     /// Deletes the file at the given path.
     async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> Result<()>;
 